@@ -1,19 +1,35 @@
-use std::io::Error;
+use std::fs;
+use std::io::{Error, ErrorKind};
 use std::path::Path;
 
+use log::{info, warn};
 use mem::MemMap;
 
+use crate::fc::apu::APU;
+use crate::fc::bus::Bus;
+use crate::fc::clock::MasterClock;
 use crate::fc::cpu::*;
 use crate::fc::input::StandardControllerState;
 use crate::fc::mem::cart::*;
-use crate::fc::ppu::*;
+use crate::fc::movie::{Movie, MovieFrame, MovieMode, MovieReset, MovieSession, MovieStart, MovieStatus, fm2};
+use crate::fc::region::Region;
+use crate::fc::state::{STATE_VERSION, Snapshot, StateHeader, StateReader, StateWriter, fnv1a_hash};
+use crate::fc::wav::WavRecorder;
 
+pub mod bus;
 pub mod cpu;
 pub mod ppu;
 pub mod apu;
+pub mod clock;
 pub mod mem;
 pub mod dbg;
+pub mod headless;
 pub mod input;
+pub mod movie;
+pub mod netplay;
+pub mod region;
+pub mod state;
+pub mod wav;
 
 pub enum ConsoleType {
     Famicom,
@@ -24,24 +40,52 @@ pub enum ConsoleType {
 
 pub struct FC {
     cpu: CPU,
-    // ppu: PPU,    // ? move PPU here instead of storing in CPU??
+    /// Everything else (PPU, APU, RAM, cartridge, controllers), as seen by the CPU.
+    bus: Bus,
     cart: Option<NESFile>,
+    region: Region,
+    /// Manually selected region, used instead of the one specified by the ROM header.
+    region_override: Option<Region>,
+    /// CPU/PPU alignment used on power-on (see [MasterClock::new])
+    cpu_ppu_alignment: u32,
+    /// Audio samples taken from the APU, but not yet taken by `take_audio_samples`.
+    audio_samples: Vec<f32>,
+    wav_recorder: Option<WavRecorder>,
+    /// The movie being recorded or played back (if any.)
+    movie: Option<MovieSession>,
 }
 
 impl FC {
     pub fn new() -> FC {
         FC {
-            cpu: CPU::new(MemMap::empty()),
-            cart: None
+            cpu: CPU::new(),
+            bus: Bus::new(MemMap::empty(), MasterClock::new(Region::NTSC, 0)),
+            cart: None,
+            region: Region::NTSC,
+            region_override: None,
+            cpu_ppu_alignment: 0,
+            audio_samples: Vec::new(),
+            wav_recorder: None,
+            movie: None,
         }
     }
 
     pub fn from_file(filename: &Path) -> Result<Box<FC>, Error> {
         let nesfile = NESFile::from_file(filename)?;
         let mem = MemMap::from_nesfile(&nesfile)?;
+        let region = Region::from_timing_mode(nesfile.cpu_ppu_timing_mode());
 
-        let cpu = CPU::new(mem);
-        Ok(Box::new(FC { cpu, cart: Some(nesfile) }))
+        Ok(Box::new(FC {
+            cpu: CPU::new(),
+            bus: Bus::new(mem, MasterClock::new(region, 0)),
+            cart: Some(nesfile),
+            region,
+            region_override: None,
+            cpu_ppu_alignment: 0,
+            audio_samples: Vec::new(),
+            wav_recorder: None,
+            movie: None,
+        }))
     }
 
     /// Reads and loads the specified ROM, including initialization.
@@ -55,58 +99,715 @@ impl FC {
     /// This is equivalent to loading the already loaded ROM from a file again.
     pub fn reset_hard(&mut self) -> Result<(), Error> {
         match &self.cart {
-            None => Err(Error::new(std::io::ErrorKind::NotFound, "no ROM loaded")),
+            None => Err(Error::new(ErrorKind::NotFound, "no ROM loaded")),
             Some(nesfile) => {
                 let mem = MemMap::from_nesfile(&nesfile)?;
+                let region = self.region_override.unwrap_or(Region::from_timing_mode(nesfile.cpu_ppu_timing_mode()));
 
-                let cpu = CPU::new(mem);
-                // self.ppu = ppu;
-                self.cpu = cpu;
+                // Don't lose the audio generated up until now
+                self.collect_audio_samples();
+
+                let mut bus = Bus::new(mem, MasterClock::new(region, self.cpu_ppu_alignment));
+                self.region = region;
+                bus.apu.copy_output_settings(&self.bus.apu);
+                self.cpu = CPU::new();
+                self.bus = bus;
                 self.init();
+
+                if let Some(session) = &mut self.movie
+                    && session.mode == MovieMode::Recording
+                {
+                    session.pending_reset = Some(MovieReset::Hard);
+                }
                 Ok(())
             }
         }
     }
 
+    /// The region the emulator is currently running as.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn region_override(&self) -> Option<Region> {
+        self.region_override
+    }
+
+    /// Manually set the region (or go back to using the one from the ROM header if `None`.)
+    ///
+    /// If this changes the region, the emulator is hard reset.
+    pub fn set_region_override(&mut self, region: Option<Region>) -> Result<(), Error> {
+        self.region_override = region;
+
+        let new_region = match (&self.cart, region) {
+            (_, Some(r)) => r,
+            (Some(nesfile), None) => Region::from_timing_mode(nesfile.cpu_ppu_timing_mode()),
+            (None, None) => Region::NTSC,
+        };
+
+        if new_region != self.region && self.cart.is_some() {
+            if self.movie.is_some() {
+                return Err(Error::other("can't change the region while a movie is being recorded or played back"));
+            }
+            info!("Switching region: {} -> {new_region}", self.region);
+            self.reset_hard()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn cpu_ppu_alignment(&self) -> u32 {
+        self.cpu_ppu_alignment
+    }
+
+    /// Set the CPU/PPU clock alignment (see [MasterClock::new]). Takes effect on the next hard reset.
+    pub fn set_cpu_ppu_alignment(&mut self, alignment: u32) {
+        self.cpu_ppu_alignment = alignment;
+    }
+
     /// "Soft reset" the emulator.
     pub fn reset(&mut self) -> () {
-        self.cpu.reset();
+        self.bus.ppu.reset();
+        self.bus.ppu.init();
+        self.cpu.reset(&mut self.bus);
+
+        if let Some(session) = &mut self.movie
+            && session.mode == MovieMode::Recording
+        {
+            // A hard reset already resets everything, so don't let a soft reset replace it
+            session.pending_reset.get_or_insert(MovieReset::Soft);
+        }
     }
 
     pub fn init(&mut self) -> () {
         // TODO: all the other initialization things.
-        self.cpu.init();
+        self.bus.ppu.init();
+        self.cpu.init(&mut self.bus);
     }
 
+    /// Run the emulator for a frame (until the PPU has finished rendering it.)
+    ///
+    /// This is also what advances the movie being recorded or played back.
     pub fn run_until_render_done(&mut self) -> () {
-        self.cpu.run_to_rendering_finished();
+        self.start_movie_frame();
+        self.cpu.run_to_rendering_finished(&mut self.bus);
+        self.end_movie_frame();
+        if self.wav_recorder.is_some() {
+            self.collect_audio_samples();
+        }
+    }
+
+    /// Run a frame ahead of the actual emulation, with the current input, for run-ahead.
+    ///
+    /// No audio is generated and the movie (if any) doesn't advance, as the state is expected to be restored to the
+    /// one before running ahead (see [FC::save_state_into] and [FC::restore_state].)
+    pub fn run_frame_ahead(&mut self) {
+        self.bus.apu.set_output_enabled(false);
+        self.cpu.run_to_rendering_finished(&mut self.bus);
+        self.bus.apu.set_output_enabled(true);
     }
 
     pub fn step(&mut self) -> () {
-        self.cpu.fetch_and_run();
+        self.cpu.fetch_and_run(&mut self.bus);
+        if self.wav_recorder.is_some() {
+            self.collect_audio_samples();
+        }
     }
 
     pub fn step_dbg(&mut self) -> () {
-        self.cpu.fetch_and_run_dbg();
+        self.cpu.fetch_and_run_dbg(&mut self.bus);
+        if self.wav_recorder.is_some() {
+            self.collect_audio_samples();
+        }
+    }
+
+    pub fn print_state(&self) {
+        self.cpu.print_state(&self.bus);
     }
 
     pub fn get_frame(&self) -> &[u8] {
-        self.cpu.ppu.get_frame_buf()
+        self.bus.ppu.get_frame_buf()
+    }
+
+    /// Take the audio samples generated since the last call (mono, at the configured sample rate).
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.collect_audio_samples();
+        std::mem::take(&mut self.audio_samples)
+    }
+
+    /// Move the samples generated by the APU into `audio_samples`, writing them to the WAV recording (if any.)
+    fn collect_audio_samples(&mut self) {
+        let samples = self.bus.apu.take_samples();
+        if samples.is_empty() {
+            return;
+        }
+
+        if let Some(recorder) = &mut self.wav_recorder
+            && let Err(e) = recorder.write_samples(&samples)
+        {
+            warn!("Failed to write audio recording, stopping: {e}");
+            self.wav_recorder = None;
+        }
+
+        self.audio_samples.extend_from_slice(&samples);
+
+        // Nothing might be taking the samples (e.g. in the debugger), so only keep the last second around
+        let max_len = self.bus.apu.sample_rate() as usize;
+        if self.audio_samples.len() > max_len {
+            let excess = self.audio_samples.len() - max_len;
+            self.audio_samples.drain(..excess);
+        }
+    }
+
+    /// Start recording the audio output to a .wav file (at the current sample rate.)
+    pub fn start_wav_recording(&mut self, path: &Path) -> Result<(), Error> {
+        self.stop_wav_recording()?;
+
+        // Samples generated before this point should not end up in the recording
+        self.collect_audio_samples();
+        self.wav_recorder = Some(WavRecorder::create(path, self.bus.apu.sample_rate())?);
+        Ok(())
+    }
+
+    /// Stop the current WAV recording (if any.)
+    pub fn stop_wav_recording(&mut self) -> Result<(), Error> {
+        // Make sure everything up until now ends up in the recording
+        self.collect_audio_samples();
+
+        match self.wav_recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// The path of the current WAV recording (if recording.)
+    pub fn wav_recording_path(&self) -> Option<&Path> {
+        self.wav_recorder.as_ref().map(|r| r.path())
+    }
+
+    pub fn audio_sample_rate(&self) -> u32 {
+        self.bus.apu.sample_rate()
+    }
+
+    /// Set the sample rate (in Hz) of the samples returned by `take_audio_samples`.
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.bus.apu.set_sample_rate(sample_rate);
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    /// The system bus, for inspecting the components connected to the CPU.
+    pub fn bus(&self) -> &Bus {
+        &self.bus
+    }
+
+    pub fn apu(&self) -> &APU {
+        &self.bus.apu
+    }
+
+    /// Mutable access to the APU, for changing its output settings (e.g. channel gains.)
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.bus.apu
     }
 
     pub fn get_nametables_dbg(&mut self) -> &[u8] {
-        self.cpu.ppu.generate_nametables_image_temp(&self.cpu.mem)
+        self.bus.ppu.generate_nametables_image_temp(&self.bus.mem)
     }
 
+    /// Set the buttons held on both controllers. Ignored while a movie is played back, as it provides the input.
     pub fn set_controller_values(&mut self, joy1: StandardControllerState, joy2: StandardControllerState) {
-        self.cpu.mem.input.update_from_controller_state(joy1, joy2);
+        if let Some(session) = &mut self.movie {
+            if session.mode == MovieMode::Playing {
+                return;
+            }
+            session.live_input = (joy1, joy2);
+        }
+        self.bus.mem.input.update_from_controller_state(joy1, joy2);
+    }
+
+    /// A checksum of the RAM and the last frame, stored in movies to detect desyncs.
+    pub fn frame_checksum(&self) -> u64 {
+        let mut data = self.bus.mem.ram().to_vec();
+        data.extend_from_slice(self.get_frame());
+        fnv1a_hash(&data)
+    }
+
+    /// Start recording a movie, either from power-on (hard resetting the emulator) or from the current state.
+    ///
+    /// Any movie that was being recorded or played back is stopped.
+    pub fn start_movie_recording(&mut self, from_power_on: bool) -> Result<(), Error> {
+        let nesfile = self.cart.as_ref().ok_or(Error::new(ErrorKind::NotFound, "no ROM loaded"))?;
+        let rom_hash = nesfile.hash();
+
+        self.movie = None;
+        let start = if from_power_on {
+            self.reset_hard()?;
+            MovieStart::PowerOn
+        } else {
+            MovieStart::SaveState(self.save_state()?)
+        };
+
+        self.movie = Some(MovieSession::new(Movie::new(rom_hash, self.region, start), MovieMode::Recording));
+        info!("Started recording movie");
+        Ok(())
+    }
+
+    /// Start playing back a movie, replacing the controller input until it ends.
+    ///
+    /// Any movie that was being recorded or played back is stopped.
+    pub fn start_movie_playback(&mut self, movie: Movie) -> Result<(), Error> {
+        let nesfile = self.cart.as_ref().ok_or(Error::new(ErrorKind::NotFound, "no ROM loaded"))?;
+        if movie.rom_hash != nesfile.hash() {
+            return Err(Error::new(ErrorKind::InvalidData, "movie is for a different ROM"));
+        }
+        if movie.region != self.region {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("movie is for {}, but the emulator is running as {}", movie.region, self.region),
+            ));
+        }
+
+        self.movie = None;
+        match &movie.start {
+            MovieStart::PowerOn => self.reset_hard()?,
+            MovieStart::SaveState(state) => self.load_state(state)?,
+        }
+
+        info!("Started playing back movie ({} frames)", movie.frames.len());
+        self.movie = Some(MovieSession::new(movie, MovieMode::Playing));
+        Ok(())
+    }
+
+    /// Stop recording or playing back the current movie, returning it.
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        let session = self.movie.take()?;
+        info!("Stopped movie at frame {}", session.frame);
+        Some(session.movie)
+    }
+
+    pub fn movie_status(&self) -> Option<MovieStatus> {
+        self.movie.as_ref().map(|session| session.status())
+    }
+
+    /// Apply the input of the next movie frame (when playing back), or record the current input (when recording.)
+    fn start_movie_frame(&mut self) {
+        let Some(session) = &mut self.movie else {
+            return;
+        };
+
+        match session.mode {
+            MovieMode::Recording => {
+                let (joy1, joy2) = session.live_input;
+                let reset = session.pending_reset.take();
+                session.movie.frames.push(MovieFrame { joy1, joy2, reset, checksum: None });
+            }
+            MovieMode::Playing => {
+                let Some(&frame) = session.movie.frames.get(session.frame) else {
+                    info!("Movie playback finished ({} frames)", session.frame);
+                    self.movie = None;
+                    return;
+                };
+
+                match frame.reset {
+                    Some(MovieReset::Soft) => self.reset(),
+                    Some(MovieReset::Hard) => {
+                        if let Err(e) = self.reset_hard() {
+                            warn!("Failed to hard reset during movie playback: {e}");
+                        }
+                    }
+                    None => {}
+                }
+                self.bus.mem.input.update_from_controller_state(frame.joy1, frame.joy2);
+            }
+        }
+    }
+
+    /// Store (when recording) or compare (when playing back) the checksum of the frame that just finished.
+    fn end_movie_frame(&mut self) {
+        if self.movie.is_none() {
+            return;
+        }
+        let checksum = self.frame_checksum();
+        let Some(session) = &mut self.movie else {
+            return;
+        };
+
+        match session.mode {
+            MovieMode::Recording => {
+                if let Some(frame) = session.movie.frames.last_mut() {
+                    frame.checksum = Some(checksum);
+                }
+            }
+            MovieMode::Playing => {
+                let expected = session.movie.frames[session.frame].checksum;
+                if expected.is_some_and(|c| c != checksum) && session.desync_frame.is_none() {
+                    warn!("Movie desynced at frame {}", session.frame);
+                    session.desync_frame = Some(session.frame);
+                }
+            }
+        }
+        session.frame += 1;
+    }
+
+    /// Read a movie from a file, either in the native format or FM2 (based on the extension.)
+    pub fn load_movie(&self, path: &Path) -> Result<Movie, Error> {
+        let nesfile = self.cart.as_ref().ok_or(Error::new(ErrorKind::NotFound, "no ROM loaded"))?;
+
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("fm2")) {
+            fm2::import(&fs::read_to_string(path)?, nesfile.hash(), fm2::rom_checksum(nesfile))
+        } else {
+            Movie::from_bytes(&fs::read(path)?)
+        }
+    }
+
+    /// Write a movie to a file, either in the native format or FM2 (based on the extension.)
+    pub fn save_movie(&self, movie: &Movie, path: &Path) -> Result<(), Error> {
+        let nesfile = self.cart.as_ref().ok_or(Error::new(ErrorKind::NotFound, "no ROM loaded"))?;
+
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("fm2")) {
+            let rom_filename = path.file_stem().unwrap_or_default().to_string_lossy();
+            fs::write(path, fm2::export(movie, fm2::rom_checksum(nesfile), &rom_filename)?)?;
+        } else {
+            fs::write(path, movie.to_bytes())?;
+        }
+        info!("Saved movie to {path:?}");
+        Ok(())
+    }
+
+    /// Save the complete machine state, see [state] for the format.
+    pub fn save_state(&self) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        self.save_state_into(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Save the complete machine state to `buf`, replacing its contents.
+    ///
+    /// Reuses the allocation of `buf`, for saving states every frame (e.g. for run-ahead.)
+    pub fn save_state_into(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        let nesfile = self.cart.as_ref().ok_or(Error::new(ErrorKind::NotFound, "no ROM loaded"))?;
+
+        let mut w = StateWriter::with_buffer(std::mem::take(buf));
+        StateHeader {
+            version: STATE_VERSION,
+            rom_hash: nesfile.hash(),
+            region: self.region,
+        }.write(&mut w);
+        self.cpu.save_state(&mut w);
+        self.bus.save_state(&mut w);
+        *buf = w.into_bytes();
+        Ok(())
+    }
+
+    /// Restore a state saved by `save_state`.
+    ///
+    /// States saved from a different ROM (or in a different region) are rejected. If the state can't be loaded, the
+    /// emulator is left as it was before.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.check_state_header(data)?;
+
+        // The header is fine, but the rest might not be. Keep the current state around so we never end up with a
+        // half-loaded machine.
+        let backup = self.save_state()?;
+        let result = self.read_state(data);
+        if result.is_err() {
+            self.read_state(&backup).expect("restoring the state from before loading should never fail");
+        }
+        result
+    }
+
+    /// Restore a state saved by this emulator, without keeping a backup of the current state.
+    ///
+    /// This is faster than [FC::load_state], but if `data` turns out to be invalid the emulator may be left
+    /// half-restored. Only use it for states that are known to be good, like the ones saved for run-ahead.
+    pub fn restore_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.check_state_header(data)?;
+        self.read_state(data)
+    }
+
+    /// Check that a state was saved from the loaded ROM, in the current region.
+    fn check_state_header(&self, data: &[u8]) -> Result<(), Error> {
+        let nesfile = self.cart.as_ref().ok_or(Error::new(ErrorKind::NotFound, "no ROM loaded"))?;
+
+        let header = StateHeader::read(&mut StateReader::new(data))?;
+        if header.rom_hash != nesfile.hash() {
+            return Err(Error::new(ErrorKind::InvalidData, "save state is for a different ROM"));
+        }
+        if header.region != self.region {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("save state is for {}, but the emulator is running as {}", header.region, self.region),
+            ));
+        }
+        Ok(())
+    }
+
+    fn read_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        let mut r = StateReader::new(data);
+        StateHeader::read(&mut r)?;
+        self.cpu.load_state(&mut r)?;
+        self.bus.load_state(&mut r)?;
+        if !r.is_at_end() {
+            return Err(Error::new(ErrorKind::InvalidData, "unexpected data at the end of the save state"));
+        }
+        Ok(())
+    }
+
+    pub fn save_state_to_file(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, self.save_state()?)?;
+        info!("Saved state to {path:?}");
+        Ok(())
+    }
+
+    pub fn load_state_from_file(&mut self, path: &Path) -> Result<(), Error> {
+        self.load_state(&fs::read(path)?)?;
+        info!("Loaded state from {path:?}");
+        Ok(())
     }
 
     pub fn load_save(&mut self, save_path: &Path) -> Result<(), std::io::Error> {
-        self.cpu.mem.read_sram_from_file(save_path)
+        self.bus.mem.read_sram_from_file(save_path)
     }
 
     pub fn save_save(&mut self, save_path: &Path) -> Result<(), std::io::Error> {
-        self.cpu.mem.write_sram_to_file(save_path)
+        self.bus.mem.write_sram_to_file(save_path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Write an NROM-128 .nes file running `program` (at $8000, with the NMI handler at `nmi`) to the temp dir.
+    pub(super) fn test_rom(name: &str, program: &[u8], nmi: u16) -> std::path::PathBuf {
+        let mut prg = vec![0xea; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3ffa..0x3ffe].copy_from_slice(&[nmi as u8, (nmi >> 8) as u8, 0x00, 0x80]);
+
+        let mut data = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend(prg);
+        data.extend([0; 0x2000]);
+
+        let path = std::env::temp_dir().join(format!("rfce_{name}_{}.nes", std::process::id()));
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[rustfmt::skip]
+    const COUNTER_PROGRAM: [u8; 25] = [
+        0xa9, 0x80, 0x8d, 0x00, 0x20, // lda #$80; sta $2000  (enable NMI)
+        0xa9, 0x1e, 0x8d, 0x01, 0x20, // lda #$1e; sta $2001  (enable rendering)
+        0xe6, 0x00,                   // loop: inc $00
+        0xa5, 0x00, 0x8d, 0x02, 0x40, // lda $00; sta $4002
+        0x4c, 0x0a, 0x80,             // jmp loop
+        0xe6, 0x01, 0x40,             // nmi: inc $01; rti
+        0x00, 0x00,
+    ];
+
+    /// Reads the controller in a loop, playing the buttons as the pulse 1 period and showing them as the background
+    /// color (written in the NMI handler.)
+    #[rustfmt::skip]
+    pub(super) const INPUT_PROGRAM: [u8; 82] = [
+        0xa9, 0x80, 0x8d, 0x00, 0x20, // lda #$80; sta $2000  (enable NMI)
+        0xa9, 0x0a, 0x8d, 0x01, 0x20, // lda #$0a; sta $2001  (enable background rendering)
+        0xa9, 0x01, 0x8d, 0x15, 0x40, // lda #$01; sta $4015  (enable pulse 1)
+        0xa9, 0xbf, 0x8d, 0x00, 0x40, // lda #$bf; sta $4000  (constant volume 15, halt length counter)
+        0xa9, 0x08, 0x8d, 0x03, 0x40, // lda #$08; sta $4003
+        0xa9, 0x01, 0x8d, 0x16, 0x40, // loop: lda #$01; sta $4016  (strobe controllers)
+        0xa9, 0x00, 0x8d, 0x16, 0x40, // lda #$00; sta $4016
+        0xa2, 0x08,                   // ldx #$08
+        0xad, 0x16, 0x40,             // read: lda $4016
+        0x4a, 0x26, 0x00,             // lsr a; rol $00
+        0xca, 0xd0, 0xf7,             // dex; bne read
+        0xa5, 0x00, 0x09, 0x40,       // lda $00; ora #$40
+        0x8d, 0x02, 0x40,             // sta $4002
+        0x4c, 0x19, 0x80,             // jmp loop
+        0xa9, 0x3f, 0x8d, 0x06, 0x20, // nmi: lda #$3f; sta $2006
+        0xa9, 0x00, 0x8d, 0x06, 0x20, // lda #$00; sta $2006
+        0xa5, 0x00, 0x29, 0x3f,       // lda $00; and #$3f
+        0x8d, 0x07, 0x20,             // sta $2007  (backdrop color)
+        0xa9, 0x00, 0x8d, 0x06, 0x20, // lda #$00; sta $2006
+        0x8d, 0x06, 0x20,             // sta $2006
+        0x40,                         // rti
+    ];
+    pub(super) const INPUT_PROGRAM_NMI: u16 = 0x8038;
+
+    #[test]
+    fn save_state_round_trip_test() {
+        let path = test_rom("state", &COUNTER_PROGRAM, 0x8014);
+        let mut fc = FC::from_file(&path).unwrap();
+        fc.init();
+        for _ in 0..5 {
+            fc.run_until_render_done();
+        }
+
+        let state = fc.save_state().unwrap();
+        for _ in 0..3 {
+            fc.run_until_render_done();
+        }
+        let expected_state = fc.save_state().unwrap();
+        let expected_frame = fc.get_frame().to_vec();
+
+        fc.load_state(&state).unwrap();
+        assert_eq!(fc.save_state().unwrap(), state);
+        for _ in 0..3 {
+            fc.run_until_render_done();
+        }
+        assert_eq!(fc.save_state().unwrap(), expected_state);
+        assert_eq!(fc.get_frame(), &expected_frame[..]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn save_state_rejected_test() {
+        let path = test_rom("state_a", &COUNTER_PROGRAM, 0x8014);
+        let mut fc = FC::from_file(&path).unwrap();
+        fc.init();
+        fc.run_until_render_done();
+        let state = fc.save_state().unwrap();
+
+        // A different ROM
+        let mut other_program = COUNTER_PROGRAM;
+        other_program[1] = 0x00;
+        let other_path = test_rom("state_b", &other_program, 0x8014);
+        let mut other = FC::from_file(&other_path).unwrap();
+        other.init();
+        assert!(other.load_state(&state).is_err());
+
+        // A truncated state leaves the emulator untouched
+        fc.run_until_render_done();
+        let before = fc.save_state().unwrap();
+        assert!(fc.load_state(&state[..state.len() - 10]).is_err());
+        assert_eq!(fc.save_state().unwrap(), before);
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(other_path).unwrap();
+    }
+
+    #[test]
+    fn movie_record_playback_test() {
+        let path = test_rom("movie", &COUNTER_PROGRAM, 0x8014);
+        let mut fc = FC::from_file(&path).unwrap();
+        fc.init();
+        fc.run_until_render_done();
+
+        fc.start_movie_recording(true).unwrap();
+        for i in 0..10 {
+            let joy1 = StandardControllerState { a: i % 2 == 0, ..Default::default() };
+            fc.set_controller_values(joy1, StandardControllerState::default());
+            if i == 5 {
+                fc.reset();
+            }
+            fc.run_until_render_done();
+        }
+        let expected_state = fc.save_state().unwrap();
+        let movie = fc.stop_movie().unwrap();
+        assert_eq!(movie.frames.len(), 10);
+        assert_eq!(movie.frames[5].reset, Some(MovieReset::Soft));
+        assert!(movie.frames[1].joy1 == StandardControllerState::default() && movie.frames[2].joy1.a);
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+
+        // Playing back reproduces the same state, ignoring live input
+        fc.start_movie_playback(movie.clone()).unwrap();
+        for _ in 0..10 {
+            fc.set_controller_values(StandardControllerState { b: true, ..Default::default() }, Default::default());
+            fc.run_until_render_done();
+        }
+        assert_eq!(fc.movie_status().unwrap().desync_frame, None);
+        assert_eq!(fc.save_state().unwrap(), expected_state);
+        fc.run_until_render_done();
+        assert!(fc.movie_status().is_none());
+
+        // A mismatching checksum is reported as a desync
+        let mut desynced = movie;
+        desynced.frames[3].checksum = Some(0);
+        fc.start_movie_playback(desynced).unwrap();
+        for _ in 0..5 {
+            fc.run_until_render_done();
+        }
+        assert_eq!(fc.movie_status().unwrap().desync_frame, Some(3));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn run_ahead_test() {
+        let path = test_rom("run_ahead", &COUNTER_PROGRAM, 0x8014);
+        let mut fc = FC::from_file(&path).unwrap();
+        fc.init();
+        fc.start_movie_recording(true).unwrap();
+        fc.run_until_render_done();
+        fc.take_audio_samples();
+
+        let mut state = Vec::new();
+        fc.save_state_into(&mut state).unwrap();
+        assert_eq!(state, fc.save_state().unwrap());
+
+        // Running ahead generates no audio and doesn't advance the movie, and restoring undoes it
+        fc.run_frame_ahead();
+        fc.run_frame_ahead();
+        assert_ne!(fc.save_state().unwrap(), state);
+        assert!(fc.take_audio_samples().is_empty());
+        assert_eq!(fc.movie_status().unwrap().frame, 1);
+        fc.restore_state(&state).unwrap();
+        assert_eq!(fc.save_state().unwrap(), state);
+
+        fc.run_until_render_done();
+        assert!(!fc.take_audio_samples().is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    /// Measures how long saving and restoring states takes, as run-ahead (and rewind) do it every frame.
+    ///
+    /// Run with `cargo test --release state_benchmark -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn state_benchmark() {
+        const ITERATIONS: u32 = 10_000;
+
+        let path = test_rom("benchmark", &COUNTER_PROGRAM, 0x8014);
+        let mut fc = FC::from_file(&path).unwrap();
+        fc.init();
+        for _ in 0..10 {
+            fc.run_until_render_done();
+        }
+
+        let mut state = Vec::new();
+        let start = std::time::Instant::now();
+        for _ in 0..ITERATIONS {
+            fc.save_state_into(&mut state).unwrap();
+        }
+        let save_time = start.elapsed() / ITERATIONS;
+
+        let start = std::time::Instant::now();
+        for _ in 0..ITERATIONS {
+            fc.restore_state(&state).unwrap();
+        }
+        let restore_time = start.elapsed() / ITERATIONS;
+
+        let start = std::time::Instant::now();
+        for _ in 0..ITERATIONS {
+            fc.load_state(&state).unwrap();
+        }
+        let load_time = start.elapsed() / ITERATIONS;
+
+        let start = std::time::Instant::now();
+        for _ in 0..100 {
+            fc.run_until_render_done();
+        }
+        let frame_time = start.elapsed() / 100;
+
+        println!("State size:      {} bytes", state.len());
+        println!("save_state_into: {save_time:.2?}");
+        println!("restore_state:   {restore_time:.2?}");
+        println!("load_state:      {load_time:.2?}");
+        println!("Frame:           {frame_time:.2?}");
+
+        fs::remove_file(path).unwrap();
     }
 }
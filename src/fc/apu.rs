@@ -1,25 +1,174 @@
+mod mixer;
+mod resampler;
+
+use std::io::Error;
+
 use log::debug;
+use mixer::Mixer;
+use resampler::Resampler;
+
+use super::region::Region;
+use super::state::{Snapshot, StateReader, StateWriter};
+
+/// The default output sample rate, in Hz.
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+/// An audio channel, as seen by the mixer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    Pulse1,
+    Pulse2,
+    Triangle,
+    Noise,
+    DMC,
+    /// Cartridge expansion audio (all chips combined).
+    Expansion,
+}
+
+impl Channel {
+    pub const COUNT: usize = 6;
+    pub const ALL: [Channel; Channel::COUNT] = [
+        Channel::Pulse1,
+        Channel::Pulse2,
+        Channel::Triangle,
+        Channel::Noise,
+        Channel::DMC,
+        Channel::Expansion,
+    ];
+}
+
+/// A cartridge expansion audio chip.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExpansionChip {
+    VRC6,
+    VRC7,
+    Namco163,
+    Sunsoft5B,
+    MMC5,
+    FDS,
+}
+
+impl ExpansionChip {
+    /// The full-scale output of the chip, relative to a single APU pulse channel at full volume.
+    ///
+    /// These are rough approximations (the actual levels vary between boards and consoles), and can be adjusted
+    /// further with the [Channel::Expansion] gain.
+    pub fn relative_level(self) -> f32 {
+        match self {
+            ExpansionChip::VRC6 => 3.0,
+            ExpansionChip::VRC7 => 2.5,
+            ExpansionChip::Namco163 => 3.0,
+            ExpansionChip::Sunsoft5B => 4.0,
+            ExpansionChip::MMC5 => 3.0,
+            ExpansionChip::FDS => 2.4,
+        }
+    }
+}
+
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Channel::Pulse1 => "pulse1",
+            Channel::Pulse2 => "pulse2",
+            Channel::Triangle => "triangle",
+            Channel::Noise => "noise",
+            Channel::DMC => "dmc",
+            Channel::Expansion => "expansion",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl std::str::FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pulse1" | "p1" | "1" => Ok(Channel::Pulse1),
+            "pulse2" | "p2" | "2" => Ok(Channel::Pulse2),
+            "triangle" | "tri" | "t" | "3" => Ok(Channel::Triangle),
+            "noise" | "n" | "4" => Ok(Channel::Noise),
+            "dmc" | "d" | "5" => Ok(Channel::DMC),
+            "expansion" | "exp" | "e" | "6" => Ok(Channel::Expansion),
+            _ => Err(format!("Unknown channel: `{s}`")),
+        }
+    }
+}
 
 const LENGTH_COUNTER_VALUES: [u8; 32] = [
     10,254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14, // 00-0f
     12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30  // 10-1f
 ];
 
-struct PulseChannel {
+const DUTY_CYCLE_SEQUENCES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1], // 25% negated
+];
+
+/// Envelope generator, shared by the pulse and noise channels.
+struct Envelope {
+    start: bool,
+    divider: u8,
+    decay_level: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            start: false,
+            divider: 0,
+            decay_level: 0,
+        }
+    }
+
+    /// Clock the envelope (quarter frame). `period` is the volume/envelope divider period and `looping` is the
+    /// length counter halt flag, which doubles as the envelope loop flag.
+    fn clock(&mut self, period: u8, looping: bool) {
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = period;
+        } else if self.divider == 0 {
+            self.divider = period;
+
+            if self.decay_level != 0 {
+                self.decay_level -= 1;
+            } else if looping {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn volume(&self, period: u8, const_vol: bool) -> u8 {
+        if const_vol { period } else { self.decay_level }
+    }
+}
+
+/// A pulse channel, also used for the MMC5's expansion audio.
+pub(crate) struct PulseChannel {
     channel_num: usize,
+    /// Whether the channel has a sweep unit (the MMC5's pulse channels don't, so they aren't muted by it either.)
+    has_sweep: bool,
 
     duty_cycle: u8,
     length_counter_halt: bool,
     const_vol_env_flag: bool,
     vol_env_div_period: u8,
+    envelope: Envelope,
 
     sweep_enabled: bool,
     sweep_div_period: u8,
     sweep_negate: bool,
     sweep_shift_count: u8,
     sweep_reload: bool,
+    sweep_divider: u8,
 
     timer: u16,
+    timer_counter: u16,
     length_counter: u8,
 
     sequencer: u8,
@@ -29,28 +178,39 @@ impl PulseChannel {
     fn new(channel_num: usize) -> PulseChannel {
         PulseChannel {
             channel_num,
+            has_sweep: true,
             duty_cycle: 0,
             length_counter_halt: false,
             const_vol_env_flag: false,
             vol_env_div_period: 0,
+            envelope: Envelope::new(),
             sweep_enabled: false,
             sweep_div_period: 0,
             sweep_negate: false,
             sweep_shift_count: 0,
             sweep_reload: false,
+            sweep_divider: 0,
             timer: 0,
-            length_counter: LENGTH_COUNTER_VALUES[0],
+            timer_counter: 0,
+            length_counter: 0,
             sequencer: 0,
         }
     }
 
-    fn write_0(&mut self, val: u8) {
+    pub(crate) fn without_sweep(channel_num: usize) -> PulseChannel {
+        PulseChannel {
+            has_sweep: false,
+            ..PulseChannel::new(channel_num)
+        }
+    }
+
+    pub(crate) fn write_0(&mut self, val: u8) {
+        // "The duty cycle is changed, but the sequencer's current position isn't affected."
         self.duty_cycle          = (val & 0b1100_0000) >> 6;
         self.length_counter_halt =  val & 0b0010_0000 != 0;
         self.const_vol_env_flag  =  val & 0b0001_0000 != 0;
         self.vol_env_div_period  =  val & 0b0000_1111;
 
-        // TODO: Side effect: "The duty cycle is changed, but the sequencer's current position isn't affected."
         debug!("Wrote {:02x} to APU PULSE{} ${:04x} (duty cycle, ...)", val, self.channel_num, 0x4000 + (self.channel_num - 1) * 4);
     }
 
@@ -58,7 +218,7 @@ impl PulseChannel {
         self.sweep_enabled     =  val & 0b1000_0000 != 0;
         self.sweep_div_period  = (val & 0b0111_0000) >> 4;
         self.sweep_negate      =  val & 0b0000_1000 != 0;
-        self.sweep_shift_count =  val & 0b1000_0111;
+        self.sweep_shift_count =  val & 0b0000_0111;
 
         // Side effect: "Sets the reload flag"
         self.sweep_reload = true;
@@ -66,34 +226,118 @@ impl PulseChannel {
         debug!("Wrote {:02x} to APU PULSE{} ${:04x} (sweep)", val, self.channel_num, 0x4000 + (self.channel_num - 1) * 4 + 1);
     }
 
-    fn write_2(&mut self, val: u8) {
+    pub(crate) fn write_2(&mut self, val: u8) {
         self.timer = (self.timer & 0x700) | val as u16;
 
         debug!("Wrote {:02x} to APU PULSE{} ${:04x} (timer low)", val, self.channel_num, 0x4000 + (self.channel_num - 1) * 4 + 2);
     }
 
-    fn write_3(&mut self, val: u8) {
+    pub(crate) fn write_3(&mut self, val: u8, enabled: bool) {
         self.timer = (self.timer & 0x0ff) | (val as u16 & 0b111) << 8;
-        self.length_counter = LENGTH_COUNTER_VALUES[(val as usize & 0b1111_1000) >> 3];
 
-        // TODO: Side effect:
+        // "If the enabled flag is set, the length counter is loaded"
+        if enabled {
+            self.length_counter = LENGTH_COUNTER_VALUES[(val as usize & 0b1111_1000) >> 3];
+        }
+
+        // Side effect:
         //   "The sequencer is immediately restarted at the first value of the current sequence.
-        //   The envelope is also restarted. The persiod divider is _not_ reset."
+        //   The envelope is also restarted. The period divider is _not_ reset."
+        self.sequencer = 0;
+        self.envelope.start = true;
 
         debug!("Wrote {:02x} to APU PULSE{} ${:04x} (length counter, timer)", val, self.channel_num, 0x4000 + (self.channel_num - 1) * 4 + 3);
     }
 
-    fn tick(&mut self) {
-        // ...
+    /// Clock the timer (every APU cycle, i.e. every second CPU cycle).
+    pub(crate) fn tick(&mut self) {
+        if self.timer_counter == 0 {
+            self.timer_counter = self.timer;
+            self.sequencer = (self.sequencer + 1) & 0b111;
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    /// Clock the envelope (quarter frame).
+    pub(crate) fn clock_envelope(&mut self) {
+        self.envelope.clock(self.vol_env_div_period, self.length_counter_halt);
+    }
+
+    /// Clock the length counter (half frame).
+    pub(crate) fn clock_length_counter(&mut self) {
+        if self.length_counter != 0 && !self.length_counter_halt {
+            self.length_counter -= 1;
+        }
+    }
+
+    /// The period the sweep unit is (continuously) calculating.
+    ///
+    /// Pulse 1 adds the ones' complement of the change amount when negating (`period - change - 1`),
+    /// while pulse 2 adds the two's complement (`period - change`).
+    fn sweep_target_period(&self) -> u16 {
+        let change = self.timer >> self.sweep_shift_count;
+
+        if self.sweep_negate {
+            let ones_complement = (self.channel_num == 1) as u16;
+            self.timer.saturating_sub(change + ones_complement)
+        } else {
+            self.timer + change
+        }
+    }
+
+    /// "If the current period is less than 8 or the target period is greater than $7FF, the sweep unit mutes the
+    /// channel", regardless of whether the sweep unit is enabled.
+    fn sweep_muting(&self) -> bool {
+        self.has_sweep && (self.timer < 8 || self.sweep_target_period() > 0x7ff)
+    }
+
+    /// Clock the sweep unit (half frame).
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift_count != 0 && !self.sweep_muting() {
+            self.timer = self.sweep_target_period();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_div_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    pub(crate) fn disable(&mut self) {
+        self.length_counter = 0;
+    }
+
+    pub(crate) fn length_counter_active(&self) -> bool {
+        self.length_counter > 0
+    }
+
+    /// Current output level of the channel (0-15).
+    pub(crate) fn output(&self) -> u8 {
+        if self.length_counter == 0
+            || self.sweep_muting()
+            || DUTY_CYCLE_SEQUENCES[self.duty_cycle as usize][self.sequencer as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.volume(self.vol_env_div_period, self.const_vol_env_flag)
+        }
     }
 }
 
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
+     0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15,
+];
+
 struct TriangleChannel {
     timer: u16,
+    timer_counter: u16,
     linear_counter: u8,
     length_counter: u8,
     linear_counter_reload_val: u8,
-    length_counter_reload_val: u8,
     linear_counter_reload: bool,
     control_length_halt: bool,
     sequencer: u8,
@@ -103,10 +347,10 @@ impl TriangleChannel {
     fn new() -> TriangleChannel {
         TriangleChannel {
             timer: 0,
+            timer_counter: 0,
             linear_counter: 0,
             length_counter: 0,
             linear_counter_reload_val: 0,
-            length_counter_reload_val: 0,
             linear_counter_reload: false,
             control_length_halt: false,
             sequencer: 0,
@@ -114,15 +358,9 @@ impl TriangleChannel {
     }
 
     fn write_8(&mut self, val: u8) {
-        self.control_length_halt = val & 0b1000_0000 != 0;
-
-        let counter_reload_val = val & 0b0111_1111;
-
-        if self.control_length_halt {
-            self.linear_counter_reload_val = counter_reload_val;
-        } else {
-            self.length_counter_reload_val = counter_reload_val;
-        }
+        // The control flag doubles as the length counter halt flag
+        self.control_length_halt       = val & 0b1000_0000 != 0;
+        self.linear_counter_reload_val = val & 0b0111_1111;
 
         debug!("Wrote {:02x} to APU TRIANGLE $4008 (linear counter setup)", val);
     }
@@ -133,12 +371,14 @@ impl TriangleChannel {
         debug!("Wrote {:02x} to APU TRIANGLE $400a (timer low)", val);
     }
 
-    fn write_b(&mut self, val: u8) {
+    fn write_b(&mut self, val: u8, enabled: bool) {
         // "Length counter load and timer"
 
         self.timer = (self.timer & 0x0ff) | (val as u16 & 0b111) << 8;
 
-        self.length_counter = LENGTH_COUNTER_VALUES[(val as usize & 0b1111_1000) >> 3];
+        if enabled {
+            self.length_counter = LENGTH_COUNTER_VALUES[(val as usize & 0b1111_1000) >> 3];
+        }
 
         // Side effect: "Sets the linear counter reload flag"
         self.linear_counter_reload = true;
@@ -146,33 +386,93 @@ impl TriangleChannel {
         debug!("Wrote {:02x} to APU TRIANGLE $400b (length counter load, timer high)", val);
     }
 
+    /// Clock the timer (every CPU cycle).
+    ///
+    /// "The sequencer is clocked by the timer as long as both the linear counter and the length counter are nonzero."
+    /// Note that this also applies to the ultrasonic periods 0 and 1, where the sequencer keeps stepping at (more or
+    /// less) the CPU rate. This averages out to a constant level of ~7.5 after filtering, just like on hardware.
     fn tick(&mut self) {
-        // ...
+        if self.timer_counter == 0 {
+            self.timer_counter = self.timer;
+
+            if self.linear_counter != 0 && self.length_counter != 0 {
+                self.sequencer = (self.sequencer + 1) & 0b1_1111;
+            }
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    /// Clock the linear counter (quarter frame).
+    fn clock_linear_counter(&mut self) {
+        if self.linear_counter_reload {
+            self.linear_counter = self.linear_counter_reload_val;
+        } else if self.linear_counter != 0 {
+            self.linear_counter -= 1;
+        }
+
+        // "If the control flag is clear, the linear counter reload flag is cleared."
+        if !self.control_length_halt {
+            self.linear_counter_reload = false;
+        }
+    }
+
+    /// Clock the length counter (half frame).
+    fn clock_length_counter(&mut self) {
+        if self.length_counter != 0 && !self.control_length_halt {
+            self.length_counter -= 1;
+        }
+    }
+
+    fn disable(&mut self) {
+        self.length_counter = 0;
+    }
+
+    /// Current output level of the channel (0-15).
+    ///
+    /// Silencing the channel (through either counter) only halts the sequencer, so the output stays at whatever
+    /// level it was at.
+    fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[self.sequencer as usize]
     }
 }
 
 struct NoiseChannel {
     envelope_vol_div_period: u8,
     envelope_const_vol: bool,
+    envelope: Envelope,
     length_counter_halt: bool,
+    length_counter: u8,
     mode: bool,
     period: u16,
-    period_shift: u16,
-    length_counter_reload_val: u8,
+    period_values: &'static [u16; 16],
+    timer_counter: u16,
+    shift_register: u16,
 }
 
-const NOISE_PERIOD_VALUES: [u16; 16] = [ 4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068 ];
+// Periods are in CPU cycles
+const NOISE_PERIOD_VALUES_NTSC: [u16; 16] = [ 4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068 ];
+const NOISE_PERIOD_VALUES_PAL:  [u16; 16] = [ 4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708,  944, 1890, 3778 ];
 
 impl NoiseChannel {
-    fn new() -> NoiseChannel {
+    fn new(region: Region) -> NoiseChannel {
+        let period_values = match region {
+            Region::NTSC | Region::Dendy => &NOISE_PERIOD_VALUES_NTSC,
+            Region::PAL => &NOISE_PERIOD_VALUES_PAL,
+        };
+
         NoiseChannel {
+            period_values,
             envelope_vol_div_period: 0,
             envelope_const_vol: false,
+            envelope: Envelope::new(),
             length_counter_halt: false,
+            length_counter: 0,
             mode: false,
-            period: NOISE_PERIOD_VALUES[0], // ?
-            period_shift: 0x0000,           // ?
-            length_counter_reload_val: 0,
+            period: period_values[0],
+            timer_counter: 0,
+            // "On power-up, the shift register is loaded with the value 1."
+            shift_register: 1,
         }
     }
 
@@ -188,45 +488,121 @@ impl NoiseChannel {
     fn write_e(&mut self, val: u8) {
         // Mode and period
         self.mode   = val & 0b1000_0000 != 0;
-        self.period = NOISE_PERIOD_VALUES[val as usize & 0b0000_1111];
+        self.period = self.period_values[val as usize & 0b0000_1111];
 
         debug!("Wrote {:02x} to APU NOISE $400e (mode, period)", val);
     }
 
-    fn write_f(&mut self, val: u8) {
+    fn write_f(&mut self, val: u8, enabled: bool) {
         // Length counter (re)load and envelope restart
-        self.length_counter_reload_val = (val & 0b1111_1000) >> 3;
+        if enabled {
+            self.length_counter = LENGTH_COUNTER_VALUES[(val as usize & 0b1111_1000) >> 3];
+        }
 
-        // TODO: "envelope restart"
+        self.envelope.start = true;
 
         debug!("Wrote {:02x} to APU NOISE $400f (length counter load)", val);
     }
 
+    /// Clock the timer (every APU cycle, i.e. every second CPU cycle).
     fn tick(&mut self) {
-        // ...
+        if self.timer_counter == 0 {
+            // The period table is in CPU cycles, while the timer is clocked every APU cycle
+            self.timer_counter = self.period / 2 - 1;
+            self.clock_shift_register();
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    /// Clock the 15-bit linear feedback shift register.
+    ///
+    /// The feedback is bit 0 XOR bit 6 in short mode ("mode flag" set), or bit 0 XOR bit 1 in long mode.
+    fn clock_shift_register(&mut self) {
+        let tap = if self.mode { 6 } else { 1 };
+        let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+
+        self.shift_register >>= 1;
+        self.shift_register |= feedback << 14;
+    }
+
+    /// Clock the envelope (quarter frame).
+    fn clock_envelope(&mut self) {
+        self.envelope.clock(self.envelope_vol_div_period, self.length_counter_halt);
+    }
+
+    /// Clock the length counter (half frame).
+    fn clock_length_counter(&mut self) {
+        if self.length_counter != 0 && !self.length_counter_halt {
+            self.length_counter -= 1;
+        }
+    }
+
+    fn disable(&mut self) {
+        self.length_counter = 0;
+    }
+
+    /// Current output level of the channel (0-15).
+    fn output(&self) -> u8 {
+        // "The mixer receives the current envelope volume except when bit 0 of the shift register is set,
+        // or the length counter is zero"
+        if self.length_counter == 0 || self.shift_register & 1 != 0 {
+            0
+        } else {
+            self.envelope.volume(self.envelope_vol_div_period, self.envelope_const_vol)
+        }
     }
 }
 
 struct DMC {
     irq_enabled: bool,
+    irq_flag: bool,
     loop_flag: bool,
     period: u16,
+    rate_values: &'static [u16; 16],
+    timer_counter: u16,
     output_level: u8,
     sample_address: u16,
-    sample_length: u16
+    sample_length: u16,
+    // Memory reader
+    curr_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    dma_pending: bool,
+    // Output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
 }
 
-const DMC_RATE_VALUES: [u16; 16] = [ 428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106,  84,  72,  54 ];
+// Rates are in CPU cycles
+const DMC_RATE_VALUES_NTSC: [u16; 16] = [ 428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106,  84,  72,  54 ];
+const DMC_RATE_VALUES_PAL:  [u16; 16] = [ 398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118,  98,  78,  66,  50 ];
 
 impl DMC {
-    fn new() -> DMC {
+    fn new(region: Region) -> DMC {
+        let rate_values = match region {
+            Region::NTSC | Region::Dendy => &DMC_RATE_VALUES_NTSC,
+            Region::PAL => &DMC_RATE_VALUES_PAL,
+        };
+
         DMC {
+            rate_values,
             irq_enabled: false,
+            irq_flag: false,
             loop_flag: false,
-            period: DMC_RATE_VALUES[0], // ?
+            period: rate_values[0],
+            timer_counter: 0,
             output_level: 0,
             sample_address: 0xc000,
-            sample_length: 1, // ?
+            sample_length: 1,
+            curr_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            dma_pending: false,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
         }
     }
 
@@ -234,7 +610,12 @@ impl DMC {
         // Flags and rate
         self.irq_enabled = val & 0b1000_0000 != 0;
         self.loop_flag   = val & 0b0100_0000 != 0;
-        self.period      = DMC_RATE_VALUES[val as usize & 0b0000_1111];
+        self.period      = self.rate_values[val as usize & 0b0000_1111];
+
+        // "If clear, the interrupt flag is cleared."
+        if !self.irq_enabled {
+            self.irq_flag = false;
+        }
 
         debug!("Wrote {:02x} to APU DMC $4010 (irq, flags, rate)", val);
     }
@@ -260,34 +641,153 @@ impl DMC {
         debug!("Wrote {:02x} to APU DMC $4013 (sample length)", val);
     }
 
+    fn restart_sample(&mut self) {
+        self.curr_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        // "Writing to this register clears the DMC interrupt flag."
+        self.irq_flag = false;
+
+        if !enabled {
+            // "If the DMC bit is clear, the DMC bytes remaining will be set to 0 and the DMC will silence when it
+            // empties."
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            // "If the DMC bit is set, the DMC sample will be restarted only if its bytes remaining is 0."
+            self.restart_sample();
+        }
+    }
+
+    /// Clock the timer (every APU cycle, i.e. every second CPU cycle).
     fn tick(&mut self) {
-        // ...
+        if self.timer_counter == 0 {
+            // The rate table is in CPU cycles, while the timer is clocked every APU cycle
+            self.timer_counter = self.period / 2 - 1;
+            self.clock_output_unit();
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    fn clock_output_unit(&mut self) {
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            // "A new output cycle is started"
+            self.bits_remaining = 8;
+
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// Whether the memory reader wants to fill the sample buffer.
+    fn needs_sample(&self) -> bool {
+        self.sample_buffer.is_none() && self.bytes_remaining != 0 && !self.dma_pending
+    }
+
+    /// Give the memory reader the sample byte fetched by the CPU.
+    fn fill_sample_buffer(&mut self, val: u8) {
+        self.dma_pending = false;
+
+        if self.bytes_remaining == 0 {
+            // Disabled while the DMA was in progress
+            return;
+        }
+
+        self.sample_buffer = Some(val);
+
+        // "The address is incremented; if it exceeds $FFFF, it is wrapped around to $8000."
+        self.curr_address = if self.curr_address == 0xffff { 0x8000 } else { self.curr_address + 1 };
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart_sample();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    /// Current output level of the channel (0-127).
+    fn output(&self) -> u8 {
+        self.output_level
     }
 }
 
+/// Frame counter steps, in CPU cycles since the sequence was (re)started.
+struct FrameCounterSteps {
+    quarter_1: u16,
+    half_1: u16,
+    quarter_2: u16,
+    /// The frame interrupt is set on this cycle and the two following it (4-step mode), the half frame is clocked on
+    /// the second one, and the sequence restarts on the third.
+    step_4: u16,
+    /// The half frame is clocked on this cycle (5-step mode), and the sequence restarts on the next one.
+    step_5: u16,
+}
+
+const FRAME_COUNTER_STEPS_NTSC: FrameCounterSteps = FrameCounterSteps {
+    quarter_1: 7457,
+    half_1: 14913,
+    quarter_2: 22371,
+    step_4: 29828,
+    step_5: 37281,
+};
+
+const FRAME_COUNTER_STEPS_PAL: FrameCounterSteps = FrameCounterSteps {
+    quarter_1: 8313,
+    half_1: 16627,
+    quarter_2: 24939,
+    step_4: 33252,
+    step_5: 41565,
+};
+
 struct FrameCounter {
     mode_5_step: bool,
     irq_enable: bool,
-    timer: u16
+    timer: u16,
+    steps: &'static FrameCounterSteps,
 }
 
 impl FrameCounter {
-    fn new() -> FrameCounter {
+    fn new(region: Region) -> FrameCounter {
         FrameCounter {
-            mode_5_step: false, // ?
-            irq_enable: true,   // ?
-            timer: 0,           // ?
+            mode_5_step: false,
+            irq_enable: true,
+            timer: 0,
+            steps: match region {
+                Region::NTSC | Region::Dendy => &FRAME_COUNTER_STEPS_NTSC,
+                Region::PAL => &FRAME_COUNTER_STEPS_PAL,
+            },
         }
     }
 
     fn write_frame_counter(&mut self, val: u8) {
         // Set mode and interrupt
         self.mode_5_step = val & 0b1000_0000 != 0;
+        self.irq_enable  = val & 0b0100_0000 == 0;
 
-        // TODO? "If set, the frame interrupt flag is cleared, otherwise it is unaffected"
-        self.irq_enable = val & 0b0100_0000 == 0;
-
-        // TODO: Side effects:
+        // Side effects (handled by the APU):
         //   "After 3 or 4 CPU clock cycles*, the timer is reset."
         //     "* If the write occurs during an APU cycle, the effects occur 3 CPU cycles after the $4017 write cycle,
         //        and if the write occurs between APU cycles, the effects occurs 4 CPU cycles after the write cycle. "
@@ -320,22 +820,28 @@ pub struct APU {
     status: APUStatus,
     frame_counter: FrameCounter,
     cycles: usize,
-    should_reset_cycles: bool,
     is_apu_cycle: bool,
     frame_counter_reset_timeout: i8,
     frame_interrupt: bool,
     open_bus: u8,
+    mixer: Mixer,
+    resampler: Resampler,
+    /// Whether samples are generated (see [APU::set_output_enabled].)
+    output_enabled: bool,
+    expansion_chip: Option<ExpansionChip>,
+    expansion_output: f32,
+    region: Region,
 }
 
 impl APU {
-    pub fn new() -> APU {
+    pub fn new(region: Region) -> APU {
         APU {
             pulse1: PulseChannel::new(1),
             pulse2: PulseChannel::new(2),
             triangle: TriangleChannel::new(),
-            noise: NoiseChannel::new(),
-            dmc: DMC::new(),
-            frame_counter: FrameCounter::new(),
+            noise: NoiseChannel::new(region),
+            dmc: DMC::new(region),
+            frame_counter: FrameCounter::new(region),
             status: APUStatus {
                 dmc_enabled: false,
                 noise_enabled: false,
@@ -345,31 +851,21 @@ impl APU {
             },
             cycles: 0,
 
-            should_reset_cycles: false,
             is_apu_cycle: false,
             frame_counter_reset_timeout: -1,
             frame_interrupt: false,
             open_bus: 0x00,
+            mixer: Mixer::new(),
+            resampler: Resampler::new(region.cpu_freq(), DEFAULT_SAMPLE_RATE),
+            output_enabled: true,
+            region,
+            expansion_chip: None,
+            expansion_output: 0.0,
         }
     }
 
     pub fn cycle(&mut self) {
-        if self.should_reset_cycles {
-            self.cycles = 0;
-            self.should_reset_cycles = false;
-        } else {
-            self.cycles += 1;
-        }
-
-        if self.frame_counter_reset_timeout > 0 {
-            self.frame_counter_reset_timeout -= 1;
-
-            if self.frame_counter_reset_timeout == 0 {
-                self.frame_counter.reset_timer();
-            }
-        }
-
-        // TODO: do things
+        self.cycles += 1;
 
         // "Triangle channel's timer is clocked on every CPU cycle"
         self.triangle.tick();
@@ -377,69 +873,219 @@ impl APU {
         // Every _other_ cycle, starting at 1
         if self.cycles & 1 == 0 {
             // "Pulse, noise, and DMC timers are clocked on every second CPU cycle and thus produce only even periods"
-            if self.status.pulse1_enabled { self.pulse1.tick(); }
-            if self.status.pulse2_enabled { self.pulse2.tick(); }
-            if self.status.noise_enabled  { self.noise.tick();  }
-            if self.status.dmc_enabled    { self.dmc.tick();    }
+            self.pulse1.tick();
+            self.pulse2.tick();
+            self.noise.tick();
+            self.dmc.tick();
 
             self.is_apu_cycle = true;
         } else {
             self.is_apu_cycle = false;
         }
 
-        match self.cycles {
-            // Quarter frame: Clock "Envelopes & triangle's linear counter"
-            // Half frame:    Clock "Length counters & sweep units" && clock quarter frame
-            3728 => {
-                // Quarter frame
-            }
-            7456 => {
-                // Half frame
-            }
-            11185 => {
-                // Quarter frame
-            }
-            14914 => {
-                if !self.frame_counter.mode_5_step {
-                    // Half frame (if 4-step)
+        self.clock_frame_counter();
 
-                    if self.frame_counter.irq_enable {
-                        // TODO: "PUT" and "GET"...?
+        if !self.output_enabled {
+            return;
+        }
+        let amp = self.mixer.mix(
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+            self.expansion_output,
+        );
+        self.resampler.push(amp);
+    }
 
-                        // Set frame interrupt flag
-                        self.frame_interrupt = true;
-                    }
+    /// Enable or disable generating samples. While disabled, the channels keep running but nothing is mixed or
+    /// resampled, so the output continues where it left off once enabled again.
+    pub(crate) fn set_output_enabled(&mut self, enabled: bool) {
+        self.output_enabled = enabled;
+    }
+
+    /// Set the expansion audio chip of the loaded cartridge (if any.)
+    pub(crate) fn set_expansion_chip(&mut self, chip: Option<ExpansionChip>) {
+        self.expansion_chip = chip;
+        self.mixer.set_expansion_level(chip.map_or(0.0, |c| c.relative_level()));
+        self.expansion_output = 0.0;
+    }
+
+    pub(crate) fn has_expansion_audio(&self) -> bool {
+        self.expansion_chip.is_some()
+    }
+
+    /// Set the current expansion audio output (0.0-1.0 of the chip's full-scale output), to be mixed in on the next
+    /// cycle.
+    pub(crate) fn set_expansion_output(&mut self, output: f32) {
+        self.expansion_output = output;
+    }
+
+    /// Take every audio sample generated since the last call (mono, in the range -1.0 to 1.0).
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.resampler.take_samples()
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    /// Set the output sample rate, in Hz.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler.set_rates(self.region.cpu_freq(), sample_rate);
+    }
+
+    /// Copy the output settings (sample rate, channel gains, mute & solo) of `other`.
+    pub(crate) fn copy_output_settings(&mut self, other: &APU) {
+        self.set_sample_rate(other.sample_rate());
+        self.mixer = other.mixer.clone();
+        self.set_expansion_chip(self.expansion_chip);
+    }
+
+    /// The gain of `channel`, not taking mute/solo into account.
+    pub fn channel_gain(&self, channel: Channel) -> f32 {
+        self.mixer.gain(channel)
+    }
+
+    /// Set the gain of `channel` (1.0 is the normal volume). This only affects the audio output.
+    pub fn set_channel_gain(&mut self, channel: Channel, gain: f32) {
+        self.mixer.set_gain(channel, gain);
+    }
+
+    pub fn is_channel_muted(&self, channel: Channel) -> bool {
+        self.mixer.is_muted(channel)
+    }
+
+    pub fn set_channel_muted(&mut self, channel: Channel, muted: bool) {
+        self.mixer.set_muted(channel, muted);
+    }
+
+    pub fn toggle_channel_muted(&mut self, channel: Channel) {
+        self.set_channel_muted(channel, !self.is_channel_muted(channel));
+    }
+
+    pub fn solo_channel(&self) -> Option<Channel> {
+        self.mixer.solo()
+    }
+
+    /// Only play `channel` (or every channel if `None`).
+    pub fn set_solo_channel(&mut self, channel: Option<Channel>) {
+        self.mixer.set_solo(channel);
+    }
+
+    /// Reset every channel to full volume, unmuted and without solo.
+    pub fn reset_channel_mixing(&mut self) {
+        for ch in Channel::ALL {
+            self.mixer.set_gain(ch, 1.0);
+            self.mixer.set_muted(ch, false);
+        }
+        self.mixer.set_solo(None);
+    }
+
+    /// Print the gain/mute/solo state of every channel.
+    pub(crate) fn print_mixing_state(&self) {
+        for ch in Channel::ALL {
+            let state = if self.mixer.is_muted(ch) {
+                " (muted)"
+            } else if self.mixer.solo() == Some(ch) {
+                " (solo)"
+            } else if self.mixer.effective_gain(ch) == 0.0 {
+                " (silenced by solo)"
+            } else {
+                ""
+            };
+            println!("{:<10} gain: {:.2}{state}", ch.to_string(), self.mixer.gain(ch));
+        }
+    }
+
+    /// Clock the frame counter (every CPU cycle), generating the quarter/half frame signals and the frame interrupt.
+    fn clock_frame_counter(&mut self) {
+        if self.frame_counter_reset_timeout > 0 {
+            self.frame_counter_reset_timeout -= 1;
+
+            if self.frame_counter_reset_timeout == 0 {
+                self.frame_counter.reset_timer();
 
-                    self.should_reset_cycles = true;
-                }
-            }
-            18640 => {
                 if self.frame_counter.mode_5_step {
-                    // Half frame (if 5-step)
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
                 }
+            }
+        }
 
-                self.should_reset_cycles = true;
+        // Quarter frame: Clock "Envelopes & triangle's linear counter"
+        // Half frame:    Clock "Length counters & sweep units" && clock quarter frame
+        let steps = self.frame_counter.steps;
+        match (self.frame_counter.timer, self.frame_counter.mode_5_step) {
+            (t, _) if t == steps.quarter_1 || t == steps.quarter_2 => {
+                // Quarter frame
+                self.clock_quarter_frame();
             }
-            0 => {
-                if !self.frame_counter.mode_5_step {
-                    if self.frame_counter.irq_enable {
-                        // Set frame interrupt flag
-                        self.frame_interrupt = true;
-                    }
-                }
+            (t, _) if t == steps.half_1 => {
+                // Half frame
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (t, false) if t == steps.step_4 => {
+                self.set_frame_interrupt();
+            }
+            (t, false) if t == steps.step_4 + 1 => {
+                // Half frame (if 4-step)
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+                self.set_frame_interrupt();
+            }
+            (t, false) if t == steps.step_4 + 2 => {
+                // Also the first cycle of the next sequence
+                self.set_frame_interrupt();
+                self.frame_counter.reset_timer();
+            }
+            (t, true) if t == steps.step_5 => {
+                // Half frame (if 5-step)
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            (t, true) if t == steps.step_5 + 1 => {
+                self.frame_counter.reset_timer();
             }
             _ => {},
         }
 
-        // Frame counter
         self.frame_counter.tick();
     }
 
+    fn set_frame_interrupt(&mut self) {
+        // "If the interrupt inhibit flag is clear, the frame interrupt flag is set"
+        if self.frame_counter.irq_enable {
+            self.frame_interrupt = true;
+        }
+    }
+
+    /// Clock the envelopes and the triangle's linear counter.
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.clock_envelope();
+        self.pulse2.clock_envelope();
+        self.triangle.clock_linear_counter();
+        self.noise.clock_envelope();
+    }
+
+    /// Clock the length counters and sweep units.
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_length_counter();
+        self.pulse2.clock_length_counter();
+        self.triangle.clock_length_counter();
+        self.noise.clock_length_counter();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+
     pub fn read_addr(&mut self, addr: u16) -> u8 {
         let val = self.read_addr_no_sideeffect(addr);
 
         if addr == 0x4015 {
-            self.frame_counter.irq_enable = false;
+            // "Reading this register clears the frame interrupt flag (but not the DMC interrupt flag)."
+            self.frame_interrupt = false;
         }
 
         val
@@ -462,22 +1108,22 @@ impl APU {
             0x4000 => self.pulse1.write_0(val),
             0x4001 => self.pulse1.write_1(val),
             0x4002 => self.pulse1.write_2(val),
-            0x4003 => self.pulse1.write_3(val),
+            0x4003 => self.pulse1.write_3(val, self.status.pulse1_enabled),
             // Pulse 2
             0x4004 => self.pulse2.write_0(val),
             0x4005 => self.pulse2.write_1(val),
             0x4006 => self.pulse2.write_2(val),
-            0x4007 => self.pulse2.write_3(val),
+            0x4007 => self.pulse2.write_3(val, self.status.pulse2_enabled),
             // Triangle
             0x4008 => self.triangle.write_8(val),
             0x4009 => {}
             0x400a => self.triangle.write_a(val),
-            0x400b => self.triangle.write_b(val),
+            0x400b => self.triangle.write_b(val, self.status.triangle_enabled),
             // Noise
             0x400c => self.noise.write_c(val),
             0x400d => {}
             0x400e => self.noise.write_e(val),
-            0x400f => self.noise.write_f(val),
+            0x400f => self.noise.write_f(val, self.status.noise_enabled),
             // DMC
             0x4010 => self.dmc.write_0(val),
             0x4011 => self.dmc.write_1(val),
@@ -488,6 +1134,11 @@ impl APU {
             0x4017 => {
                 self.frame_counter.write_frame_counter(val);
 
+                // "If set, the frame interrupt flag is cleared, otherwise it is unaffected."
+                if !self.frame_counter.irq_enable {
+                    self.frame_interrupt = false;
+                }
+
                 if self.is_apu_cycle {
                     self.frame_counter_reset_timeout = 3;
                 } else {
@@ -502,17 +1153,16 @@ impl APU {
         // "will read as 1 if the corresponding length counter has not been halted through either
         // expiring or a write of 0 to the corresponding bit.
         // For the triangle channel,the status of the linear counter is irrelevant."
-        let bit0 = !self.pulse1.length_counter_halt   as u8;
-        let bit1 = !self.pulse2.length_counter_halt   as u8;
-        let bit2 = !self.triangle.control_length_halt as u8;
-        let bit3 = !self.noise.length_counter_halt    as u8;
+        let bit0 = (self.pulse1.length_counter > 0)   as u8;
+        let bit1 = (self.pulse2.length_counter > 0)   as u8;
+        let bit2 = (self.triangle.length_counter > 0) as u8;
+        let bit3 = (self.noise.length_counter > 0)    as u8;
         // "Will read as 1 if the DMC bytes remaining is more than 0."
-        let bit4 = 0; // TODO: "will read as 1 if the DMC bytes remaining is more than 0"
+        let bit4 = (self.dmc.bytes_remaining > 0) as u8;
         let bit5 = (self.open_bus & 0b0010_0000) >> 5; // Open bus read ("the open bus value comes from the last cycle that did not read $4015")
 
-        // TODO: "Reading this register clears the frame interrupt flag (but no the DMC interrupt flag)."
-        let bit6 = self.frame_counter.irq_enable as u8;
-        let bit7 = self.dmc.irq_enabled as u8;
+        let bit6 = self.frame_interrupt as u8;
+        let bit7 = self.dmc.irq_flag as u8;
 
         bit0
         | (bit1 << 1)
@@ -531,10 +1181,439 @@ impl APU {
         self.status.noise_enabled    = (val & 0b01000) != 0;
         self.status.dmc_enabled      = (val & 0b10000) != 0;
 
+        // "When the enabled bit is cleared (via $4015), the length counter is forced to 0"
+        if !self.status.pulse1_enabled   { self.pulse1.disable();   }
+        if !self.status.pulse2_enabled   { self.pulse2.disable();   }
+        if !self.status.triangle_enabled { self.triangle.disable(); }
+        if !self.status.noise_enabled    { self.noise.disable();    }
+        self.dmc.set_enabled(self.status.dmc_enabled);
+
         debug!("Wrote {:02x} to APU STATUS ($4015)", val & 0b11111)
     }
 
+    pub(super) fn print_state(&self) -> () {
+        println!("APU STATE:");
+        println!(
+            "  pulse1: {:2} (period: {:03x}, length: {:3}), pulse2: {:2} (period: {:03x}, length: {:3})",
+            self.pulse1.output(), self.pulse1.timer, self.pulse1.length_counter,
+            self.pulse2.output(), self.pulse2.timer, self.pulse2.length_counter,
+        );
+        println!(
+            "  triangle: {:2} (period: {:03x}, length: {:3}, linear: {:3})",
+            self.triangle.output(), self.triangle.timer, self.triangle.length_counter, self.triangle.linear_counter,
+        );
+        println!(
+            "  noise: {:2} (period: {:4}, length: {:3}, mode: {}, lfsr: {:04x})",
+            self.noise.output(), self.noise.period, self.noise.length_counter, self.noise.mode as u8,
+            self.noise.shift_register,
+        );
+        println!(
+            "  dmc: {:3} (address: {:04x}, remaining: {:4}, irq: {})",
+            self.dmc.output(), self.dmc.curr_address, self.dmc.bytes_remaining, self.dmc.irq_flag,
+        );
+    }
+
+    /// If the DMC memory reader needs a new sample byte, returns the address the CPU should fetch it from.
+    ///
+    /// The fetch is then considered in progress until it is completed through [APU::dmc_dma_complete].
+    pub(crate) fn dmc_dma_request(&mut self) -> Option<u16> {
+        if self.dmc.needs_sample() {
+            self.dmc.dma_pending = true;
+            Some(self.dmc.curr_address)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn dmc_dma_complete(&mut self, val: u8) {
+        self.dmc.fill_sample_buffer(val);
+    }
+
+    pub(crate) fn dmc_irq_triggered(&self) -> bool {
+        self.dmc.irq_flag
+    }
+
+    pub(crate) fn frame_irq_triggered(&self) -> bool {
+        self.frame_interrupt
+    }
+
+    /// Whether the current CPU cycle is a "put" cycle (as opposed to a "get" cycle).
+    pub(crate) fn is_put_cycle(&self) -> bool {
+        self.is_apu_cycle
+    }
+
     pub(crate) fn set_open_bus(&mut self, val: u8) -> () {
         self.open_bus = val;
     }
 }
+
+
+impl Snapshot for Envelope {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.start);
+        w.write(&self.divider);
+        w.write(&self.decay_level);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.start = r.read()?;
+        self.divider = r.read()?;
+        self.decay_level = r.read()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for PulseChannel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.duty_cycle);
+        w.write(&self.length_counter_halt);
+        w.write(&self.const_vol_env_flag);
+        w.write(&self.vol_env_div_period);
+        self.envelope.save_state(w);
+        w.write(&self.sweep_enabled);
+        w.write(&self.sweep_div_period);
+        w.write(&self.sweep_negate);
+        w.write(&self.sweep_shift_count);
+        w.write(&self.sweep_reload);
+        w.write(&self.sweep_divider);
+        w.write(&self.timer);
+        w.write(&self.timer_counter);
+        w.write(&self.length_counter);
+        w.write(&self.sequencer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.duty_cycle = r.read()?;
+        self.length_counter_halt = r.read()?;
+        self.const_vol_env_flag = r.read()?;
+        self.vol_env_div_period = r.read()?;
+        self.envelope.load_state(r)?;
+        self.sweep_enabled = r.read()?;
+        self.sweep_div_period = r.read()?;
+        self.sweep_negate = r.read()?;
+        self.sweep_shift_count = r.read()?;
+        self.sweep_reload = r.read()?;
+        self.sweep_divider = r.read()?;
+        self.timer = r.read()?;
+        self.timer_counter = r.read()?;
+        self.length_counter = r.read()?;
+        self.sequencer = r.read()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for TriangleChannel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.timer);
+        w.write(&self.timer_counter);
+        w.write(&self.linear_counter);
+        w.write(&self.length_counter);
+        w.write(&self.linear_counter_reload_val);
+        w.write(&self.linear_counter_reload);
+        w.write(&self.control_length_halt);
+        w.write(&self.sequencer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.timer = r.read()?;
+        self.timer_counter = r.read()?;
+        self.linear_counter = r.read()?;
+        self.length_counter = r.read()?;
+        self.linear_counter_reload_val = r.read()?;
+        self.linear_counter_reload = r.read()?;
+        self.control_length_halt = r.read()?;
+        self.sequencer = r.read()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for NoiseChannel {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.envelope_vol_div_period);
+        w.write(&self.envelope_const_vol);
+        self.envelope.save_state(w);
+        w.write(&self.length_counter_halt);
+        w.write(&self.length_counter);
+        w.write(&self.mode);
+        w.write(&self.period);
+        w.write(&self.timer_counter);
+        w.write(&self.shift_register);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.envelope_vol_div_period = r.read()?;
+        self.envelope_const_vol = r.read()?;
+        self.envelope.load_state(r)?;
+        self.length_counter_halt = r.read()?;
+        self.length_counter = r.read()?;
+        self.mode = r.read()?;
+        self.period = r.read()?;
+        self.timer_counter = r.read()?;
+        self.shift_register = r.read()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for DMC {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.irq_enabled);
+        w.write(&self.irq_flag);
+        w.write(&self.loop_flag);
+        w.write(&self.period);
+        w.write(&self.timer_counter);
+        w.write(&self.output_level);
+        w.write(&self.sample_address);
+        w.write(&self.sample_length);
+        w.write(&self.curr_address);
+        w.write(&self.bytes_remaining);
+        w.write(&self.sample_buffer);
+        w.write(&self.dma_pending);
+        w.write(&self.shift_register);
+        w.write(&self.bits_remaining);
+        w.write(&self.silence);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.irq_enabled = r.read()?;
+        self.irq_flag = r.read()?;
+        self.loop_flag = r.read()?;
+        self.period = r.read()?;
+        self.timer_counter = r.read()?;
+        self.output_level = r.read()?;
+        self.sample_address = r.read()?;
+        self.sample_length = r.read()?;
+        self.curr_address = r.read()?;
+        self.bytes_remaining = r.read()?;
+        self.sample_buffer = r.read()?;
+        self.dma_pending = r.read()?;
+        self.shift_register = r.read()?;
+        self.bits_remaining = r.read()?;
+        self.silence = r.read()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for APU {
+    /// Note: only the state of the APU itself is saved, not the output settings (mixing, sample rate.)
+    fn save_state(&self, w: &mut StateWriter) {
+        self.pulse1.save_state(w);
+        self.pulse2.save_state(w);
+        self.triangle.save_state(w);
+        self.noise.save_state(w);
+        self.dmc.save_state(w);
+
+        w.write(&self.status.dmc_enabled);
+        w.write(&self.status.noise_enabled);
+        w.write(&self.status.triangle_enabled);
+        w.write(&self.status.pulse1_enabled);
+        w.write(&self.status.pulse2_enabled);
+
+        w.write(&self.frame_counter.mode_5_step);
+        w.write(&self.frame_counter.irq_enable);
+        w.write(&self.frame_counter.timer);
+
+        w.write(&self.cycles);
+        w.write(&self.is_apu_cycle);
+        w.write(&self.frame_counter_reset_timeout);
+        w.write(&self.frame_interrupt);
+        w.write(&self.open_bus);
+        w.write(&self.expansion_output);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.pulse1.load_state(r)?;
+        self.pulse2.load_state(r)?;
+        self.triangle.load_state(r)?;
+        self.noise.load_state(r)?;
+        self.dmc.load_state(r)?;
+
+        self.status.dmc_enabled = r.read()?;
+        self.status.noise_enabled = r.read()?;
+        self.status.triangle_enabled = r.read()?;
+        self.status.pulse1_enabled = r.read()?;
+        self.status.pulse2_enabled = r.read()?;
+
+        self.frame_counter.mode_5_step = r.read()?;
+        self.frame_counter.irq_enable = r.read()?;
+        self.frame_counter.timer = r.read()?;
+
+        self.cycles = r.read()?;
+        self.is_apu_cycle = r.read()?;
+        self.frame_counter_reset_timeout = r.read()?;
+        self.frame_interrupt = r.read()?;
+        self.open_bus = r.read()?;
+        self.expansion_output = r.read()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sweep_negate_complement_test() {
+        let mut p1 = PulseChannel::new(1);
+        let mut p2 = PulseChannel::new(2);
+        for p in [&mut p1, &mut p2] {
+            p.write_2(0x00);
+            p.write_3(0x01, true); // period = $100
+            p.write_1(0b1000_1001); // enabled, negate, shift 1
+        }
+
+        assert_eq!(p1.sweep_target_period(), 0x100 - 0x80 - 1);
+        assert_eq!(p2.sweep_target_period(), 0x100 - 0x80);
+    }
+
+    #[test]
+    fn sweep_muting_test() {
+        let mut p = PulseChannel::new(2);
+        p.write_0(0b1011_1111); // 50% duty, constant volume 15
+        p.write_2(0xff);
+        p.write_3(0x07, true); // period = $7ff, so adding anything overflows
+        p.write_1(0b0000_0001); // disabled sweep, shift 1
+        assert!(p.sweep_muting());
+
+        p.write_1(0b0000_1001); // negate
+        assert!(!p.sweep_muting());
+    }
+
+    #[test]
+    fn triangle_linear_counter_test() {
+        let mut t = TriangleChannel::new();
+        t.write_8(0x02); // control flag clear, reload value 2
+        t.write_b(0x08, true);
+
+        t.clock_linear_counter();
+        assert_eq!(t.linear_counter, 2);
+        assert!(!t.linear_counter_reload);
+
+        t.clock_linear_counter();
+        t.clock_linear_counter();
+        t.clock_linear_counter();
+        assert_eq!(t.linear_counter, 0);
+
+        // The sequencer is halted once the linear counter reaches 0
+        let seq = t.sequencer;
+        for _ in 0..16 {
+            t.tick();
+        }
+        assert_eq!(t.sequencer, seq);
+    }
+
+    #[test]
+    fn noise_lfsr_test() {
+        let mut n = NoiseChannel::new(Region::NTSC);
+
+        // Long mode: 32767 step sequence
+        let start = n.shift_register;
+        let mut steps = 0;
+        loop {
+            n.clock_shift_register();
+            steps += 1;
+            if n.shift_register == start {
+                break;
+            }
+        }
+        assert_eq!(steps, 32767);
+
+        // Short mode: 93 step sequence (starting from 1)
+        n.write_e(0b1000_0000);
+        let start = n.shift_register;
+        let mut steps = 0;
+        loop {
+            n.clock_shift_register();
+            steps += 1;
+            if n.shift_register == start {
+                break;
+            }
+        }
+        assert_eq!(steps, 93);
+    }
+
+    #[test]
+    fn dmc_memory_reader_test() {
+        let mut apu = APU::new(Region::NTSC);
+        apu.write_addr(0x4010, 0b1000_1111); // IRQ enabled, no loop, fastest rate
+        apu.write_addr(0x4012, 0xff);         // $ffc0
+        apu.write_addr(0x4013, 0x00);         // 1 byte
+        apu.write_addr(0x4015, 0b1_0000);
+
+        assert_eq!(apu.read_addr_no_sideeffect(0x4015) & 0b1_0000, 0b1_0000);
+        assert_eq!(apu.dmc_dma_request(), Some(0xffc0));
+        // Only one fetch at a time
+        assert_eq!(apu.dmc_dma_request(), None);
+
+        apu.dmc_dma_complete(0xaa);
+        assert_eq!(apu.read_addr_no_sideeffect(0x4015) & 0b1_0000, 0);
+        assert!(apu.dmc_irq_triggered());
+
+        // Writing $4015 acknowledges the IRQ
+        apu.write_addr(0x4015, 0);
+        assert!(!apu.dmc_irq_triggered());
+    }
+
+    #[test]
+    fn frame_irq_test() {
+        let mut apu = APU::new(Region::NTSC);
+        for _ in 0..29828 {
+            apu.cycle();
+        }
+        assert!(!apu.frame_irq_triggered());
+        apu.cycle();
+        assert!(apu.frame_irq_triggered());
+
+        // Reading $4015 acknowledges the IRQ
+        assert_eq!(apu.read_addr(0x4015) & 0b0100_0000, 0b0100_0000);
+        assert!(!apu.frame_irq_triggered());
+
+        // No IRQs in 5-step mode
+        apu.write_addr(0x4017, 0b1000_0000);
+        for _ in 0..(37282 * 2) {
+            apu.cycle();
+        }
+        assert!(!apu.frame_irq_triggered());
+    }
+
+    #[test]
+    fn frame_irq_pal_test() {
+        let mut apu = APU::new(Region::PAL);
+        for _ in 0..33252 {
+            apu.cycle();
+        }
+        assert!(!apu.frame_irq_triggered());
+        apu.cycle();
+        assert!(apu.frame_irq_triggered());
+    }
+
+    #[test]
+    fn mixer_mute_solo_test() {
+        let mut mixer = Mixer::new();
+        let full = mixer.mix(15, 15, 15, 15, 127, 0.0);
+
+        // Halving the gain is the same as halving the channel's output
+        mixer.set_gain(Channel::Noise, 0.5);
+        let half = mixer.mix(0, 0, 0, 14, 0, 0.0);
+        assert!((half - Mixer::new().mix(0, 0, 0, 7, 0, 0.0)).abs() < 0.000001, "half: {half}");
+
+        // Scaling with unity gain must match the lookup tables
+        mixer.set_gain(Channel::Noise, 1.0);
+        assert_eq!(mixer.mix(15, 15, 15, 15, 127, 0.0), full);
+
+        mixer.set_muted(Channel::Pulse1, true);
+        assert_eq!(mixer.mix(15, 0, 0, 0, 0, 0.0), 0.0);
+        assert!(mixer.mix(0, 15, 0, 0, 0, 0.0) > 0.0);
+
+        mixer.set_muted(Channel::Pulse1, false);
+        mixer.set_solo(Some(Channel::Triangle));
+        assert_eq!(mixer.mix(15, 15, 0, 15, 127, 0.0), 0.0);
+        assert_eq!(mixer.mix(0, 0, 15, 0, 0, 0.0), Mixer::new().mix(0, 0, 15, 0, 0, 0.0));
+
+        // Expansion audio is scaled by the chip level
+        mixer.set_solo(Some(Channel::Expansion));
+        mixer.set_expansion_level(ExpansionChip::FDS.relative_level());
+        assert_eq!(mixer.mix(15, 15, 15, 15, 127, 0.0), 0.0);
+        let fds = mixer.mix(0, 0, 0, 0, 0, 1.0);
+        assert!((fds - 2.4 * Mixer::new().mix(15, 0, 0, 0, 0, 0.0)).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,128 @@
+// See https://www.nesdev.org/wiki/APU_Mixer#Lookup_Table
+
+use super::Channel;
+
+/// Output of a single pulse channel at full volume; expansion audio levels are relative to this.
+const PULSE_FULL_VOLUME: f32 = 95.52 / (8128.0 / 15.0 + 100.0);
+
+/// The nonlinear APU mixer, using the lookup table approximation.
+#[derive(Clone)]
+pub struct Mixer {
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+    gains: [f32; Channel::COUNT],
+    muted: [bool; Channel::COUNT],
+    solo: Option<Channel>,
+    /// Gain of each channel after applying mute/solo.
+    effective_gains: [f32; Channel::COUNT],
+    /// Whether every channel is at full volume, meaning the lookup tables can be used as-is.
+    unity_gain: bool,
+    /// Full-scale output of the expansion audio chip, in mixer units.
+    expansion_level: f32,
+}
+
+impl Mixer {
+    pub fn new() -> Mixer {
+        let mut pulse_table = [0.0; 31];
+        let mut tnd_table = [0.0; 203];
+
+        // "pulse_table[n] = 95.52 / (8128.0 / n + 100)"
+        for (n, val) in pulse_table.iter_mut().enumerate().skip(1) {
+            *val = pulse_out(n as f32);
+        }
+
+        // "tnd_table[n] = 163.67 / (24329.0 / n + 100)"
+        for (n, val) in tnd_table.iter_mut().enumerate().skip(1) {
+            *val = tnd_out(n as f32);
+        }
+
+        Mixer {
+            pulse_table,
+            tnd_table,
+            gains: [1.0; Channel::COUNT],
+            muted: [false; Channel::COUNT],
+            solo: None,
+            effective_gains: [1.0; Channel::COUNT],
+            unity_gain: true,
+            expansion_level: 0.0,
+        }
+    }
+
+    /// Mix the channel outputs (pulse/triangle/noise: 0-15, DMC: 0-127, expansion: 0.0-1.0) into a single value.
+    ///
+    /// Without expansion audio, the result is in the range 0.0-1.0.
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8, expansion: f32) -> f32 {
+        // Expansion audio is mixed linearly, as it comes in through the cartridge's audio input/output pins
+        let expansion_out = expansion * self.expansion_level * self.effective_gains[Channel::Expansion as usize];
+
+        if self.unity_gain {
+            let pulse_out = self.pulse_table[(pulse1 + pulse2) as usize];
+            let tnd_out = self.tnd_table[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+
+            return pulse_out + tnd_out + expansion_out;
+        }
+
+        // Scaling the inputs (instead of the outputs) keeps the nonlinearity between the channels intact
+        let g = &self.effective_gains;
+        let pulse_n = pulse1 as f32 * g[Channel::Pulse1 as usize] + pulse2 as f32 * g[Channel::Pulse2 as usize];
+        let tnd_n = 3.0 * triangle as f32 * g[Channel::Triangle as usize]
+            + 2.0 * noise as f32 * g[Channel::Noise as usize]
+            + dmc as f32 * g[Channel::DMC as usize];
+
+        pulse_out(pulse_n) + tnd_out(tnd_n) + expansion_out
+    }
+
+    /// Set the full-scale output level of the expansion audio chip, relative to a single APU pulse channel.
+    pub fn set_expansion_level(&mut self, relative_level: f32) {
+        self.expansion_level = relative_level * PULSE_FULL_VOLUME;
+    }
+
+    pub fn gain(&self, channel: Channel) -> f32 {
+        self.gains[channel as usize]
+    }
+
+    pub fn set_gain(&mut self, channel: Channel, gain: f32) {
+        self.gains[channel as usize] = gain.max(0.0);
+        self.update_effective_gains();
+    }
+
+    pub fn is_muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+        self.update_effective_gains();
+    }
+
+    pub fn solo(&self) -> Option<Channel> {
+        self.solo
+    }
+
+    pub fn set_solo(&mut self, solo: Option<Channel>) {
+        self.solo = solo;
+        self.update_effective_gains();
+    }
+
+    /// The gain of `channel`, taking mute/solo into account.
+    pub fn effective_gain(&self, channel: Channel) -> f32 {
+        self.effective_gains[channel as usize]
+    }
+
+    fn update_effective_gains(&mut self) {
+        for ch in Channel::ALL {
+            let silenced = self.muted[ch as usize] || self.solo.is_some_and(|s| s != ch);
+            self.effective_gains[ch as usize] = if silenced { 0.0 } else { self.gains[ch as usize] };
+        }
+
+        self.unity_gain = self.effective_gains.iter().all(|&g| g == 1.0);
+    }
+}
+
+fn pulse_out(n: f32) -> f32 {
+    if n <= 0.0 { 0.0 } else { 95.52 / (8128.0 / n + 100.0) }
+}
+
+fn tnd_out(n: f32) -> f32 {
+    if n <= 0.0 { 0.0 } else { 163.67 / (24329.0 / n + 100.0) }
+}
@@ -0,0 +1,221 @@
+// Band-limited resampling, in the style of blargg's blip_buf:
+// Instead of producing a sample for every input clock, only changes in amplitude are recorded, as a band-limited
+// (windowed sinc) impulse placed at the exact fractional output sample position. Integrating the buffer then gives
+// the band-limited output signal.
+
+use std::f64::consts::PI;
+
+/// Half the width of the kernel (in output samples).
+const KERNEL_HALF_WIDTH: usize = 8;
+const KERNEL_WIDTH: usize = KERNEL_HALF_WIDTH * 2;
+/// Number of fractional positions the kernel is precomputed for.
+const KERNEL_PHASES: usize = 64;
+/// Cutoff frequency, relative to the output sample rate.
+const CUTOFF: f64 = 0.45;
+
+/// Cutoff frequency of the DC-removing high-pass filter, in Hz (close to the Famicom's ~37 Hz).
+const HIGH_PASS_CUTOFF: f64 = 37.0;
+
+/// Maximum number of buffered output samples (in seconds); anything older is dropped.
+const MAX_BUFFERED_SECONDS: usize = 1;
+/// Number of output samples after which they're flushed, even if nobody takes them (to keep `buf` and `pos` small.)
+const FLUSH_SAMPLES: usize = 1024;
+
+pub struct Resampler {
+    clock_rate: f64,
+    sample_rate: u32,
+    samples_per_clock: f64,
+    kernel: Vec<[f32; KERNEL_WIDTH]>,
+    /// Output sample position of the current clock, relative to the start of `buf`.
+    pos: f64,
+    /// Amplitude deltas, spread out by the kernel.
+    buf: Vec<f32>,
+    last_amp: f32,
+    integrator: f32,
+    high_pass_factor: f32,
+    high_pass_prev_in: f32,
+    high_pass_prev_out: f32,
+    samples: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Resampler {
+        let mut resampler = Resampler {
+            clock_rate,
+            sample_rate,
+            samples_per_clock: 0.0,
+            kernel: generate_kernel(),
+            pos: 0.0,
+            buf: vec![0.0; KERNEL_WIDTH * 2],
+            last_amp: 0.0,
+            integrator: 0.0,
+            high_pass_factor: 0.0,
+            high_pass_prev_in: 0.0,
+            high_pass_prev_out: 0.0,
+            samples: Vec::new(),
+        };
+        resampler.set_rates(clock_rate, sample_rate);
+        resampler
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Change the input clock rate and/or the output sample rate.
+    pub fn set_rates(&mut self, clock_rate: f64, sample_rate: u32) {
+        self.clock_rate = clock_rate;
+        self.sample_rate = sample_rate;
+        self.samples_per_clock = sample_rate as f64 / clock_rate;
+
+        let rc = 1.0 / (2.0 * PI * HIGH_PASS_CUTOFF);
+        let dt = 1.0 / sample_rate as f64;
+        self.high_pass_factor = (rc / (rc + dt)) as f32;
+    }
+
+    /// Advance the input by one clock, with the amplitude `amp` for that clock.
+    #[inline]
+    pub fn push(&mut self, amp: f32) {
+        if amp != self.last_amp {
+            self.add_delta(amp - self.last_amp);
+            self.last_amp = amp;
+        }
+
+        self.pos += self.samples_per_clock;
+        if self.pos >= FLUSH_SAMPLES as f64 {
+            self.flush();
+        }
+    }
+
+    fn add_delta(&mut self, delta: f32) {
+        let idx = self.pos as usize;
+        let phase = ((self.pos - idx as f64) * KERNEL_PHASES as f64) as usize;
+
+        if self.buf.len() < idx + KERNEL_WIDTH {
+            self.buf.resize(idx + KERNEL_WIDTH * 2, 0.0);
+        }
+
+        for (b, k) in self.buf[idx..idx + KERNEL_WIDTH].iter_mut().zip(self.kernel[phase].iter()) {
+            *b += delta * k;
+        }
+    }
+
+    /// Move every finished sample into the output buffer.
+    fn flush(&mut self) {
+        let count = self.pos as usize;
+        if count == 0 {
+            return;
+        }
+
+        if self.buf.len() < count + KERNEL_WIDTH {
+            self.buf.resize(count + KERNEL_WIDTH, 0.0);
+        }
+
+        for &delta in &self.buf[..count] {
+            self.integrator += delta;
+
+            let out = self.high_pass_factor * (self.high_pass_prev_out + self.integrator - self.high_pass_prev_in);
+            self.high_pass_prev_in = self.integrator;
+            self.high_pass_prev_out = out;
+
+            self.samples.push(out.clamp(-1.0, 1.0));
+        }
+
+        self.buf.drain(..count);
+        self.buf.resize(self.buf.len().max(KERNEL_WIDTH * 2), 0.0);
+        self.pos -= count as f64;
+
+        let max_len = self.sample_rate as usize * MAX_BUFFERED_SECONDS;
+        if self.samples.len() > max_len {
+            let excess = self.samples.len() - max_len;
+            self.samples.drain(..excess);
+        }
+    }
+
+    /// Take every finished output sample.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.flush();
+        std::mem::take(&mut self.samples)
+    }
+}
+
+/// Generate the (windowed sinc) low-pass impulse response for every phase.
+///
+/// Each phase is offset by `KERNEL_HALF_WIDTH` samples, which makes the output lag behind by that much.
+fn generate_kernel() -> Vec<[f32; KERNEL_WIDTH]> {
+    let mut kernel = vec![[0.0; KERNEL_WIDTH]; KERNEL_PHASES];
+
+    for (phase, taps) in kernel.iter_mut().enumerate() {
+        let frac = phase as f64 / KERNEL_PHASES as f64;
+
+        let mut sum = 0.0;
+        let mut vals = [0.0f64; KERNEL_WIDTH];
+        for (i, val) in vals.iter_mut().enumerate() {
+            let x = i as f64 - KERNEL_HALF_WIDTH as f64 - frac;
+
+            let sinc = if x == 0.0 {
+                2.0 * CUTOFF
+            } else {
+                (2.0 * PI * CUTOFF * x).sin() / (PI * x)
+            };
+
+            // Blackman window
+            let w = (x + KERNEL_HALF_WIDTH as f64) / KERNEL_WIDTH as f64;
+            let window = if (0.0..=1.0).contains(&w) {
+                0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos()
+            } else {
+                0.0
+            };
+
+            *val = sinc * window;
+            sum += *val;
+        }
+
+        // Normalize, so a step always ends up with exactly the same height
+        for (tap, val) in taps.iter_mut().zip(vals.iter()) {
+            *tap = (val / sum) as f32;
+        }
+    }
+
+    kernel
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sample_count_test() {
+        let mut r = Resampler::new(1_789_773.0, 48_000);
+        for _ in 0..1_789_773 {
+            r.push(0.0);
+        }
+        let samples = r.take_samples();
+        assert!((samples.len() as i64 - 48_000).abs() <= 1);
+    }
+
+    #[test]
+    fn buffer_bounded_test() {
+        // Three seconds without anyone taking the samples
+        let mut r = Resampler::new(1_000_000.0, 50_000);
+        for i in 0..3_000_000 {
+            r.push(if i % 100 < 50 { 0.5 } else { 0.0 });
+        }
+        assert!(r.pos < FLUSH_SAMPLES as f64);
+        assert!(r.buf.len() <= FLUSH_SAMPLES + KERNEL_WIDTH * 2);
+        assert_eq!(r.samples.len(), 50_000);
+    }
+
+    #[test]
+    fn step_settles_test() {
+        let mut r = Resampler::new(1_000_000.0, 50_000);
+        for _ in 0..1000 {
+            r.push(0.5);
+        }
+        let samples = r.take_samples();
+
+        // After the step, the output should jump up and then slowly decay through the high-pass filter
+        let peak = samples.iter().cloned().fold(f32::MIN, f32::max);
+        assert!(peak > 0.45 && peak < 0.55, "peak: {peak}");
+    }
+}
@@ -0,0 +1,254 @@
+use std::io::Error;
+
+use log::info;
+
+use crate::fc::apu::APU;
+use crate::fc::clock::MasterClock;
+use crate::fc::mem::{MemMap, Memory};
+use crate::fc::mem::mapper::Mapper;
+use crate::fc::ppu::PPU;
+use crate::fc::state::{Snapshot, StateReader, StateWriter};
+
+/// The system bus, connecting the CPU to the rest of the console.
+///
+/// The bus owns every component the CPU talks to, and decodes the CPU address space to them:
+///
+/// | Address         | Component                         |
+/// |-----------------|-----------------------------------|
+/// | `$0000-$1fff`   | Internal RAM ([MemMap])           |
+/// | `$2000-$3fff`   | PPU registers (mirrored every 8)  |
+/// | `$4000-$4013`   | APU registers                     |
+/// | `$4014`         | OAM DMA                           |
+/// | `$4015`         | APU status                        |
+/// | `$4016-$4017`   | Controllers / APU frame counter   |
+/// | `$4018-$401f`   | APU test mode (unused)            |
+/// | `$4020-$ffff`   | Cartridge ([MemMap])              |
+///
+/// The PPU address space (pattern tables, nametables and palettes) is decoded by the PPU and the cartridge mapper.
+///
+/// The bus is also responsible for keeping the other components in sync with the CPU, see [Bus::cycle].
+pub struct Bus {
+    pub mem: MemMap,
+    pub ppu: PPU,
+    pub apu: APU,
+    clock: MasterClock,
+    /// The last value on the CPU data bus, returned by open bus reads.
+    data_bus: u8,
+    /// Set by writes to $4014, the CPU performs the OAM DMA before its next instruction.
+    oam_dma_request: bool,
+}
+
+impl Bus {
+    pub fn new(mem: MemMap, clock: MasterClock) -> Bus {
+        let region = clock.region();
+        let mut apu = APU::new(region);
+        apu.set_expansion_chip(mem.mapper.expansion_audio_chip());
+
+        Bus {
+            mem,
+            ppu: PPU::new(region),
+            apu,
+            clock,
+            data_bus: 0x00, // ?
+            oam_dma_request: false,
+        }
+    }
+
+    pub fn print_state(&self) {
+        println!(
+            "MASTER CLOCK: {} ({}, alignment: {})",
+            self.clock.cycles(), self.clock.region(), self.clock.alignment()
+        );
+        self.ppu.print_state();
+        self.apu.print_state();
+        self.mem.print_state();
+    }
+
+    pub fn clock(&self) -> &MasterClock {
+        &self.clock
+    }
+
+    /// Run every other component for the duration of one CPU cycle.
+    ///
+    /// `cpu_cycles` is the number of CPU cycles elapsed, including this one.
+    pub fn cycle(&mut self, cpu_cycles: u64) {
+        for _ in 0..self.clock.advance_cpu_cycle() {
+            self.ppu.cycle(&mut self.mem, cpu_cycles as usize);
+        }
+        if self.apu.has_expansion_audio() {
+            self.mem.mapper.clock_expansion_audio();
+            self.apu.set_expansion_output(self.mem.mapper.expansion_audio_output());
+        }
+        self.apu.cycle();
+    }
+
+    /// Read from the CPU address space.
+    pub fn read(&mut self, addr: u16) -> u8 {
+        let val = match addr {
+            0x2000..=0x3fff => self.ppu.read_mmio((addr & 0x7) + 0x2000, &mut self.mem),
+            0x4000..=0x4014 => {
+                info!("Open bus read at ${addr:04x}");
+                self.data_bus
+            },
+            0x4015          => self.apu.read_addr(addr),
+            0x4018..=0x401f => {
+                // TODO: open bus read? (APU test mode & unused IRQ timer)
+                info!("Open bus read at ${addr:04x}");
+                self.data_bus
+            }
+            _ => self.mem.read(addr),
+        };
+
+        // TODO? check if this is proper handling of open bus read at 0x4015?
+        if addr != 0x4015 {
+            self.set_open_bus(val);
+        }
+        val
+    }
+
+    /// Read from the CPU address space, without affecting the state of any component (for debugging.)
+    pub fn read_no_sideeffect(&self, addr: u16) -> u8 {
+        match addr {
+            0x2000..=0x3fff => self.ppu.read_mmio_no_sideeffect((addr & 0x7) + 0x2000),
+            0x4000..=0x4014 => self.data_bus, // Open bus read
+            0x4015          => self.apu.read_addr_no_sideeffect(addr),
+            0x4018..=0x401f => self.data_bus, // TODO: open bus read? (APU test mode & unused IRQ timer)
+            _ => self.mem.read_no_sideeffect(addr),
+        }
+    }
+
+    /// Write to the CPU address space.
+    pub fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x2000..=0x3fff => {
+                self.mem.mapper.ppu_register_written((addr & 0x7) + 0x2000, val);
+                self.ppu.write_mmio((addr & 0x7) + 0x2000, val, &mut self.mem)
+            }
+            0x4014 => {
+                self.ppu.write_oamdma(val);
+                self.oam_dma_request = true;
+            }
+            0x4000..=0x4015 => self.apu.write_addr(addr, val),
+            0x4017          => self.apu.write_addr(addr, val),
+            0x4018..=0x401f => (), // APU test mode & unused IRQ timer
+            _ => self.mem.write(addr, val),
+        };
+
+        self.data_bus = val;
+    }
+
+    /// Set the value on the data bus (as seen by open bus reads.)
+    pub fn set_open_bus(&mut self, val: u8) {
+        self.data_bus = val;
+        self.apu.set_open_bus(val);
+        self.mem.set_open_bus(val);
+    }
+
+    /// Read from the PPU address space.
+    pub fn read_ppu(&mut self, addr: u16) -> u8 {
+        self.ppu.read_addr(addr, &mut self.mem)
+    }
+
+    /// Read from the PPU address space, without affecting the state of any component (for debugging.)
+    pub fn read_ppu_no_sideeffect(&self, addr: u16) -> u8 {
+        self.ppu.read_addr_no_sideeffect(addr, &self.mem)
+    }
+
+    /// Take the pending OAM DMA request, returning the page to copy from (if any.)
+    pub fn take_oam_dma_request(&mut self) -> Option<u8> {
+        if self.oam_dma_request {
+            self.oam_dma_request = false;
+            Some(self.ppu.oamdma())
+        } else {
+            None
+        }
+    }
+
+    /// Write a byte copied by OAM DMA to OAM.
+    pub fn write_oam_dma(&mut self, dst: u8, val: u8) {
+        self.ppu.write_oam(dst, val);
+    }
+
+    /// The address of the sample byte the DMC wants to fetch (if any.)
+    pub fn dmc_dma_request(&mut self) -> Option<u16> {
+        self.apu.dmc_dma_request()
+    }
+
+    pub fn dmc_dma_complete(&mut self, val: u8) {
+        self.apu.dmc_dma_complete(val);
+    }
+
+    /// Whether the current APU cycle is a "put" cycle (DMA reads can only happen on "get" cycles.)
+    pub fn is_put_cycle(&self) -> bool {
+        self.apu.is_put_cycle()
+    }
+
+    /// The state of the NMI line (vblank with NMIs enabled.)
+    pub fn nmi_line(&self) -> bool {
+        self.ppu.nmi_enable() && self.ppu.is_vblank()
+    }
+
+    pub fn is_vblank(&self) -> bool {
+        self.ppu.is_vblank()
+    }
+
+    /// The state of the IRQ line (any of the APU or cartridge IRQ sources.)
+    pub fn irq_line(&mut self) -> bool {
+        self.mem.irq_triggered(&self.apu)
+    }
+
+    /// Acknowledge the IRQ, after the CPU has started handling it.
+    pub fn acknowledge_irq(&mut self) {
+        self.mem.irq_un_trigger();
+    }
+
+    pub fn just_finished_rendering(&self) -> bool {
+        self.ppu.just_finished_rendering()
+    }
+}
+
+impl Snapshot for Bus {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.clock.save_state(w);
+        w.write(&self.data_bus);
+        w.write(&self.oam_dma_request);
+        self.mem.save_state(w);
+        self.ppu.save_state(w);
+        self.apu.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.clock.load_state(r)?;
+        self.data_bus = r.read()?;
+        self.oam_dma_request = r.read()?;
+        self.mem.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.apu.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fc::region::Region;
+
+    #[test]
+    fn cpu_address_decoding_test() {
+        let mut bus = Bus::new(MemMap::empty(), MasterClock::new(Region::NTSC, 0));
+
+        // Internal RAM is mirrored every $800 bytes
+        bus.write(0x0012, 0x34);
+        assert_eq!(bus.read(0x0812), 0x34);
+        assert_eq!(bus.read_no_sideeffect(0x1812), 0x34);
+
+        // Unmapped APU registers return the last value on the data bus
+        bus.write(0x0000, 0x56);
+        assert_eq!(bus.read(0x4000), 0x56);
+
+        // $4014 requests an OAM DMA from the written page
+        assert_eq!(bus.take_oam_dma_request(), None);
+        bus.write(0x4014, 0x02);
+        assert_eq!(bus.take_oam_dma_request(), Some(0x02));
+        assert_eq!(bus.take_oam_dma_request(), None);
+    }
+}
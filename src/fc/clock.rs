@@ -0,0 +1,107 @@
+use std::io::Error;
+
+use super::region::Region;
+use super::state::{Snapshot, StateReader, StateWriter};
+
+/// The master clock, which all other clocks (CPU, PPU, APU and the mapper) are derived from.
+///
+/// The CPU drives the emulation one CPU cycle at a time (as every cycle is a memory access), so the master clock is
+/// advanced by one CPU cycle's worth of master clock cycles at a time, and the other components are caught up to it.
+///
+/// See: https://www.nesdev.org/wiki/Cycle_reference_chart#Clock_rates
+pub struct MasterClock {
+    region: Region,
+    /// Master clock cycles elapsed since power-on.
+    cycles: u64,
+    /// PPU cycles run since power-on.
+    ppu_cycles: u64,
+    /// Offset (in master clock cycles) of the PPU clock, relative to the CPU clock.
+    alignment: u32,
+}
+
+impl MasterClock {
+    /// Create a new master clock, using the given CPU/PPU `alignment`.
+    ///
+    /// Real consoles power on in one of several alignments (four for NTSC), which change the master clock cycle
+    /// the PPU is clocked at relative to the CPU. This affects e.g. whether a PPU register write lands before or after
+    /// a specific dot. The alignment is wrapped to `0..region.ppu_divider()`.
+    pub fn new(region: Region, alignment: u32) -> MasterClock {
+        MasterClock {
+            region,
+            cycles: 0,
+            ppu_cycles: 0,
+            alignment: alignment % region.ppu_divider(),
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn alignment(&self) -> u32 {
+        self.alignment
+    }
+
+    /// Master clock cycles elapsed since power-on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Advance the master clock by one CPU cycle.
+    ///
+    /// Returns the number of PPU cycles that have to be run to catch up to the CPU (the APU and mapper are always
+    /// clocked once per CPU cycle, so they don't have to be scheduled.)
+    pub fn advance_cpu_cycle(&mut self) -> u32 {
+        self.cycles += self.region.cpu_divider() as u64;
+
+        // PPU cycle `n` happens at master clock cycle `n * ppu_divider + alignment`
+        let ppu_target = self.cycles.saturating_sub(self.alignment as u64) / self.region.ppu_divider() as u64;
+        let ppu_cycles = ppu_target - self.ppu_cycles;
+        self.ppu_cycles = ppu_target;
+
+        ppu_cycles as u32
+    }
+}
+
+impl Snapshot for MasterClock {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.cycles);
+        w.write(&self.ppu_cycles);
+        w.write(&self.alignment);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.cycles = r.read()?;
+        self.ppu_cycles = r.read()?;
+        self.alignment = r.read::<u32>()? % self.region.ppu_divider();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ntsc_alignment_test() {
+        for alignment in 0..4 {
+            let mut clock = MasterClock::new(Region::NTSC, alignment);
+            let first = clock.advance_cpu_cycle();
+            assert_eq!(first, if alignment == 0 { 3 } else { 2 });
+
+            // Always 3 PPU cycles per CPU cycle afterwards
+            for _ in 0..100 {
+                assert_eq!(clock.advance_cpu_cycle(), 3);
+            }
+        }
+    }
+
+    #[test]
+    fn pal_ratio_test() {
+        let mut clock = MasterClock::new(Region::PAL, 0);
+        let cycles: Vec<u32> = (0..5).map(|_| clock.advance_cpu_cycle()).collect();
+
+        // 3.2 PPU cycles per CPU cycle
+        assert_eq!(cycles, [3, 3, 3, 3, 4]);
+    }
+}
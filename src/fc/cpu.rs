@@ -1,17 +1,15 @@
 use inst::*;
 use log::*;
 
+use std::io::Error;
+
 use crate::bits::{Addr, Bitwise, as_address};
-use crate::fc::apu::APU;
-use crate::fc::mem::*;
 
-use super::PPU;
+use super::bus::Bus;
+use super::state::{Snapshot, StateReader, StateWriter};
 
 pub mod inst;
 
-pub const MASTER_FREQ: f64 = 21_477_272.727272727;
-pub const CPU_FREQ: f64 = MASTER_FREQ / 12.0;
-
 const NMI_VECTOR: u16 = 0xfffa;
 const RESET_VECTOR: u16 = 0xfffc;
 const IRQ_VECTOR: u16 = 0xfffe;
@@ -65,7 +63,6 @@ pub struct Registers {
     pc: u16,      // Program counter
     irq: bool,    // Interrupt request flag
     nmi: bool,    // Non-maskable interrupt flag
-    dma: bool,    // OAM DMA 'flag'
 }
 
 impl Registers {
@@ -79,35 +76,30 @@ impl Registers {
             pc: RESET_VECTOR,
             irq: false,
             nmi: false,
-            dma: false,
         }
     }
 }
 
+/// The 6502 CPU core (2A03/2A07).
+///
+/// The CPU only holds its own state; everything it accesses goes through the [Bus], which is passed to every
+/// method that needs it (which is any method that takes cycles.)
 pub struct CPU {
     reg: Registers,
-    pub mem: MemMap,
-    pub ppu: PPU,
-    pub apu: APU,
     cycles: u64,
     pub halted: bool,
-    data_bus: u8,
 }
 
 impl CPU {
-    pub fn new(mem: MemMap) -> CPU {
+    pub fn new() -> CPU {
         CPU {
             reg: Registers::new(),
-            mem,
-            ppu: PPU::new(),
-            apu: APU::new(),
             cycles: 0,
             halted: false,
-            data_bus: 0x00, // ?
         }
     }
 
-    pub fn print_state(&self) -> () {
+    pub fn print_state(&self, bus: &Bus) -> () {
         println!("CPU STATE:");
         println!("  a: {:02x}, x: {:02x}, y: {:02x}", self.reg.a, self.reg.x, self.reg.y);
         let p: u8 = self.reg.p.into();
@@ -117,12 +109,11 @@ impl CPU {
             "  cycles: {}, nmi: {}, irq: {}",
             self.cycles, self.reg.nmi, self.reg.irq
         );
-        self.ppu.print_state();
-        self.mem.print_state();
+        bus.print_state();
 
-        let inst = self.fetch_next_inst_nocycle();
-        let operand_u8 = self.mem_read_no_sideeffect(self.reg.pc + 1);
-        let operand_u16 = as_address(operand_u8, self.mem_read_no_sideeffect(self.reg.pc + 2));
+        let inst = self.fetch_next_inst_nocycle(bus);
+        let operand_u8 = bus.read_no_sideeffect(self.reg.pc + 1);
+        let operand_u16 = as_address(operand_u8, bus.read_no_sideeffect(self.reg.pc + 2));
         let operand_rel = (self.reg.pc + 2).wrapping_add_signed((operand_u8 as i8).into());
 
         println!(
@@ -143,57 +134,55 @@ impl CPU {
         self.cycles
     }
 
-    pub fn init(&mut self) -> () {
-        self.ppu.init();
-        let l = self.read_addr_nocycle(RESET_VECTOR);
-        let m = self.read_addr_nocycle(RESET_VECTOR + 1);
+    pub fn init(&mut self, bus: &mut Bus) -> () {
+        let l = self.read_addr_nocycle(bus, RESET_VECTOR);
+        let m = self.read_addr_nocycle(bus, RESET_VECTOR + 1);
         let addr = as_address(l, m);
         self.reg.pc = addr;
         // This is purely based on the value of the Mesen debugger after RESET
         self.cycles = 7;
 
         self.halted = false;
-        self.data_bus = m;  // ?
+        bus.set_open_bus(m);  // ?
     }
 
-    pub fn reset(&mut self) -> () {
-        self.ppu.reset();
+    pub fn reset(&mut self, bus: &mut Bus) -> () {
         self.reg.pc = RESET_VECTOR;
         self.reg.sp -= 3;
         self.reg.p.i = true;
 
-        self.init();
+        self.init(bus);
     }
 
-    fn handle_nmi(&mut self) -> () {
+    fn handle_nmi(&mut self, bus: &mut Bus) -> () {
         debug!("NMI");
 
-        self.push(self.reg.pc.msb()); // +2 cycles
-        self.push(self.reg.pc.lsb()); // +2 cycles
+        self.push(bus, self.reg.pc.msb()); // +2 cycles
+        self.push(bus, self.reg.pc.lsb()); // +2 cycles
 
-        self.push(self.reg.p.into()); // +2 cycles
+        self.push(bus, self.reg.p.into()); // +2 cycles
 
-        let l = self.read_addr_nocycle(NMI_VECTOR);
-        let m = self.read_addr_nocycle(NMI_VECTOR + 1);
+        let l = self.read_addr_nocycle(bus, NMI_VECTOR);
+        let m = self.read_addr_nocycle(bus, NMI_VECTOR + 1);
         let addr = as_address(l, m);
-        self.cycle(); // +1 cycle
+        self.cycle(bus); // +1 cycle
 
         self.reg.pc = addr;
         self.reg.nmi = true;
     }
 
-    fn handle_irq(&mut self) -> () {
+    fn handle_irq(&mut self, bus: &mut Bus) -> () {
         debug!("IRQ");
 
-        self.push(self.reg.pc.msb()); // +2 cycles
-        self.push(self.reg.pc.lsb()); // +2 cycles
+        self.push(bus, self.reg.pc.msb()); // +2 cycles
+        self.push(bus, self.reg.pc.lsb()); // +2 cycles
 
-        self.push(self.reg.p.into()); // +2 cycles
+        self.push(bus, self.reg.p.into()); // +2 cycles
 
-        let l = self.read_addr_nocycle(IRQ_VECTOR);
-        let m = self.read_addr_nocycle(IRQ_VECTOR + 1);
+        let l = self.read_addr_nocycle(bus, IRQ_VECTOR);
+        let m = self.read_addr_nocycle(bus, IRQ_VECTOR + 1);
         let addr = as_address(l, m);
-        self.cycle(); // +1 cycle
+        self.cycle(bus); // +1 cycle
 
         // Disable interrupts
         self.reg.p.i = true;
@@ -202,129 +191,88 @@ impl CPU {
         self.reg.irq = false;
     }
 
-    fn handle_oam_dma(&mut self) {
+    fn handle_oam_dma(&mut self, bus: &mut Bus, src_msb: u8) {
         debug!("OAM DMA");
 
-        let src_msb = self.ppu.oamdma();
         for dst in 0..=255u8 {
             // ?Internally (or, at least in mesen,) OAMADDR is incremented for each read
             let src_addr = as_address(dst, src_msb);
 
-            let val = self.read_addr_cycle(src_addr); // +1 cycle
+            let val = self.read_addr_cycle(bus, src_addr); // +1 cycle
 
-            self.ppu.write_oam(dst, val);
-            self.cycle(); // +1 cycle
+            bus.write_oam_dma(dst, val);
+            self.cycle(bus); // +1 cycle
         }
         // "The copy takes 513 or 514 cycles"
-        self.cycle(); // +1 cycle
-
-        self.reg.dma = false;
+        self.cycle(bus); // +1 cycle
     }
 
     /// "Cycle" the cpu.
     ///
     /// Cycles: `1`
-    pub fn cycle(&mut self) -> () {
+    pub fn cycle(&mut self, bus: &mut Bus) -> () {
         self.cycles += 1;
-        self.ppu.cycle(&mut self.mem, self.cycles as usize);
-        self.apu.cycle();
-    }
-
-    fn mem_read(&mut self, addr: u16) -> u8 {
-        let val = match addr {
-            0x2000..=0x3fff => self.ppu.read_mmio((addr & 0x7) + 0x2000, &mut self.mem),
-            0x4000..=0x4014 => {
-                info!("Open bus read at ${addr:04x}");
-                self.data_bus
-            },
-            0x4015          => self.apu.read_addr(addr),
-            0x4018..=0x401f => {
-                // TODO: open bus read? (APU test mode & unused IRQ timer)
-                info!("Open bus read at ${addr:04x}");
-                self.data_bus
-            }
-            _ => self.mem.read(addr),
-        };
+        bus.cycle(self.cycles);
 
-        // TODO? check if this is proper handling of open bus read at 0x4015?
-        if addr != 0x4015 {
-            self.data_bus = val;
-            self.apu.set_open_bus(val);
-            self.mem.set_open_bus(val);
+        if let Some(addr) = bus.dmc_dma_request() {
+            self.handle_dmc_dma(bus, addr);
         }
-        val
     }
 
-    fn mem_read_no_sideeffect(&self, addr: u16) -> u8 {
-        match addr {
-            0x2000..=0x3fff => self.ppu.read_mmio_no_sideeffect((addr & 0x7) + 0x2000),
-            0x4000..=0x4014 => self.data_bus, // Open bus read
-            0x4015          => self.apu.read_addr_no_sideeffect(addr),
-            0x4018..=0x401f => self.data_bus, // TODO: open bus read? (APU test mode & unused IRQ timer)
-            _ => self.mem.read_no_sideeffect(addr),
-        }
-    }
+    /// Fetch a sample byte for the APU's DMC, stalling the CPU.
+    ///
+    /// Cycles: `3` or `4`
+    fn handle_dmc_dma(&mut self, bus: &mut Bus, addr: u16) {
+        debug!("DMC DMA (${addr:04x})");
 
-    fn mem_write(&mut self, addr: u16, val: u8) -> () {
-        match addr {
-            0x2000..=0x3fff => self.ppu.write_mmio((addr & 0x7) + 0x2000, val, &mut self.mem),
-            0x4014 => {
-                self.ppu.write_oamdma(val);
-                self.reg.dma = true
-            }
-            0x4000..=0x4015 => self.apu.write_addr(addr, val),
-            0x4017          => self.apu.write_addr(addr, val),
-            0x4018..=0x401f => (), // APU test mode & unused IRQ timer
-            _ => self.mem.write(addr, val),
-        };
+        self.cycle(bus); // +1 cycle (halt)
+        self.cycle(bus); // +1 cycle (dummy)
+
+        // The read has to happen on a "get" cycle
+        if !bus.is_put_cycle() {
+            self.cycle(bus); // +1 cycle (alignment)
+        }
 
-        self.data_bus = val;
+        let val = self.read_addr_cycle(bus, addr); // +1 cycle
+        bus.dmc_dma_complete(val);
     }
 
     /// Read the value at the address `addr` without any cycles (including in the PPU).
     ///
     /// Cycles: `0`
-    pub fn read_addr_nocycle(&self, addr: u16) -> u8 {
-        self.mem_read_no_sideeffect(addr)
-    }
-
-    pub fn read_addr_ppu(&mut self, addr: u16) -> u8 {
-        self.ppu.read_addr(addr, &mut self.mem)
-    }
-
-    pub fn read_addr_ppu_no_sideeffect(&self, addr: u16) -> u8 {
-        self.ppu.read_addr_no_sideeffect(addr, &self.mem)
+    pub fn read_addr_nocycle(&self, bus: &Bus, addr: u16) -> u8 {
+        bus.read_no_sideeffect(addr)
     }
 
     /// Read the value at the address `addr`
     ///
     /// Cycles: `1`
-    pub fn read_addr_cycle(&mut self, addr: u16) -> u8 {
-        self.cycle();
-        self.mem_read(addr)
+    pub fn read_addr_cycle(&mut self, bus: &mut Bus, addr: u16) -> u8 {
+        self.cycle(bus);
+        bus.read(addr)
     }
 
     /// Write the value `val` to the address `addr`
     ///
     /// Cycles: `1`
-    pub fn write_addr_cycle(&mut self, addr: u16, val: u8) -> () {
-        self.cycle();
-        self.mem_write(addr, val);
+    pub fn write_addr_cycle(&mut self, bus: &mut Bus, addr: u16, val: u8) -> () {
+        self.cycle(bus);
+        bus.write(addr, val);
     }
 
     /// Read the value pointe to by the pc without any cycles (including in the PPU).
     ///
     /// Cycles: `0`
-    pub fn pc_read_nocycle(&self) -> u8 {
-        self.mem_read_no_sideeffect(self.reg.pc)
+    pub fn pc_read_nocycle(&self, bus: &Bus) -> u8 {
+        bus.read_no_sideeffect(self.reg.pc)
     }
 
     /// Read the value pointe to by the pc.
     ///
     /// Cycles: `1`
-    pub fn pc_read(&mut self) -> u8 {
-        self.cycle();
-        self.mem_read(self.reg.pc)
+    pub fn pc_read(&mut self, bus: &mut Bus) -> u8 {
+        self.cycle(bus);
+        bus.read(self.reg.pc)
     }
 
     /// Increment the pc.
@@ -337,8 +285,8 @@ impl CPU {
     /// Read the value pointed to by the pc, then increment the pc.
     ///
     /// Cycles: `1`
-    pub fn pc_read_inc(&mut self) -> u8 {
-        let val = self.pc_read(); // +1 cycle
+    pub fn pc_read_inc(&mut self, bus: &mut Bus) -> u8 {
+        let val = self.pc_read(bus); // +1 cycle
         self.pc_inc();
         val
     }
@@ -346,84 +294,84 @@ impl CPU {
     /// Add an offset to the value of the pc
     ///
     /// Cycles: `1` (`+1` if to a new page)
-    fn pc_offset_cycle(&mut self, offset: i8) -> () {
+    fn pc_offset_cycle(&mut self, bus: &mut Bus, offset: i8) -> () {
         let old_pc = self.reg.pc;
         let new_pc = old_pc.wrapping_add_signed(offset.into());
         if new_pc.msb() != old_pc.msb() {
-            self.cycle(); // +1 cycle
+            self.cycle(bus); // +1 cycle
         }
         self.reg.pc = new_pc;
-        self.cycle(); // +1 cycle
+        self.cycle(bus); // +1 cycle
     }
 
     /// Read the value at address $00(pc+x/y).
     ///
     /// Cycles: `2` (if page crossed)
-    pub fn zp_read_cycle(&mut self, ir: IndexRegister) -> u8 {
-        let operand = self.pc_read(); // +1 cycle
+    pub fn zp_read_cycle(&mut self, bus: &mut Bus, ir: IndexRegister) -> u8 {
+        let operand = self.pc_read(bus); // +1 cycle
         let delta = match ir {
             IndexRegister::N => 0,
             IndexRegister::X => self.reg.x, // Note: does NOT add +1 cycle
             IndexRegister::Y => self.reg.y, // Note: does NOT add +1 cycle
         };
         // if operand as u16 + delta as u16 > 0xff {
-        //     self.cycle();
+        //     self.cycle(bus);
         // }
-        self.read_addr_cycle(as_address(operand + delta, 0x00)) // +1 cycle
+        self.read_addr_cycle(bus, as_address(operand + delta, 0x00)) // +1 cycle
     }
 
     /// Read the value at address $00(pc+x/y), and increment the pc.
     ///
     /// Cycles: `2` (`+1` if x/y)
-    pub fn zp_read_inc(&mut self, ir: IndexRegister) -> u8 {
-        let operand = self.pc_read_inc(); // +1 cycle
+    pub fn zp_read_inc(&mut self, bus: &mut Bus, ir: IndexRegister) -> u8 {
+        let operand = self.pc_read_inc(bus); // +1 cycle
         let delta = match ir {
             IndexRegister::N => 0,
             IndexRegister::X => {
-                self.cycle();
+                self.cycle(bus);
                 self.reg.x
             } // +1 cycle
             IndexRegister::Y => {
-                self.cycle();
+                self.cycle(bus);
                 self.reg.y
             } // +1 cycle
         };
         // if operand as u16 + delta as u16 > 0xff {
-        // self.cycle();
+        // self.cycle(bus);
         // }
-        self.read_addr_cycle(as_address(operand + delta, 0x00)) // +1 cycle
+        self.read_addr_cycle(bus, as_address(operand + delta, 0x00)) // +1 cycle
     }
 
     /// Write the value `val` to the address $00(pc+x/y), and increment the pc.
     ///
     /// Cycles: `2` (`+1` if x/y)
-    pub fn zp_write_inc(&mut self, val: u8, ir: IndexRegister) -> () {
-        let operand = self.pc_read_inc(); // +1 cycle
+    pub fn zp_write_inc(&mut self, bus: &mut Bus, val: u8, ir: IndexRegister) -> () {
+        let operand = self.pc_read_inc(bus); // +1 cycle
         let delta = match ir {
             IndexRegister::N => 0,
             IndexRegister::X => {
-                self.cycle();
+                self.cycle(bus);
                 self.reg.x
             } // always +1 cycle
             IndexRegister::Y => {
-                self.cycle();
+                self.cycle(bus);
                 self.reg.y
             } // always +1 cycle
         };
         let addr = as_address(operand + delta, 0x00);
-        self.write_addr_cycle(addr, val); // +1 cycle
+        self.write_addr_cycle(bus, addr, val); // +1 cycle
     }
 
     /// Read the value at address $(pc)<<8|(pc+1) +x/y
     ///
     /// Cycles: `2`
-    pub fn abs_read_cycle(&mut self, ir: IndexRegister) -> u8 {
-        let l = self.pc_read(); // +1 cycle
+    pub fn abs_read_cycle(&mut self, bus: &mut Bus, ir: IndexRegister) -> u8 {
+        let l = self.pc_read(bus); // +1 cycle
 
-        // "m: u8 = self.pc_read(pc+1);"
+        // "m: u8 = self.pc_read(bus, pc+1);"
         let m = {
-            self.cycle();
-            self.mem_read(self.reg.pc + 1)
+            self.cycle(bus);
+            bus.read(self.reg.pc + 1)
         }; // +1 cycle
 
         let addr = as_address(l, m);
@@ -433,18 +381,18 @@ impl CPU {
             IndexRegister::Y => self.reg.y, // Note: does NOT add +1 cycle
         } as u16;
         // if (addr & 0xff) + delta > 0xff {
-        //     self.cycle();
+        //     self.cycle(bus);
         // }
         // TODO? change this?
-        self.read_addr_nocycle(addr + delta) // +0 cycle
+        self.read_addr_nocycle(bus, addr + delta) // +0 cycle
     }
 
     /// Read the value at address $(pc)<<8|(pc+1) +x/y, and increment the pc.
     ///
     /// Cycles: `3` (`+1` if page crossed)
-    pub fn abs_read_inc(&mut self, ir: IndexRegister) -> u8 {
-        let l = self.pc_read_inc(); // +1 cycle
-        let m = self.pc_read_inc(); // +1 cycle
+    pub fn abs_read_inc(&mut self, bus: &mut Bus, ir: IndexRegister) -> u8 {
+        let l = self.pc_read_inc(bus); // +1 cycle
+        let m = self.pc_read_inc(bus); // +1 cycle
         let addr = as_address(l, m);
         let delta = match ir {
             IndexRegister::N => 0,
@@ -452,31 +400,31 @@ impl CPU {
             IndexRegister::Y => self.reg.y, // +1 cycle
         } as u16;
         if (addr & 0xff00) != (addr + delta) & 0xff00 {
-            self.cycle();
+            self.cycle(bus);
         }
-        self.read_addr_cycle(addr + delta) // +1 cycle
+        self.read_addr_cycle(bus, addr + delta) // +1 cycle
     }
 
     /// Write the value `val` to the address $(pc)<<8|(pc+1) +x/y, and increment the pc.
     ///
     /// Cycles: `3`(`+1` for x/y)
-    pub fn abs_write_inc(&mut self, val: u8, ir: IndexRegister) -> () {
-        let l = self.pc_read_inc(); // +1 cycle
-        let m = self.pc_read_inc(); // +1 cycle
+    pub fn abs_write_inc(&mut self, bus: &mut Bus, val: u8, ir: IndexRegister) -> () {
+        let l = self.pc_read_inc(bus); // +1 cycle
+        let m = self.pc_read_inc(bus); // +1 cycle
         let addr = as_address(l, m);
         let delta = match ir {
             IndexRegister::N => 0,
             IndexRegister::X => {
-                self.cycle();
+                self.cycle(bus);
                 self.reg.x
             }
             IndexRegister::Y => {
-                self.cycle();
+                self.cycle(bus);
                 self.reg.y
             }
         } as u16;
         // ?from wiki: "assumes the worst case of page crossing and always spends 1 extra read cycle"
-        self.write_addr_cycle(addr + delta, val); // +1 cycle
+        self.write_addr_cycle(bus, addr + delta, val); // +1 cycle
     }
 
     /// Read the operand in the way specified by the addressing mode, without increasing the pc
@@ -486,14 +434,14 @@ impl CPU {
     /// - Imm: `1`
     /// - Zp : `2`
     /// - Abs: `2`
-    pub fn operand_read_cycle(&mut self, am: AddrMode) -> u8 {
+    pub fn operand_read_cycle(&mut self, bus: &mut Bus, am: AddrMode) -> u8 {
         match am {
             // TODO: shouldn't it really be the write func that has the fewer cycle things?
             AddrMode::Acc => self.reg.a,
-            AddrMode::Imm => self.pc_read(),              // +1
-            AddrMode::ZP(ir) => self.zp_read_cycle(ir),   // +2
-            AddrMode::Abs(ir) => self.abs_read_cycle(ir), // +2
-            AddrMode::Ind(ir) => self.ind_read_cycle(ir),
+            AddrMode::Imm => self.pc_read(bus),              // +1
+            AddrMode::ZP(ir) => self.zp_read_cycle(bus, ir),   // +2
+            AddrMode::Abs(ir) => self.abs_read_cycle(bus, ir), // +2
+            AddrMode::Ind(ir) => self.ind_read_cycle(bus, ir),
             AddrMode::Imp => panic!("Implied does not have an operand"),
             AddrMode::Rel => panic!("Relative operand is only used in branch instructions"),
         }
@@ -507,13 +455,13 @@ impl CPU {
     /// - Zp : `2`(`+1` for x/y page crossing)
     /// - Abs: `3`(`+1` for x/y page crossing)
     /// - Ind: `5`("`-1`"" y NOT page crossing)
-    pub fn operand_read_inc(&mut self, am: AddrMode) -> u8 {
+    pub fn operand_read_inc(&mut self, bus: &mut Bus, am: AddrMode) -> u8 {
         match am {
             AddrMode::Acc => self.reg.a,
-            AddrMode::Imm => self.pc_read_inc(),        // +1
-            AddrMode::ZP(ir) => self.zp_read_inc(ir),   // +2(+1)
-            AddrMode::Abs(ir) => self.abs_read_inc(ir), // +3(+1)
-            AddrMode::Ind(ir) => self.ind_read_inc(ir), // +5(-1)
+            AddrMode::Imm => self.pc_read_inc(bus),        // +1
+            AddrMode::ZP(ir) => self.zp_read_inc(bus, ir),   // +2(+1)
+            AddrMode::Abs(ir) => self.abs_read_inc(bus, ir), // +3(+1)
+            AddrMode::Ind(ir) => self.ind_read_inc(bus, ir), // +5(-1)
             AddrMode::Imp => panic!("Implied does not have an operand"),
             AddrMode::Rel => panic!("Relative operand is only used in branch instructions"),
         }
@@ -525,11 +473,11 @@ impl CPU {
     /// - Zp : `2`(`+1` for x/y)
     /// - Abs: `3`(`+1` for x/y)
     /// - Ind: `5` for both x/y
-    pub fn operand_write_inc(&mut self, am: AddrMode, val: u8) {
+    pub fn operand_write_inc(&mut self, bus: &mut Bus, am: AddrMode, val: u8) {
         match am {
-            AddrMode::ZP(ir) => self.zp_write_inc(val, ir),   // +2(+1 x/y)
-            AddrMode::Abs(ir) => self.abs_write_inc(val, ir), // +3
-            AddrMode::Ind(ir) => self.ind_write_inc(val, ir), // +5
+            AddrMode::ZP(ir) => self.zp_write_inc(bus, val, ir),   // +2(+1 x/y)
+            AddrMode::Abs(ir) => self.abs_write_inc(bus, val, ir), // +3
+            AddrMode::Ind(ir) => self.ind_write_inc(bus, val, ir), // +5
             AddrMode::Acc => unreachable!(),
             AddrMode::Imp => unreachable!(),
             AddrMode::Imm => unreachable!(),
@@ -540,15 +488,15 @@ impl CPU {
     /// Get the indirect address ... yeah
     ///
     /// Cycles: `2`
-    pub fn get_indirect(&mut self, addr: u16) -> u16 {
+    pub fn get_indirect(&mut self, bus: &mut Bus, addr: u16) -> u16 {
         if addr & 0xff == 0x00ff {
             // Wraparound
-            let l = self.read_addr_cycle((addr & 0xff00) | 0xff); // +1 cycle
-            let m = self.read_addr_cycle((addr & 0xff00) | 0x00); // +1 cycle
+            let l = self.read_addr_cycle(bus, (addr & 0xff00) | 0xff); // +1 cycle
+            let m = self.read_addr_cycle(bus, (addr & 0xff00) | 0x00); // +1 cycle
             as_address(l, m)
         } else {
-            let l = self.read_addr_cycle(addr); // +1 cycle
-            let m = self.read_addr_cycle(addr + 1); // +1 cycle
+            let l = self.read_addr_cycle(bus, addr); // +1 cycle
+            let m = self.read_addr_cycle(bus, addr + 1); // +1 cycle
             as_address(l, m)
         }
     }
@@ -556,25 +504,25 @@ impl CPU {
     /// Read the indirect address in relation to x or y
     ///
     /// Cycles: `5` for BOTH x AND y
-    pub fn ind_read_cycle(&mut self, ir: IndexRegister) -> u8 {
-        let m = self.pc_read(); // +1 cycle
+    pub fn ind_read_cycle(&mut self, bus: &mut Bus, ir: IndexRegister) -> u8 {
+        let m = self.pc_read(bus); // +1 cycle
         match ir {
             IndexRegister::X => {
                 let ptr = as_address(m + self.reg.x, 0x00);
-                let addr = self.get_indirect(ptr); // +2 cycles
-                self.cycle(); //?? +1 cycle extra??
-                self.read_addr_cycle(addr) // +1 cycle
+                let addr = self.get_indirect(bus, ptr); // +2 cycles
+                self.cycle(bus); //?? +1 cycle extra??
+                self.read_addr_cycle(bus, addr) // +1 cycle
             }
             IndexRegister::Y => {
                 let ptr = as_address(m, 0x00);
                 let delta = self.reg.y as u16;
-                let addr = self.get_indirect(ptr); // +2 cycles
+                let addr = self.get_indirect(bus, ptr); // +2 cycles
                 // if (addr & 0xff00) != ((addr + delta) & 0xff00) {
                 // !! NOTE: this function is only ever used in the unofficial instructions,
                 // !! who "[...] always have a page crossing penalty even if not crossing a page."
-                self.cycle(); // +1 cycle
+                self.cycle(bus); // +1 cycle
                 // }
-                self.read_addr_cycle(addr + delta) // +1 cycle
+                self.read_addr_cycle(bus, addr + delta) // +1 cycle
             }
             IndexRegister::N => unreachable!(),
         }
@@ -583,23 +531,23 @@ impl CPU {
     /// Read the indirect address in relation to x or y
     ///
     /// Cycles: `5` for x, `4` (`+1` if page crossed) for y
-    pub fn ind_read_inc(&mut self, ir: IndexRegister) -> u8 {
-        let m = self.pc_read_inc(); // +1 cycle
+    pub fn ind_read_inc(&mut self, bus: &mut Bus, ir: IndexRegister) -> u8 {
+        let m = self.pc_read_inc(bus); // +1 cycle
         match ir {
             IndexRegister::X => {
                 let ptr = as_address(m + self.reg.x, 0x00);
-                let addr = self.get_indirect(ptr); // +2 cycles
-                self.cycle(); //?? +1 cycle extra??
-                self.read_addr_cycle(addr) // +1 cycle
+                let addr = self.get_indirect(bus, ptr); // +2 cycles
+                self.cycle(bus); //?? +1 cycle extra??
+                self.read_addr_cycle(bus, addr) // +1 cycle
             }
             IndexRegister::Y => {
                 let ptr = as_address(m, 0x00);
                 let delta = self.reg.y as u16;
-                let addr = self.get_indirect(ptr); // +2 cycles
+                let addr = self.get_indirect(bus, ptr); // +2 cycles
                 if (addr & 0xff00) != ((addr + delta) & 0xff00) {
-                    self.cycle(); // +1 cycle
+                    self.cycle(bus); // +1 cycle
                 }
-                self.read_addr_cycle(addr + delta) // +1 cycle
+                self.read_addr_cycle(bus, addr + delta) // +1 cycle
             }
             IndexRegister::N => unreachable!(),
         }
@@ -608,21 +556,21 @@ impl CPU {
     /// Write `val` to the indirect address in relation to x or y
     ///
     /// Cycles: `5` for x and y
-    pub fn ind_write_inc(&mut self, val: u8, ir: IndexRegister) -> () {
-        let pcval = self.pc_read_inc(); // +1 cycle
+    pub fn ind_write_inc(&mut self, bus: &mut Bus, val: u8, ir: IndexRegister) -> () {
+        let pcval = self.pc_read_inc(bus); // +1 cycle
         match ir {
             IndexRegister::X => {
                 let ptr = as_address(pcval + self.reg.x, 0x00);
-                let addr = self.get_indirect(ptr as u16); // +2 cycles
-                self.cycle(); // +1 cycle extra
-                self.write_addr_cycle(addr, val) // +1 cycle
+                let addr = self.get_indirect(bus, ptr as u16); // +2 cycles
+                self.cycle(bus); // +1 cycle extra
+                self.write_addr_cycle(bus, addr, val) // +1 cycle
             }
             IndexRegister::Y => {
                 let ptr = as_address(pcval, 0x00);
                 let delta = self.reg.y as u16;
-                let addr = self.get_indirect(ptr) + delta; // +2 cycles
-                self.cycle(); // +1 cycle extra
-                self.write_addr_cycle(addr, val) // +1 cycle
+                let addr = self.get_indirect(bus, ptr) + delta; // +2 cycles
+                self.cycle(bus); // +1 cycle extra
+                self.write_addr_cycle(bus, addr, val) // +1 cycle
             }
             IndexRegister::N => unreachable!(),
         }
@@ -631,91 +579,91 @@ impl CPU {
     /// Pull from the stack, increasing sp by 1
     ///
     /// Cycles: `3`
-    fn pull(&mut self) -> u8 {
-        self.cycle(); // +1
+    fn pull(&mut self, bus: &mut Bus) -> u8 {
+        self.cycle(bus); // +1
         self.reg.sp += 1;
-        self.cycle(); // +1
-        self.read_addr_cycle(as_address(self.reg.sp, 0x01)) // +1
+        self.cycle(bus); // +1
+        self.read_addr_cycle(bus, as_address(self.reg.sp, 0x01)) // +1
     }
 
     /// Pull from the stack, increasing sp by 1. Does NOT 'waste' any extra cycles
     ///
     /// Cycles: `1`
-    fn pull_noextra(&mut self) -> u8 {
+    fn pull_noextra(&mut self, bus: &mut Bus) -> u8 {
         self.reg.sp += 1;
-        self.read_addr_cycle(as_address(self.reg.sp, 0x01)) // +1
+        self.read_addr_cycle(bus, as_address(self.reg.sp, 0x01)) // +1
     }
 
     /// Push `val` onto the stack, decreasing sp by 1
     ///
     /// Cycles: `2`
-    fn push(&mut self, val: u8) -> () {
-        self.write_addr_cycle(as_address(self.reg.sp, 0x01), val); // +1
+    fn push(&mut self, bus: &mut Bus, val: u8) -> () {
+        self.write_addr_cycle(bus, as_address(self.reg.sp, 0x01), val); // +1
         self.reg.sp -= 1;
-        self.cycle(); // +1
+        self.cycle(bus); // +1
     }
 
-    fn fetch_next_op(&mut self) -> u8 {
-        self.pc_read_inc() // 1 cycle
+    fn fetch_next_op(&mut self, bus: &mut Bus) -> u8 {
+        self.pc_read_inc(bus) // 1 cycle
     }
 
-    fn fetch_next_inst(&mut self) -> Inst {
-        INST_TABLE[self.fetch_next_op() as usize] // 1 cycle
+    fn fetch_next_inst(&mut self, bus: &mut Bus) -> Inst {
+        INST_TABLE[self.fetch_next_op(bus) as usize] // 1 cycle
     }
 
-    fn fetch_next_inst_nocycle(&self) -> Inst {
-        INST_TABLE[self.pc_read_nocycle() as usize]
+    fn fetch_next_inst_nocycle(&self, bus: &Bus) -> Inst {
+        INST_TABLE[self.pc_read_nocycle(bus) as usize]
     }
 
-    fn run_inst(&mut self, inst: Inst) -> () {
-        inst.run(self);
+    fn run_inst(&mut self, bus: &mut Bus, inst: Inst) -> () {
+        inst.run(self, bus);
     }
 
-    pub fn fetch_and_run(&mut self) -> () {
+    pub fn fetch_and_run(&mut self, bus: &mut Bus) -> () {
         // If cpu is halted, do nothing except cycling the other processors
         if self.halted {
             // TODO: check exact behavior
-            self.cycle();
+            self.cycle(bus);
             return;
         }
 
         // OAM DMA handling
-        if self.reg.dma {
-            self.handle_oam_dma();
+        if let Some(page) = bus.take_oam_dma_request() {
+            self.handle_oam_dma(bus, page);
         }
 
         let cycles_before = self.cycles;
 
-        let inst = self.fetch_next_inst(); // 1 cycle
-        self.run_inst(inst); // n cycles
+        let inst = self.fetch_next_inst(bus); // 1 cycle
+        self.run_inst(bus, inst); // n cycles
 
         let cycles_after = self.cycles;
 
         if cycles_after - cycles_before == 1 {
-            self.cycle();
+            self.cycle(bus);
         }
 
         // NMI handling
-        if self.ppu.nmi_enable() && self.ppu.is_vblank() && !self.reg.nmi {
-            self.handle_nmi();
-        } else if self.reg.nmi && !self.ppu.is_vblank() {
+        if bus.nmi_line() && !self.reg.nmi {
+            self.handle_nmi(bus);
+        } else if self.reg.nmi && !bus.is_vblank() {
             self.reg.nmi = false;
         }
 
         // IRQ handling
-        if !self.reg.p.i && self.mem.irq_triggered() {
-            self.handle_irq();
-            self.mem.irq_un_trigger();
+        if !self.reg.p.i && bus.irq_line() {
+            self.handle_irq(bus);
+            bus.acknowledge_irq();
         } else {
             self.reg.irq = false;
         }
     }
 
-    pub fn run_to_rendering_finished(&mut self) -> () {
+    pub fn run_to_rendering_finished(&mut self, bus: &mut Bus) -> () {
         loop {
-            let before = self.ppu.just_finished_rendering();
-            self.fetch_and_run();
-            let after = self.ppu.just_finished_rendering();
+            let before = bus.just_finished_rendering();
+            self.fetch_and_run(bus);
+            let after = bus.just_finished_rendering();
 
             if after && !before {
                 break;
@@ -723,13 +671,42 @@ impl CPU {
         }
     }
 
-    pub fn fetch_and_run_dbg(&mut self) -> () {
+    pub fn fetch_and_run_dbg(&mut self, bus: &mut Bus) -> () {
         let cycles_before = self.cycles;
 
-        self.fetch_and_run();
+        self.fetch_and_run(bus);
 
         let cycles_after = self.cycles;
 
         debug!("took {} cycles", cycles_after - cycles_before);
     }
 }
+
+impl Snapshot for CPU {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.reg.a);
+        w.write(&self.reg.x);
+        w.write(&self.reg.y);
+        w.write(&Into::<u8>::into(self.reg.p));
+        w.write(&self.reg.sp);
+        w.write(&self.reg.pc);
+        w.write(&self.reg.irq);
+        w.write(&self.reg.nmi);
+        w.write(&self.cycles);
+        w.write(&self.halted);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.reg.a = r.read()?;
+        self.reg.x = r.read()?;
+        self.reg.y = r.read()?;
+        self.reg.p = r.read::<u8>()?.into();
+        self.reg.sp = r.read()?;
+        self.reg.pc = r.read()?;
+        self.reg.irq = r.read()?;
+        self.reg.nmi = r.read()?;
+        self.cycles = r.read()?;
+        self.halted = r.read()?;
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::bits::{Addr, Bitwise, as_address};
+use crate::fc::bus::Bus;
 
 use super::{CPU, IRQ_VECTOR};
 
@@ -213,28 +214,28 @@ impl Inst {
         INST_TABLE[opcode as usize]
     }
 
-    pub fn run(self, cpu: &mut CPU) -> () {
+    pub fn run(self, cpu: &mut CPU, bus: &mut Bus) -> () {
         match self {
-            NOP(am) => nop(cpu, am),
-            ADC(am) => adc(cpu, am, false),
-            AND(am) => and(cpu, am),
-            EOR(am) => eor(cpu, am),
-            ORA(am) => ora(cpu, am),
-            ASL(am) => { rot(cpu, am, false, true); },
-            BIT(am) => bit(cpu, am),
-            BCC(_a) => branch(cpu, !cpu.reg.p.c),
-            BCS(_a) => branch(cpu, cpu.reg.p.c),
-            BEQ(_a) => branch(cpu, cpu.reg.p.z),
-            BMI(_a) => branch(cpu, cpu.reg.p.n),
-            BNE(_a) => branch(cpu, !cpu.reg.p.z),
-            BPL(_a) => branch(cpu, !cpu.reg.p.n),
-            BVC(_a) => branch(cpu, !cpu.reg.p.v),
-            BVS(_a) => branch(cpu, cpu.reg.p.v),
-            JMP(am) => jmp(cpu, am, false),
-            JSR(am) => jmp(cpu, am, true),
-            RTS(_a) => rts(cpu, false),
-            RTI(_a) => rts(cpu, true),
-            BRK(_a) => brk(cpu),
+            NOP(am) => nop(cpu, bus, am),
+            ADC(am) => adc(cpu, bus, am, false),
+            AND(am) => and(cpu, bus, am),
+            EOR(am) => eor(cpu, bus, am),
+            ORA(am) => ora(cpu, bus, am),
+            ASL(am) => { rot(cpu, bus, am, false, true); },
+            BIT(am) => bit(cpu, bus, am),
+            BCC(_a) => branch(cpu, bus, !cpu.reg.p.c),
+            BCS(_a) => branch(cpu, bus, cpu.reg.p.c),
+            BEQ(_a) => branch(cpu, bus, cpu.reg.p.z),
+            BMI(_a) => branch(cpu, bus, cpu.reg.p.n),
+            BNE(_a) => branch(cpu, bus, !cpu.reg.p.z),
+            BPL(_a) => branch(cpu, bus, !cpu.reg.p.n),
+            BVC(_a) => branch(cpu, bus, !cpu.reg.p.v),
+            BVS(_a) => branch(cpu, bus, cpu.reg.p.v),
+            JMP(am) => jmp(cpu, bus, am, false),
+            JSR(am) => jmp(cpu, bus, am, true),
+            RTS(_a) => rts(cpu, bus, false),
+            RTI(_a) => rts(cpu, bus, true),
+            BRK(_a) => brk(cpu, bus),
             CLC(_a) => cpu.reg.p.c = false,
             CLD(_a) => cpu.reg.p.d = false,
             CLI(_a) => cpu.reg.p.i = false,
@@ -242,29 +243,29 @@ impl Inst {
             SEC(_a) => cpu.reg.p.c = true,
             SED(_a) => cpu.reg.p.d = true,
             SEI(_a) => cpu.reg.p.i = true,
-            CMP(am) => cmp(cpu, am, InstrReg::A),
-            CPX(am) => cmp(cpu, am, InstrReg::X),
-            CPY(am) => cmp(cpu, am, InstrReg::Y),
-            DEC(am) => inc(cpu, am, true),
+            CMP(am) => cmp(cpu, bus, am, InstrReg::A),
+            CPX(am) => cmp(cpu, bus, am, InstrReg::X),
+            CPY(am) => cmp(cpu, bus, am, InstrReg::Y),
+            DEC(am) => inc(cpu, bus, am, true),
             DEX(_a) => set_x(cpu, cpu.reg.x - 1),
             DEY(_a) => set_y(cpu, cpu.reg.y - 1),
-            INC(am) => inc(cpu, am, false),
+            INC(am) => inc(cpu, bus, am, false),
             INX(_a) => set_x(cpu, cpu.reg.x + 1),
             INY(_a) => set_y(cpu, cpu.reg.y + 1),
-            LDA(am) => ld(cpu, am, InstrReg::A),
-            LDX(am) => ld(cpu, am, InstrReg::X),
-            LDY(am) => ld(cpu, am, InstrReg::Y),
-            LSR(am) => { rot(cpu, am, false, false); },
-            PHA(_a) => cpu.push(cpu.reg.a),
-            PHP(_a) => cpu.push(Into::<u8>::into(cpu.reg.p) | 0b0011_0000),
-            PLA(_a) => pla(cpu),
-            PLP(_a) => cpu.reg.p = (cpu.pull() & 0b1100_1111).into(),
-            ROL(am) => { rot(cpu, am, true, true); },
-            ROR(am) => { rot(cpu, am, true, false); },
-            SBC(am) => adc(cpu, am, true),
-            STA(am) => st(cpu, am, InstrReg::A),
-            STX(am) => st(cpu, am, InstrReg::X),
-            STY(am) => st(cpu, am, InstrReg::Y),
+            LDA(am) => ld(cpu, bus, am, InstrReg::A),
+            LDX(am) => ld(cpu, bus, am, InstrReg::X),
+            LDY(am) => ld(cpu, bus, am, InstrReg::Y),
+            LSR(am) => { rot(cpu, bus, am, false, false); },
+            PHA(_a) => cpu.push(bus, cpu.reg.a),
+            PHP(_a) => cpu.push(bus, Into::<u8>::into(cpu.reg.p) | 0b0011_0000),
+            PLA(_a) => pla(cpu, bus),
+            PLP(_a) => cpu.reg.p = (cpu.pull(bus) & 0b1100_1111).into(),
+            ROL(am) => { rot(cpu, bus, am, true, true); },
+            ROR(am) => { rot(cpu, bus, am, true, false); },
+            SBC(am) => adc(cpu, bus, am, true),
+            STA(am) => st(cpu, bus, am, InstrReg::A),
+            STX(am) => st(cpu, bus, am, InstrReg::X),
+            STY(am) => st(cpu, bus, am, InstrReg::Y),
             TAX(_a) => set_x(cpu, cpu.reg.a),
             TAY(_a) => set_y(cpu, cpu.reg.a),
             TXA(_a) => set_a(cpu, cpu.reg.x),
@@ -272,31 +273,31 @@ impl Inst {
             TSX(_a) => set_x(cpu, cpu.reg.sp),
             TXS(_a) => cpu.reg.sp = cpu.reg.x,
             // Unofficial
-            ALR(am) => alr(cpu, am),
-            ANC(am) => anc(cpu, am),
-            ARR(am) => arr(cpu, am),
-            AHX(am) => shn(cpu, am, InstrReg::A),
-            AXS(am) => axs(cpu, am),
-            DCP(am) => dcp(cpu, am, false),
-            RLA(am) => rla(cpu, am, true, true),
-            RRA(am) => rra(cpu, am),
-            LAX(am) => lax(cpu, am),
-            SAX(am) => sax(cpu, am),
-            SLO(am) => slo(cpu, am, false, true),
-            SRE(am) => sre(cpu, am, false, false),
-            SHX(am) => shn(cpu, am, InstrReg::X),
-            SHY(am) => shn(cpu, am, InstrReg::Y),
-            ISC(am) => dcp(cpu, am, true),
-            TAS(am) => tas(cpu, am),
-            LAS(am) => las(cpu, am),
-            XAA(am) => xaa(cpu, am),
+            ALR(am) => alr(cpu, bus, am),
+            ANC(am) => anc(cpu, bus, am),
+            ARR(am) => arr(cpu, bus, am),
+            AHX(am) => shn(cpu, bus, am, InstrReg::A),
+            AXS(am) => axs(cpu, bus, am),
+            DCP(am) => dcp(cpu, bus, am, false),
+            RLA(am) => rla(cpu, bus, am, true, true),
+            RRA(am) => rra(cpu, bus, am),
+            LAX(am) => lax(cpu, bus, am),
+            SAX(am) => sax(cpu, bus, am),
+            SLO(am) => slo(cpu, bus, am, false, true),
+            SRE(am) => sre(cpu, bus, am, false, false),
+            SHX(am) => shn(cpu, bus, am, InstrReg::X),
+            SHY(am) => shn(cpu, bus, am, InstrReg::Y),
+            ISC(am) => dcp(cpu, bus, am, true),
+            TAS(am) => tas(cpu, bus, am),
+            LAS(am) => las(cpu, bus, am),
+            XAA(am) => xaa(cpu, bus, am),
             STP(op) => stp(cpu, op),
         }
     }
 }
 
 
-fn nop(cpu: &mut CPU, am: AddrMode) {
+fn nop(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     if let AddrMode::Imp = am {
         // Note: Imp does nothing, however since min cycles per instruction is 2,
         // an extra cycle is performed either way (outside of this function.)
@@ -304,11 +305,11 @@ fn nop(cpu: &mut CPU, am: AddrMode) {
     }
 
     // TODO: check properly every addressing mode
-    cpu.operand_read_inc(am);   // +n cycles
+    cpu.operand_read_inc(bus, am);   // +n cycles
 }
 
-fn pla(cpu: &mut CPU) {
-    cpu.reg.a = cpu.pull();
+fn pla(cpu: &mut CPU, bus: &mut Bus) {
+    cpu.reg.a = cpu.pull(bus);
     cpu.reg.p.z = cpu.reg.a == 0;
     cpu.reg.p.n = cpu.reg.a.test_bit(7)
 }
@@ -330,8 +331,8 @@ a_op_val!(eor_val, ^);
 
 macro_rules! a_op_fn {
     ($fn_name: ident, $op_fn: ident) => {
-        fn $fn_name(cpu: &mut CPU, am: AddrMode) {
-            let val = cpu.operand_read_inc(am);
+        fn $fn_name(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
+            let val = cpu.operand_read_inc(bus, am);
             $op_fn(cpu, val);
         }
     };
@@ -359,9 +360,9 @@ fn set_y(cpu: &mut CPU, f: u8) {
     cpu.reg.p.n = cpu.reg.y.test_bit(7)
 }
 
-fn bit(cpu: &mut CPU, am: AddrMode) -> () {
+fn bit(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) -> () {
     let a = cpu.reg.a;
-    let m = cpu.operand_read_inc(am);
+    let m = cpu.operand_read_inc(bus, am);
     let result = a & m;
 
     cpu.reg.p.z = result == 0;
@@ -369,17 +370,17 @@ fn bit(cpu: &mut CPU, am: AddrMode) -> () {
     cpu.reg.p.n = m.test_bit(7);
 }
 
-fn increment(cpu: &mut CPU, am: AddrMode, decrement: bool) -> u8 {
-    let m = cpu.operand_read_cycle(am);
+fn increment(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, decrement: bool) -> u8 {
+    let m = cpu.operand_read_cycle(bus, am);
     let result = if decrement { m - 1 } else { m + 1 };
     cpu.reg.p.z = result == 0;
     cpu.reg.p.n = result.test_bit(7);
-    cpu.operand_write_inc(am, result);
+    cpu.operand_write_inc(bus, am, result);
     result
 }
 
-fn inc(cpu: &mut CPU, am: AddrMode, decrement: bool) -> () {
-    increment(cpu, am, decrement);
+fn inc(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, decrement: bool) -> () {
+    increment(cpu, bus, am, decrement);
 }
 
 #[derive(Debug)]
@@ -407,8 +408,8 @@ impl InstrReg {
     }
 }
 
-fn ld(cpu: &mut CPU, am: AddrMode, inst_reg: InstrReg) -> () {
-    let val = cpu.operand_read_inc(am);
+fn ld(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, inst_reg: InstrReg) -> () {
+    let val = cpu.operand_read_inc(bus, am);
     let z = val == 0;
     let n = val.test_bit(7);
 
@@ -417,10 +418,10 @@ fn ld(cpu: &mut CPU, am: AddrMode, inst_reg: InstrReg) -> () {
     cpu.reg.p.n = n;
 }
 
-fn st(cpu: &mut CPU, am: AddrMode, instr_reg: InstrReg) -> () {
+fn st(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, instr_reg: InstrReg) -> () {
     let val = instr_reg.get(cpu);
 
-    cpu.operand_write_inc(am, val);
+    cpu.operand_write_inc(bus, am, val);
 }
 
 fn add_with_carry(cpu: &mut CPU, a: u16, m: u16, c: u16) -> () {
@@ -433,9 +434,9 @@ fn add_with_carry(cpu: &mut CPU, a: u16, m: u16, c: u16) -> () {
     cpu.reg.p.n = result.test_bit(7);
 }
 
-fn adc(cpu: &mut CPU, am: AddrMode, sbc: bool) -> () {
+fn adc(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, sbc: bool) -> () {
     let a = cpu.reg.a as u16;
-    let m = (cpu.operand_read_inc(am) ^ (if sbc { 0xff } else { 0 })) as u16;
+    let m = (cpu.operand_read_inc(bus, am) ^ (if sbc { 0xff } else { 0 })) as u16;
     let c = cpu.reg.p.c as u16;
 
     add_with_carry(cpu, a, m, c);
@@ -449,18 +450,18 @@ fn compare(cpu: &mut CPU, r: u8, m: u8) -> () {
     cpu.reg.p.n = result.test_bit(7);
 }
 
-fn cmp(cpu: &mut CPU, am: AddrMode, instr_reg: InstrReg) -> () {
+fn cmp(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, instr_reg: InstrReg) -> () {
     let r = instr_reg.get(cpu);
-    let m = cpu.operand_read_inc(am);
+    let m = cpu.operand_read_inc(bus, am);
     compare(cpu, r, m);
 }
 
-fn rot(cpu: &mut CPU, am: AddrMode, rotate: bool, left: bool) -> u8 {
+fn rot(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, rotate: bool, left: bool) -> u8 {
     let shift_fn: fn(u8) -> u8 = if left { |x| x << 1 } else { |x| x >> 1 };
     let pad_amount = if left { 0 } else { 7 };
     let old_bit = if left { 7 } else { 0 };
 
-    let val = cpu.operand_read_cycle(am);   // +n cycles (note: always +5 for ind y!)
+    let val = cpu.operand_read_cycle(bus, am);   // +n cycles (note: always +5 for ind y!)
     let new_val = shift_fn(val) | (if rotate { cpu.reg.p.c as u8 } else { 0 } << pad_amount);
 
     let val = match am {
@@ -471,19 +472,19 @@ fn rot(cpu: &mut CPU, am: AddrMode, rotate: bool, left: bool) -> u8 {
         }
         ZP(ir) => {
             cpu.reg.p.c = val.test_bit(old_bit);
-            cpu.zp_write_inc(new_val, ir); // +2(+1) cycles
+            cpu.zp_write_inc(bus, new_val, ir); // +2(+1) cycles
             new_val
         }
         Abs(ir) => {
             cpu.reg.p.c = val.test_bit(old_bit);
-            cpu.abs_write_inc(new_val, ir); // +3(+1) cycles
+            cpu.abs_write_inc(bus, new_val, ir); // +3(+1) cycles
             new_val
             // = 5(+1)
         }
         // Used for unofficial instructions (rla, rra, slo, sre)
         Ind(ir) => {
             cpu.reg.p.c = val.test_bit(old_bit);
-            cpu.ind_write_inc(new_val, ir); // +5 cycles
+            cpu.ind_write_inc(bus, new_val, ir); // +5 cycles
             new_val
         }
         _ => unreachable!()
@@ -493,65 +494,65 @@ fn rot(cpu: &mut CPU, am: AddrMode, rotate: bool, left: bool) -> u8 {
     val
 }
 
-fn branch(cpu: &mut CPU, condition: bool) -> () {
-    let offset = cpu.pc_read_inc() as i8; // +1 cycle
+fn branch(cpu: &mut CPU, bus: &mut Bus, condition: bool) -> () {
+    let offset = cpu.pc_read_inc(bus) as i8; // +1 cycle
     if condition {
-        cpu.pc_offset_cycle(offset); // +1(+1) cycle(s)
+        cpu.pc_offset_cycle(bus, offset); // +1(+1) cycle(s)
     }
 }
 
-fn jmp(cpu: &mut CPU, am: AddrMode, jsr: bool) -> () {
+fn jmp(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, jsr: bool) -> () {
     match am {
         AddrMode::Abs(N) => {
-            let l = cpu.pc_read_inc(); // +1 cycle
+            let l = cpu.pc_read_inc(bus); // +1 cycle
             let m = if !jsr {
-                cpu.pc_read_inc() // +1 cycle
+                cpu.pc_read_inc(bus) // +1 cycle
             } else {
-                cpu.push(cpu.reg.pc.msb()); // +2
-                cpu.push(cpu.reg.pc.lsb()); // +2
+                cpu.push(bus, cpu.reg.pc.msb()); // +2
+                cpu.push(bus, cpu.reg.pc.lsb()); // +2
 
                 // as a side effect of using nocycle, pc is already "pc-1"
                 // (which is what _should_ be pushed)
-                let val = cpu.pc_read_nocycle(); // !!TODO: check (jsr is 5 cycles (w/o opcode))
+                let val = cpu.pc_read_nocycle(bus); // !!TODO: check (jsr is 5 cycles (w/o opcode))
                 val
             };
             let addr = as_address(l, m);
             cpu.reg.pc = addr;
         }
         AddrMode::Ind(N) => {
-            let l = cpu.pc_read_inc(); // +1 cycle
-            let m = cpu.pc_read_inc(); // +1 cycle
+            let l = cpu.pc_read_inc(bus); // +1 cycle
+            let m = cpu.pc_read_inc(bus); // +1 cycle
             let addr = as_address(l, m);
-            let addr_indirect = cpu.get_indirect(addr); // +2 cycles
+            let addr_indirect = cpu.get_indirect(bus, addr); // +2 cycles
             cpu.reg.pc = addr_indirect;
         }
         _ => unreachable!(),
     }
 }
 
-fn rts(cpu: &mut CPU, rti: bool) -> () {
+fn rts(cpu: &mut CPU, bus: &mut Bus, rti: bool) -> () {
     let delta = if rti {
-        cpu.reg.p = cpu.pull_noextra().into(); // +1
+        cpu.reg.p = cpu.pull_noextra(bus).into(); // +1
         0
     } else {
-        cpu.cycle(); // +1
+        cpu.cycle(bus); // +1
         1
     };
-    let l = cpu.pull(); // +3
-    let m = cpu.pull_noextra(); // +1
+    let l = cpu.pull(bus); // +3
+    let m = cpu.pull_noextra(bus); // +1
     let addr = as_address(l, m);
     cpu.reg.pc = addr + delta;
 }
 
-fn brk(cpu: &mut CPU) -> () {
+fn brk(cpu: &mut CPU, bus: &mut Bus) -> () {
     // TODO: interrupts
-    cpu.push((cpu.reg.pc + 1).msb()); // +2
-    cpu.push((cpu.reg.pc + 1).lsb()); // +2
-    cpu.push(Into::<u8>::into(cpu.reg.p) | 0b0011_0000); // +2
+    cpu.push(bus, (cpu.reg.pc + 1).msb()); // +2
+    cpu.push(bus, (cpu.reg.pc + 1).lsb()); // +2
+    cpu.push(bus, Into::<u8>::into(cpu.reg.p) | 0b0011_0000); // +2
     // cpu.reg.p.b = true; // ?
 
-    let l = cpu.read_addr_nocycle(IRQ_VECTOR);
-    let m = cpu.read_addr_nocycle(IRQ_VECTOR + 1);
+    let l = cpu.read_addr_nocycle(bus, IRQ_VECTOR);
+    let m = cpu.read_addr_nocycle(bus, IRQ_VECTOR + 1);
     let addr = as_address(l, m);
     cpu.reg.pc = addr;
 }
@@ -559,9 +560,9 @@ fn brk(cpu: &mut CPU) -> () {
 // Unofficial
 macro_rules! rot_inst_unofficial {
     ($fn_name: ident, $fn: ident, adc) => {
-        fn $fn_name(cpu: &mut CPU, am: AddrMode) {
+        fn $fn_name(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
             log::info!("Unofficial {} (rotate right) with addr mode: {am:?}", stringify!($fn_name));
-            let val = $fn(cpu, am, true, false);
+            let val = $fn(cpu, bus, am, true, false);
 
             let a = cpu.reg.a as u16;
             let m = val as u16;
@@ -572,9 +573,9 @@ macro_rules! rot_inst_unofficial {
     };
 
     ($fn_name: ident, rot, $op: ident) => {
-        fn $fn_name(cpu: &mut CPU, am: AddrMode, rotate: bool, left: bool) {
+        fn $fn_name(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, rotate: bool, left: bool) {
             log::info!("Unofficial {} ({} {}) with addr mode: {am:?}", stringify!($fn_name), if rotate {"rotate"} else {"shift"}, if left {"left"} else {"right"});
-            let val = rot(cpu, am, rotate, left);
+            let val = rot(cpu, bus, am, rotate, left);
 
             $op(cpu, val)
         }
@@ -586,9 +587,9 @@ rot_inst_unofficial!(rra, rot, adc);
 rot_inst_unofficial!(slo, rot, ora_val);
 rot_inst_unofficial!(sre, rot, eor_val);
 
-fn dcp(cpu: &mut CPU, am: AddrMode, isc: bool) {
+fn dcp(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, isc: bool) {
     log::info!("Unofficial {} with addr mode: {am:?}", stringify!($fn_name));
-    let val = increment(cpu, am, !isc);
+    let val = increment(cpu, bus, am, !isc);
 
     if isc {
         // SBC
@@ -603,37 +604,37 @@ fn dcp(cpu: &mut CPU, am: AddrMode, isc: bool) {
     }
 }
 
-fn alr(cpu: &mut CPU, am: AddrMode) {
+fn alr(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     log::info!("Unofficial alr with addr mode: {am:?}");
-    let val = cpu.operand_read_inc(am);
+    let val = cpu.operand_read_inc(bus, am);
     and_val(cpu, val);
 
-    rot(cpu, AddrMode::Acc, false, false);
+    rot(cpu, bus, AddrMode::Acc, false, false);
 }
 
-fn anc(cpu: &mut CPU, am: AddrMode) {
+fn anc(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     log::info!("Unofficial anc with addr mode: {am:?}");
-    let val = cpu.operand_read_inc(am);
+    let val = cpu.operand_read_inc(bus, am);
     and_val(cpu, val);
 
     cpu.reg.p.c = cpu.reg.p.n;
 }
 
-fn arr(cpu: &mut CPU, am: AddrMode) {
+fn arr(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     log::info!("Unofficial arr with addr mode: {am:?}");
-    let val = cpu.operand_read_inc(am);
+    let val = cpu.operand_read_inc(bus, am);
     and_val(cpu, val);
 
-    rot(cpu, AddrMode::Acc, true, false);
+    rot(cpu, bus, AddrMode::Acc, true, false);
 
     let a = cpu.reg.a;
     cpu.reg.p.c = a.test_bit(6);
     cpu.reg.p.v = a.test_bit(6) ^ a.test_bit(5);
 }
 
-fn axs(cpu: &mut CPU, am: AddrMode) {
+fn axs(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     log::info!("Unofficial axs with addr mode: {am:?}");
-    let val = cpu.operand_read_inc(am);
+    let val = cpu.operand_read_inc(bus, am);
 
     let a = cpu.reg.a as u16;
     let x = cpu.reg.x as u16;
@@ -648,20 +649,20 @@ fn axs(cpu: &mut CPU, am: AddrMode) {
     cpu.reg.p.n = result.test_bit(7);
 }
 
-fn lax(cpu: &mut CPU, am: AddrMode) {
+fn lax(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     log::info!("Unofficial lax with addr mode: {am:?}");
-    ld(cpu, am, InstrReg::A);
+    ld(cpu, bus, am, InstrReg::A);
     cpu.reg.x = cpu.reg.a;
 }
 
-fn sax(cpu: &mut CPU, am: AddrMode) {
+fn sax(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     log::info!("Unofficial sax with addr mode: {am:?}");
     let val = cpu.reg.a & cpu.reg.x;
-    cpu.operand_write_inc(am, val);
+    cpu.operand_write_inc(bus, am, val);
 }
 
 // Unstable
-fn shn(cpu: &mut CPU, am: AddrMode, instr_reg: InstrReg) {
+fn shn(cpu: &mut CPU, bus: &mut Bus, am: AddrMode, instr_reg: InstrReg) {
     // TODO: make the instruction perform as expected.
     log::info!("Unofficial sh{instr_reg:?} with addr mode: {am:?}");
 
@@ -677,14 +678,14 @@ fn shn(cpu: &mut CPU, am: AddrMode, instr_reg: InstrReg) {
     let addr = match am {
         AddrMode::Ind(_) => {
             // Note: ir will always be Y
-            let zp = cpu.pc_read_inc();  // +1 cycle
-            let l = cpu.read_addr_cycle(as_address(zp, 0x00));      // +1 cycle
-            let m = cpu.read_addr_cycle(as_address(zp + 1, 0x00));  // +1 cycle
+            let zp = cpu.pc_read_inc(bus);  // +1 cycle
+            let l = cpu.read_addr_cycle(bus, as_address(zp, 0x00));      // +1 cycle
+            let m = cpu.read_addr_cycle(bus, as_address(zp + 1, 0x00));  // +1 cycle
             as_address(l, m)
         }
         _ => {
-            let l = cpu.pc_read_inc();  // +1 cycle
-            let m = cpu.pc_read_inc();  // +1 cycle
+            let l = cpu.pc_read_inc(bus);  // +1 cycle
+            let m = cpu.pc_read_inc(bus);  // +1 cycle
             as_address(l, m)
         }
     };
@@ -709,7 +710,7 @@ fn shn(cpu: &mut CPU, am: AddrMode, instr_reg: InstrReg) {
 
     let pre_addr = addr + ind_reg_val as u16;
     let page_crossing = (addr & 0xff00) != (pre_addr as u16) & 0xff00;
-    cpu.read_addr_cycle(pre_addr);  // Dummy read. +1 cycle i.e. the 4th / 5th cycle
+    cpu.read_addr_cycle(bus, pre_addr);  // Dummy read. +1 cycle i.e. the 4th / 5th cycle
 
     let dma_interrupted = cpu.cycles - cycles_before > 1;
 
@@ -730,22 +731,22 @@ fn shn(cpu: &mut CPU, am: AddrMode, instr_reg: InstrReg) {
         log::info!("DMA interrupted!!!");
         // DMA occurred
         // "Unless interrupted by DMC DMA on the 4th clock [...]"
-        cpu.write_addr_cycle(write_addr, to_write_val);
+        cpu.write_addr_cycle(bus, write_addr, to_write_val);
     } else {
-        cpu.write_addr_cycle(write_addr, to_write_val & (pre_addr.msb() + 1));
+        cpu.write_addr_cycle(bus, write_addr, to_write_val & (pre_addr.msb() + 1));
     }
 }
 
-fn tas(cpu: &mut CPU, am: AddrMode) {
+fn tas(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     log::info!("Unofficial tas with addr mode: {am:?}");
     log::info!("(uses sha, as seen in the next line)");
-    shn(cpu, am, InstrReg::A);
+    shn(cpu, bus, am, InstrReg::A);
     cpu.reg.sp = cpu.reg.a & cpu.reg.x;
 }
 
-fn las(cpu: &mut CPU, am: AddrMode) {
+fn las(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     log::info!("Unofficial las with addr mode: {am:?}");
-    let val = cpu.operand_read_inc(am);
+    let val = cpu.operand_read_inc(bus, am);
     let sp = cpu.reg.sp;
 
     let result = val & sp;
@@ -759,14 +760,14 @@ fn las(cpu: &mut CPU, am: AddrMode) {
 }
 
 // Unpredictable
-fn xaa(cpu: &mut CPU, am: AddrMode) {
+fn xaa(cpu: &mut CPU, bus: &mut Bus, am: AddrMode) {
     log::info!("Unofficial xaa with addr mode: {am:?}");
 
     // This instruction depends on analog behavior... so instead we simply use 0xff as magic.
     let magic = 0xff;
     let a = cpu.reg.a;
     let x = cpu.reg.x;
-    let val = cpu.operand_read_inc(am);
+    let val = cpu.operand_read_inc(bus, am);
 
     let result = (a | magic) & x & val;
 
@@ -6,6 +6,8 @@ use image::RgbImage;
 use log::info;
 
 use crate::bits;
+use crate::fc::apu::Channel;
+use crate::fc::region::Region;
 
 use super::FC;
 
@@ -51,11 +53,21 @@ impl Debugger {
         Ok(())
     }
 
+    /// Manually set the region (or use the one from the ROM header if `None`.)
+    pub fn set_region_override(&mut self, region: Option<Region>) -> Result<(), String> {
+        self.fc.set_region_override(region).map_err(|e| format!("Failed to change region: {e}"))
+    }
+
+    /// Start recording audio to `path`.
+    pub fn start_wav_recording(&mut self, path: &Path) -> Result<(), String> {
+        self.fc.start_wav_recording(path).map_err(|e| format!("Failed to start recording: {e}"))
+    }
+
     pub fn run(&mut self) -> () {
         let mut input = String::new();
 
         println!("Started debugger");
-        self.fc.cpu.print_state();
+        self.fc.print_state();
         loop {
             print!("{}", if !self.ed_mode { "(dbg) " } else { "" });
             io::stdout().flush().unwrap();
@@ -92,39 +104,39 @@ impl Debugger {
                 let mut prev_vbl_check = false;
                 loop {
                     // Continue running
-                    self.fc.cpu.fetch_and_run();
+                    self.fc.step();
 
                     let cpu_cycles_after = self.fc.cpu.cycles();
-                    let ppu_cycles_after = self.fc.cpu.ppu.cycles();
+                    let ppu_cycles_after = self.fc.bus.ppu.cycles();
 
-                    if self.fc.cpu.ppu.just_finished_rendering() && !prev_vbl_check {
+                    if self.fc.bus.ppu.just_finished_rendering() && !prev_vbl_check {
                         let end = start.elapsed();
                         info!("Time: {:.2?}", end);
                         start = std::time::Instant::now();
                     }
-                    prev_vbl_check = self.fc.cpu.ppu.just_finished_rendering();
+                    prev_vbl_check = self.fc.bus.ppu.just_finished_rendering();
 
                     if !self.breakpoints.is_empty() {
                         let addr_break = Breakpoint::Address(self.fc.cpu.pc());
-                        let scan_break = Breakpoint::Scanline(self.fc.cpu.ppu.scanlines());
+                        let scan_break = Breakpoint::Scanline(self.fc.bus.ppu.scanlines());
                         let cpu_cyc_break = Breakpoint::CPUCycle(cpu_cycles_after);
                         let ppu_cyc_break = Breakpoint::PPUCycle(ppu_cycles_after);
 
                         if self.breakpoints.contains(&addr_break) {
                             println!("Hit breakpoint {}", addr_break);
-                            self.fc.cpu.print_state();
+                            self.fc.print_state();
                             break;
                         } else if self.breakpoints.contains(&scan_break) {
                             println!("Hit breakpoint {}", scan_break);
-                            self.fc.cpu.print_state();
+                            self.fc.print_state();
                             break;
                         } else if self.breakpoints.contains(&cpu_cyc_break) {
                             println!("Hit breakpoint {}", cpu_cyc_break);
-                            self.fc.cpu.print_state();
+                            self.fc.print_state();
                             break;
                         } else if self.breakpoints.contains(&ppu_cyc_break) {
                             println!("Hit breakpoint {}", ppu_cyc_break);
-                            self.fc.cpu.print_state();
+                            self.fc.print_state();
                             break;
                         }
                     }
@@ -135,24 +147,24 @@ impl Debugger {
                 // TODO: handle breakpoints as well?
                 self.fc.run_until_render_done();
 
-                let nametable_buf = self.fc.cpu.ppu.generate_nametables_image_temp(&mut self.fc.cpu.mem);
+                let nametable_buf = self.fc.bus.ppu.generate_nametables_image_temp(&self.fc.bus.mem);
                 // TODO: remove this so we don't need 100 extra dependencies...
                 let img_w = 256;
                 let img_h = 240;
                 let img: RgbImage = RgbImage::from_raw(img_w * 2, img_h * 2, nametable_buf.to_vec()).unwrap();
                 img.save(Path::new("nametables.png")).unwrap();
 
-                let frame_buf = self.fc.cpu.ppu.get_frame_buf();
+                let frame_buf = self.fc.bus.ppu.get_frame_buf();
                 let img: RgbImage = RgbImage::from_raw(img_w, img_h, frame_buf.to_vec()).unwrap();
                 img.save(Path::new("frame.png")).unwrap();
 
-                self.fc.cpu.print_state();
+                self.fc.print_state();
                 Ok(())
             }
             ["s"] => {
                 // Step cpu forward 1 instruction
-                self.fc.cpu.fetch_and_run_dbg();
-                self.fc.cpu.print_state();
+                self.fc.step_dbg();
+                self.fc.print_state();
                 Ok(())
             }
             ["b" | "break", addr] => {
@@ -179,7 +191,7 @@ impl Debugger {
             }
             ["p" | "print"] => {
                 // Print cpu info
-                self.fc.cpu.print_state();
+                self.fc.print_state();
                 Ok(())
             }
             ["r" | "hr" | "reload" | "run"] | ["hard", "reset"] => {
@@ -205,6 +217,94 @@ impl Debugger {
                 }
             }
             ["load", ..] => Err(String::from("Usage: load <filen.nes>")),
+            ["state", "save", filename] => {
+                self.fc.save_state_to_file(Path::new(filename)).map_err(|e| format!("Could not save state: {e}"))
+            }
+            ["state", "load", filename] => {
+                self.fc.load_state_from_file(Path::new(filename)).map_err(|e| format!("Could not load state: {e}"))?;
+                self.fc.print_state();
+                Ok(())
+            }
+            ["state", ..] => Err(String::from("Usage: state [save|load] <file>")),
+            ["mix" | "mixer"] => {
+                self.fc.apu().print_mixing_state();
+                Ok(())
+            }
+            ["mix" | "mixer", "reset"] => {
+                self.fc.apu_mut().reset_channel_mixing();
+                Ok(())
+            }
+            ["mute", "all"] => {
+                Channel::ALL.iter().for_each(|&ch| self.fc.apu_mut().set_channel_muted(ch, true));
+                Ok(())
+            }
+            ["unmute", "all"] => {
+                Channel::ALL.iter().for_each(|&ch| self.fc.apu_mut().set_channel_muted(ch, false));
+                Ok(())
+            }
+            ["mute", ch] => {
+                self.fc.apu_mut().set_channel_muted(ch.parse()?, true);
+                Ok(())
+            }
+            ["unmute", ch] => {
+                self.fc.apu_mut().set_channel_muted(ch.parse()?, false);
+                Ok(())
+            }
+            ["mute" | "unmute", ..] => Err(String::from("Usage: [mute|unmute] [<channel>|all]")),
+            ["solo", "off"] | ["unsolo"] => {
+                self.fc.apu_mut().set_solo_channel(None);
+                Ok(())
+            }
+            ["solo", ch] => {
+                self.fc.apu_mut().set_solo_channel(Some(ch.parse()?));
+                Ok(())
+            }
+            ["solo", ..] => Err(String::from("Usage: solo [<channel>|off]")),
+            ["gain", ch, val] => {
+                let gain = val.parse().map_err(|_| format!("Invalid gain: `{val}`"))?;
+                self.fc.apu_mut().set_channel_gain(ch.parse()?, gain);
+                Ok(())
+            }
+            ["gain", ..] => Err(String::from(
+                "Usage: gain <channel> <value>\n\
+                 Channels: pulse1, pulse2, triangle, noise, dmc, expansion",
+            )),
+            ["align" | "alignment"] => {
+                println!("CPU/PPU alignment: {} (takes effect on hard reset)", self.fc.cpu_ppu_alignment());
+                Ok(())
+            }
+            ["align" | "alignment", val] => {
+                match val.parse() {
+                    Ok(alignment) => {
+                        self.fc.set_cpu_ppu_alignment(alignment);
+                        Ok(())
+                    }
+                    Err(_) => Err(format!("Invalid alignment: `{val}`")),
+                }
+            }
+            ["align" | "alignment", ..] => Err(String::from("Usage: align <alignment>")),
+            ["region"] => {
+                let source = if self.fc.region_override().is_some() { "manual" } else { "auto" };
+                println!("Region: {} ({source})", self.fc.region());
+                Ok(())
+            }
+            ["region", "auto"] => self.set_region_override(None),
+            ["region", region] => self.set_region_override(Some(region.parse()?)),
+            ["region", ..] => Err(String::from("Usage: region [ntsc|pal|dendy|auto]")),
+            ["wav"] => {
+                match self.fc.wav_recording_path() {
+                    Some(path) => println!("Recording audio to {}", path.display()),
+                    None => println!("Not recording audio"),
+                }
+                Ok(())
+            }
+            ["wav", "stop"] => {
+                self.fc.stop_wav_recording().map_err(|e| format!("Failed to finish the recording: {e}"))
+            }
+            ["wav", filename] => {
+                self.start_wav_recording(Path::new(filename))
+            }
+            ["wav", ..] => Err(String::from("Usage: wav [<file.wav>|stop]")),
             ["x", mem_type, addr] => self.examine(mem_type, addr),
             ["x", addr] => self.examine("cpu", addr),
             ["x", ..] => Err(String::from("Usage: x $<address>")),
@@ -237,13 +337,13 @@ impl Debugger {
         match mem_type {
             "c" | "cpu" => {
                 let from_addr = (try_parse_hex(addr)? & 0xfff0) as u32;
-                let f = |a| self.fc.cpu.read_addr_nocycle(a);
+                let f = |a| self.fc.bus.read_no_sideeffect(a);
                 self.print_mem_region(from_addr, from_addr + 0x30, f);
                 Ok(())
             },
             "p" | "ppu" => {
                 let from_addr = (try_parse_hex(addr)? & 0xfff0) as u32;
-                let f = |a| self.fc.cpu.read_addr_ppu_no_sideeffect(a);
+                let f = |a| self.fc.bus.read_ppu_no_sideeffect(a);
                 self.print_mem_region(from_addr, from_addr + 0x30, f);
                 Ok(())
             },
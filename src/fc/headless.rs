@@ -0,0 +1,182 @@
+//! Running movies without a GUI (or debugger), hashing the output for regression testing.
+//!
+//! The hashes of a run are written as one line per hashed frame, `<frame> <video hash> <audio hash>` (both hashes as
+//! 16 hex digits), which is also the format of the golden files they are compared against.
+
+use std::fmt::Display;
+use std::io::{Error, ErrorKind};
+
+use super::FC;
+use super::apu::DEFAULT_SAMPLE_RATE;
+use super::movie::Movie;
+use super::state::{FNV1A_OFFSET_BASIS, fnv1a_hash, fnv1a_update};
+use super::wav::quantize_sample;
+
+/// The output hashes at a single frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameHash {
+    /// The frame number, starting at 1 for the first frame run.
+    pub frame: usize,
+    /// Hash of the frame buffer.
+    pub video: u64,
+    /// Hash of all audio samples generated up to (and including) this frame, as 16-bit PCM.
+    pub audio: u64,
+}
+
+impl Display for FrameHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {:016x} {:016x}", self.frame, self.video, self.audio)
+    }
+}
+
+/// The result of [run_movie].
+pub struct MovieRun {
+    pub hashes: Vec<FrameHash>,
+    /// The first frame the movie desynced at (if any.)
+    pub desync_frame: Option<usize>,
+}
+
+/// Play back `movie` for `frames` frames, hashing the output after each frame in `hash_frames`.
+///
+/// If the movie is shorter than `frames`, the remaining frames are run without any input.
+pub fn run_movie(fc: &mut FC, movie: Movie, frames: usize, hash_frames: &[usize]) -> Result<MovieRun, Error> {
+    // The samples depend on the sample rate, so always use the same one
+    fc.set_audio_sample_rate(DEFAULT_SAMPLE_RATE);
+    fc.start_movie_playback(movie)?;
+    fc.take_audio_samples();
+
+    let mut hashes = Vec::new();
+    let mut desync_frame = None;
+    let mut audio_hash = FNV1A_OFFSET_BASIS;
+
+    for frame in 1..=frames {
+        fc.run_until_render_done();
+
+        // Quantized like a .wav recording, so tiny differences in floating point math between platforms (e.g. in the
+        // resampler's kernel) don't change the hash
+        for sample in fc.take_audio_samples() {
+            audio_hash = fnv1a_update(audio_hash, &quantize_sample(sample).to_le_bytes());
+        }
+        if desync_frame.is_none() {
+            desync_frame = fc.movie_status().and_then(|s| s.desync_frame);
+        }
+
+        if hash_frames.contains(&frame) {
+            hashes.push(FrameHash {
+                frame,
+                video: fnv1a_hash(fc.get_frame()),
+                audio: audio_hash,
+            });
+        }
+    }
+
+    fc.stop_movie();
+    Ok(MovieRun { hashes, desync_frame })
+}
+
+/// Format hashes as written to golden files.
+pub fn format_hashes(hashes: &[FrameHash]) -> String {
+    hashes.iter().map(|h| format!("{h}\n")).collect()
+}
+
+/// Parse hashes written by [format_hashes]. Empty lines and lines starting with `#` are ignored.
+pub fn parse_hashes(text: &str) -> Result<Vec<FrameHash>, Error> {
+    let invalid = |line: &str| Error::new(ErrorKind::InvalidData, format!("invalid frame hash `{line}`"));
+
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let [frame, video, audio] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return Err(invalid(line));
+            };
+            Ok(FrameHash {
+                frame: frame.parse().map_err(|_| invalid(line))?,
+                video: u64::from_str_radix(video, 16).map_err(|_| invalid(line))?,
+                audio: u64::from_str_radix(audio, 16).map_err(|_| invalid(line))?,
+            })
+        })
+        .collect()
+}
+
+/// Compare the hashes of a run against the expected ones, describing every difference.
+pub fn compare_hashes(expected: &[FrameHash], actual: &[FrameHash]) -> Result<(), String> {
+    let mut errors = Vec::new();
+
+    for exp in expected {
+        match actual.iter().find(|h| h.frame == exp.frame) {
+            None => errors.push(format!("frame {}: not hashed", exp.frame)),
+            Some(act) => {
+                if act.video != exp.video {
+                    errors.push(format!("frame {}: video hash {:016x}, expected {:016x}", exp.frame, act.video, exp.video));
+                }
+                if act.audio != exp.audio {
+                    errors.push(format!("frame {}: audio hash {:016x}, expected {:016x}", exp.frame, act.audio, exp.audio));
+                }
+            }
+        }
+    }
+    for act in actual.iter().filter(|a| !expected.iter().any(|e| e.frame == a.frame)) {
+        errors.push(format!("frame {}: missing from the expected hashes", act.frame));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::fc::movie::fm2;
+    use crate::fc::test::{INPUT_PROGRAM, INPUT_PROGRAM_NMI, test_rom};
+
+    const GOLDEN_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/headless_input.txt");
+
+    /// Plays back `tests/golden/headless_input.fm2`, comparing the output with the golden hashes.
+    ///
+    /// Run with `RFCE_UPDATE_GOLDEN=1` to write the current hashes to the golden file instead (after checking that the
+    /// change in output is intended!)
+    #[test]
+    fn headless_movie_golden_test() {
+        let path = test_rom("headless", &INPUT_PROGRAM, INPUT_PROGRAM_NMI);
+        let mut fc = FC::from_file(&path).unwrap();
+        fc.init();
+
+        let movie_text = include_str!("../../tests/golden/headless_input.fm2");
+        let movie = fm2::import(movie_text, fc.cart.as_ref().unwrap().hash(), [0; 16]).unwrap();
+        let hash_frames: Vec<usize> = (30..=120).step_by(30).collect();
+        let run = run_movie(&mut fc, movie, 120, &hash_frames).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(run.desync_frame, None);
+        assert_eq!(run.hashes.len(), 4);
+        // The input changes the output
+        assert_ne!(run.hashes[0].video, run.hashes[1].video);
+
+        if std::env::var_os("RFCE_UPDATE_GOLDEN").is_some() {
+            fs::write(GOLDEN_PATH, format_hashes(&run.hashes)).unwrap();
+            return;
+        }
+        let expected = parse_hashes(&fs::read_to_string(GOLDEN_PATH).unwrap()).unwrap();
+        if let Err(e) = compare_hashes(&expected, &run.hashes) {
+            panic!("output differs from {GOLDEN_PATH}:\n{e}");
+        }
+    }
+
+    #[test]
+    fn compare_hashes_test() {
+        let hash = |frame, video, audio| FrameHash { frame, video, audio };
+        let expected = vec![hash(1, 0x11, 0x22), hash(2, 0x33, 0x44)];
+
+        assert_eq!(parse_hashes(&format_hashes(&expected)).unwrap(), expected);
+        assert!(compare_hashes(&expected, &expected).is_ok());
+        assert!(compare_hashes(&expected, &[hash(1, 0x11, 0x22), hash(2, 0x33, 0x45)]).is_err());
+        assert!(compare_hashes(&expected, &[hash(1, 0x11, 0x22)]).is_err());
+        assert!(parse_hashes("1 zz 00").is_err());
+    }
+}
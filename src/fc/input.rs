@@ -1,6 +1,10 @@
+use std::io::Error;
+
 use log::debug;
 
-#[derive(Clone, Copy, Default)]
+use super::state::{Snapshot, StateReader, StateWriter};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct StandardControllerState {
     pub a: bool,
     pub b: bool,
@@ -12,6 +16,34 @@ pub struct StandardControllerState {
     pub right: bool,
 }
 
+impl StandardControllerState {
+    /// The buttons as the byte shifted out by the controller: A, B, Select, Start, Up, Down, Left, Right (from bit 0
+    /// to bit 7.)
+    pub fn to_bits(&self) -> u8 {
+        (self.a as u8)
+            | (self.b as u8) << 1
+            | (self.select as u8) << 2
+            | (self.start as u8) << 3
+            | (self.up as u8) << 4
+            | (self.down as u8) << 5
+            | (self.left as u8) << 6
+            | (self.right as u8) << 7
+    }
+
+    pub fn from_bits(bits: u8) -> StandardControllerState {
+        StandardControllerState {
+            a: bits & 1 != 0,
+            b: bits & (1 << 1) != 0,
+            select: bits & (1 << 2) != 0,
+            start: bits & (1 << 3) != 0,
+            up: bits & (1 << 4) != 0,
+            down: bits & (1 << 5) != 0,
+            left: bits & (1 << 6) != 0,
+            right: bits & (1 << 7) != 0,
+        }
+    }
+}
+
 pub struct Controller {
     controller_latch: bool,
     expansion_latch: u8,
@@ -67,43 +99,23 @@ impl Controller {
     }
 
     pub fn update_from_controller_state(&mut self, joy1_state: StandardControllerState, joy2_state: StandardControllerState) {
-        self.joy1_tmp = (joy1_state.a as u8)
-                      | (joy1_state.b as u8) << 1
-                      | (joy1_state.select as u8) << 2
-                      | (joy1_state.start as u8) << 3
-                      | (joy1_state.up as u8) << 4
-                      | (joy1_state.down as u8) << 5
-                      | (joy1_state.left as u8) << 6
-                      | (joy1_state.right as u8) << 7;
-
-        if self.joy1_tmp & 0b11000000 == 0b11000000 {
-            // Forbid left + right input
-            self.joy1_tmp &= 0b111111;
-        }
-        if self.joy1_tmp & 0b00110000 == 0b00110000 {
-            // Forbid up + down input
-            self.joy1_tmp &= 0b11001111;
-        }
-
+        self.joy1_tmp = Self::forbid_opposite_directions(joy1_state.to_bits());
         debug!("wrote {:02x} to joy1_tmp", self.joy1_tmp);
 
-        self.joy2_tmp = (joy2_state.a as u8)
-                      | (joy2_state.b as u8) << 1
-                      | (joy2_state.select as u8) << 2
-                      | (joy2_state.start as u8) << 3
-                      | (joy2_state.up as u8) << 4
-                      | (joy2_state.down as u8) << 5
-                      | (joy2_state.left as u8) << 6
-                      | (joy2_state.right as u8) << 7;
-        if self.joy2_tmp & 0b11000000 == 0b11000000 {
+        self.joy2_tmp = Self::forbid_opposite_directions(joy2_state.to_bits());
+        debug!("wrote {:02x} to joy2_tmp", self.joy2_tmp);
+    }
+
+    fn forbid_opposite_directions(mut bits: u8) -> u8 {
+        if bits & 0b11000000 == 0b11000000 {
             // Forbid left + right input
-            self.joy2_tmp &= 0b111111;
+            bits &= 0b111111;
         }
-        if self.joy2_tmp & 0b00110000 == 0b00110000 {
+        if bits & 0b00110000 == 0b00110000 {
             // Forbid up + down input
-            self.joy2_tmp &= 0b11001111;
+            bits &= 0b11001111;
         }
-        debug!("wrote {:02x} to joy2_tmp", self.joy2_tmp);
+        bits
     }
 
     define_read_fn!(read_joy1, joy1, 1);
@@ -133,3 +145,26 @@ impl Controller {
     }
 
 }
+
+impl Snapshot for Controller {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.controller_latch);
+        w.write(&self.expansion_latch);
+        w.write(&self.joy1);
+        w.write(&self.joy2);
+        w.write(&self.joy1_tmp);
+        w.write(&self.joy2_tmp);
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.controller_latch = r.read()?;
+        self.expansion_latch = r.read()?;
+        self.joy1 = r.read()?;
+        self.joy2 = r.read()?;
+        self.joy1_tmp = r.read()?;
+        self.joy2_tmp = r.read()?;
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
@@ -1,14 +1,23 @@
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{Error, ErrorKind, Read, Write};
 
 use cart::NESFile;
 use log::{info, warn};
 use mapper::nrom::NROMMapper;
 use mapper::{Mapper, RealMapper};
 
+use crate::fc::apu::{APU, ExpansionChip};
 use crate::fc::input::Controller;
 use crate::fc::mem::mapper::mmc1::MMC1Mapper;
+use crate::fc::mem::mapper::mmc2::MMC2Mapper;
 use crate::fc::mem::mapper::mmc3::MMC3Mapper;
+use crate::fc::mem::mapper::mmc5::MMC5Mapper;
+use crate::fc::mem::mapper::uxrom::UxROMMapper;
+use crate::fc::mem::mapper::cnrom::CNROMMapper;
+use crate::fc::mem::mapper::discrete_latch::DiscreteLatchMapper;
+use crate::fc::mem::mapper::axrom::AxROMMapper;
+use crate::fc::mem::mapper::bnrom::BNROMMapper;
+use crate::fc::state::{Snapshot, StateReader, StateValue, StateWriter};
 
 pub mod cart;
 pub mod mapper;
@@ -20,12 +29,23 @@ const MAPPER_SPACE: usize = 0x10000 - MAPPER_START_ADDRESS;
 pub(crate) enum NametableArrangement {
     HorizontalMirroring,
     VerticalMirroring,
+    /// All nametables show the lower 1KiB of VRAM.
     SingleScreenA,
+    /// All nametables show the upper 1KiB of VRAM.
     SingleScreenB,
     FourScreen,
 }
 
 impl NametableArrangement {
+    /// The fixed (soldered) arrangement of a cartridge, according to its header.
+    pub(crate) fn from_header(nesfile: &NESFile) -> NametableArrangement {
+        if nesfile.nametable_layout() {
+            NametableArrangement::VerticalMirroring
+        } else {
+            NametableArrangement::HorizontalMirroring
+        }
+    }
+
     fn nametable_addr_fix(self: NametableArrangement, addr: u16) -> u16 {
         let a = addr - 0x2000;
         match self {
@@ -38,6 +58,23 @@ impl NametableArrangement {
     }
 }
 
+impl StateValue for NametableArrangement {
+    fn write_to(&self, w: &mut StateWriter) {
+        w.write(&(*self as u8));
+    }
+
+    fn read_from(r: &mut StateReader) -> Result<Self, Error> {
+        match r.read::<u8>()? {
+            0 => Ok(NametableArrangement::HorizontalMirroring),
+            1 => Ok(NametableArrangement::VerticalMirroring),
+            2 => Ok(NametableArrangement::SingleScreenA),
+            3 => Ok(NametableArrangement::SingleScreenB),
+            4 => Ok(NametableArrangement::FourScreen),
+            n => Err(Error::new(ErrorKind::InvalidData, format!("invalid nametable arrangement {n} in save state"))),
+        }
+    }
+}
+
 pub trait Memory {
     fn read(&mut self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, val: u8) -> ();
@@ -78,11 +115,27 @@ impl Mapper for DummyMapper {
     }
 }
 
+impl Snapshot for DummyMapper {
+    fn save_state(&self, _w: &mut StateWriter) {}
+
+    fn load_state(&mut self, _r: &mut StateReader) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
 pub enum MapperImpl {
     DUMMY(DummyMapper),
     NROM(NROMMapper),
     MMC1(MMC1Mapper),
+    MMC2(MMC2Mapper),
     MMC3(MMC3Mapper),
+    MMC5(MMC5Mapper),
+    UxROM(UxROMMapper),
+    CNROM(CNROMMapper),
+    /// GxROM and Color Dreams.
+    DiscreteLatch(DiscreteLatchMapper),
+    AxROM(AxROMMapper),
+    BNROM(BNROMMapper),
 }
 
 impl Mapper for MapperImpl {
@@ -91,7 +144,14 @@ impl Mapper for MapperImpl {
             MapperImpl::DUMMY(m) => m.read_no_sideeffect(addr),
             MapperImpl::NROM(m)   => m.read_no_sideeffect(addr),
             MapperImpl::MMC1(m)   => m.read_no_sideeffect(addr),
+            MapperImpl::MMC2(m)   => m.read_no_sideeffect(addr),
             MapperImpl::MMC3(m)   => m.read_no_sideeffect(addr),
+            MapperImpl::MMC5(m)   => m.read_no_sideeffect(addr),
+            MapperImpl::UxROM(m)  => m.read_no_sideeffect(addr),
+            MapperImpl::CNROM(m)  => m.read_no_sideeffect(addr),
+            MapperImpl::DiscreteLatch(m) => m.read_no_sideeffect(addr),
+            MapperImpl::AxROM(m)  => m.read_no_sideeffect(addr),
+            MapperImpl::BNROM(m)  => m.read_no_sideeffect(addr),
         }
     }
 
@@ -100,7 +160,14 @@ impl Mapper for MapperImpl {
             MapperImpl::DUMMY(m) => m.read_chr(addr),
             MapperImpl::NROM(m)   => m.read_chr(addr),
             MapperImpl::MMC1(m)   => m.read_chr(addr),
+            MapperImpl::MMC2(m)   => m.read_chr(addr),
             MapperImpl::MMC3(m)   => m.read_chr(addr),
+            MapperImpl::MMC5(m)   => m.read_chr(addr),
+            MapperImpl::UxROM(m)  => m.read_chr(addr),
+            MapperImpl::CNROM(m)  => m.read_chr(addr),
+            MapperImpl::DiscreteLatch(m) => m.read_chr(addr),
+            MapperImpl::AxROM(m)  => m.read_chr(addr),
+            MapperImpl::BNROM(m)  => m.read_chr(addr),
         }
     }
 
@@ -109,7 +176,14 @@ impl Mapper for MapperImpl {
             MapperImpl::DUMMY(m) => m.write_chr(addr, val),
             MapperImpl::NROM(m)   => m.write_chr(addr, val),
             MapperImpl::MMC1(m)   => m.write_chr(addr, val),
+            MapperImpl::MMC2(m)   => m.write_chr(addr, val),
             MapperImpl::MMC3(m)   => m.write_chr(addr, val),
+            MapperImpl::MMC5(m)   => m.write_chr(addr, val),
+            MapperImpl::UxROM(m)  => m.write_chr(addr, val),
+            MapperImpl::CNROM(m)  => m.write_chr(addr, val),
+            MapperImpl::DiscreteLatch(m) => m.write_chr(addr, val),
+            MapperImpl::AxROM(m)  => m.write_chr(addr, val),
+            MapperImpl::BNROM(m)  => m.write_chr(addr, val),
         }
     }
 
@@ -118,7 +192,14 @@ impl Mapper for MapperImpl {
             MapperImpl::DUMMY(m) => m.nametable_read(addr, vram),
             MapperImpl::NROM(m) => m.nametable_read(addr, vram),
             MapperImpl::MMC1(m) => m.nametable_read(addr, vram),
+            MapperImpl::MMC2(m) => m.nametable_read(addr, vram),
             MapperImpl::MMC3(m) => m.nametable_read(addr, vram),
+            MapperImpl::MMC5(m) => m.nametable_read(addr, vram),
+            MapperImpl::UxROM(m) => m.nametable_read(addr, vram),
+            MapperImpl::CNROM(m) => m.nametable_read(addr, vram),
+            MapperImpl::DiscreteLatch(m) => m.nametable_read(addr, vram),
+            MapperImpl::AxROM(m) => m.nametable_read(addr, vram),
+            MapperImpl::BNROM(m) => m.nametable_read(addr, vram),
         }
     }
 
@@ -127,7 +208,160 @@ impl Mapper for MapperImpl {
             MapperImpl::DUMMY(m) => m.nametable_write(addr, val, vram),
             MapperImpl::NROM(m)   => m.nametable_write(addr, val, vram),
             MapperImpl::MMC1(m)   => m.nametable_write(addr, val, vram),
+            MapperImpl::MMC2(m)   => m.nametable_write(addr, val, vram),
             MapperImpl::MMC3(m)   => m.nametable_write(addr, val, vram),
+            MapperImpl::MMC5(m)   => m.nametable_write(addr, val, vram),
+            MapperImpl::UxROM(m)  => m.nametable_write(addr, val, vram),
+            MapperImpl::CNROM(m)  => m.nametable_write(addr, val, vram),
+            MapperImpl::DiscreteLatch(m) => m.nametable_write(addr, val, vram),
+            MapperImpl::AxROM(m)  => m.nametable_write(addr, val, vram),
+            MapperImpl::BNROM(m)  => m.nametable_write(addr, val, vram),
+        }
+    }
+
+    fn pattern_fetched(&mut self, addr: u16) -> () {
+        match self {
+            MapperImpl::DUMMY(m) => m.pattern_fetched(addr),
+            MapperImpl::NROM(m)   => m.pattern_fetched(addr),
+            MapperImpl::MMC1(m)   => m.pattern_fetched(addr),
+            MapperImpl::MMC2(m)   => m.pattern_fetched(addr),
+            MapperImpl::MMC3(m)   => m.pattern_fetched(addr),
+            MapperImpl::MMC5(m)   => m.pattern_fetched(addr),
+            MapperImpl::UxROM(m)  => m.pattern_fetched(addr),
+            MapperImpl::CNROM(m)  => m.pattern_fetched(addr),
+            MapperImpl::DiscreteLatch(m) => m.pattern_fetched(addr),
+            MapperImpl::AxROM(m)  => m.pattern_fetched(addr),
+            MapperImpl::BNROM(m)  => m.pattern_fetched(addr),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> () {
+        match self {
+            MapperImpl::DUMMY(m) => m.ppu_read(addr),
+            MapperImpl::NROM(m)   => m.ppu_read(addr),
+            MapperImpl::MMC1(m)   => m.ppu_read(addr),
+            MapperImpl::MMC2(m)   => m.ppu_read(addr),
+            MapperImpl::MMC3(m)   => m.ppu_read(addr),
+            MapperImpl::MMC5(m)   => m.ppu_read(addr),
+            MapperImpl::UxROM(m)  => m.ppu_read(addr),
+            MapperImpl::CNROM(m)  => m.ppu_read(addr),
+            MapperImpl::DiscreteLatch(m) => m.ppu_read(addr),
+            MapperImpl::AxROM(m)  => m.ppu_read(addr),
+            MapperImpl::BNROM(m)  => m.ppu_read(addr),
+        }
+    }
+
+    fn ppu_register_written(&mut self, addr: u16, val: u8) -> () {
+        match self {
+            MapperImpl::DUMMY(m) => m.ppu_register_written(addr, val),
+            MapperImpl::NROM(m)   => m.ppu_register_written(addr, val),
+            MapperImpl::MMC1(m)   => m.ppu_register_written(addr, val),
+            MapperImpl::MMC2(m)   => m.ppu_register_written(addr, val),
+            MapperImpl::MMC3(m)   => m.ppu_register_written(addr, val),
+            MapperImpl::MMC5(m)   => m.ppu_register_written(addr, val),
+            MapperImpl::UxROM(m)  => m.ppu_register_written(addr, val),
+            MapperImpl::CNROM(m)  => m.ppu_register_written(addr, val),
+            MapperImpl::DiscreteLatch(m) => m.ppu_register_written(addr, val),
+            MapperImpl::AxROM(m)  => m.ppu_register_written(addr, val),
+            MapperImpl::BNROM(m)  => m.ppu_register_written(addr, val),
+        }
+    }
+
+    fn vblank_started(&mut self) -> () {
+        match self {
+            MapperImpl::DUMMY(m) => m.vblank_started(),
+            MapperImpl::NROM(m)   => m.vblank_started(),
+            MapperImpl::MMC1(m)   => m.vblank_started(),
+            MapperImpl::MMC2(m)   => m.vblank_started(),
+            MapperImpl::MMC3(m)   => m.vblank_started(),
+            MapperImpl::MMC5(m)   => m.vblank_started(),
+            MapperImpl::UxROM(m)  => m.vblank_started(),
+            MapperImpl::CNROM(m)  => m.vblank_started(),
+            MapperImpl::DiscreteLatch(m) => m.vblank_started(),
+            MapperImpl::AxROM(m)  => m.vblank_started(),
+            MapperImpl::BNROM(m)  => m.vblank_started(),
+        }
+    }
+
+    fn expansion_audio_chip(&self) -> Option<ExpansionChip> {
+        match self {
+            MapperImpl::DUMMY(m) => m.expansion_audio_chip(),
+            MapperImpl::NROM(m)   => m.expansion_audio_chip(),
+            MapperImpl::MMC1(m)   => m.expansion_audio_chip(),
+            MapperImpl::MMC2(m)   => m.expansion_audio_chip(),
+            MapperImpl::MMC3(m)   => m.expansion_audio_chip(),
+            MapperImpl::MMC5(m)   => m.expansion_audio_chip(),
+            MapperImpl::UxROM(m)  => m.expansion_audio_chip(),
+            MapperImpl::CNROM(m)  => m.expansion_audio_chip(),
+            MapperImpl::DiscreteLatch(m) => m.expansion_audio_chip(),
+            MapperImpl::AxROM(m)  => m.expansion_audio_chip(),
+            MapperImpl::BNROM(m)  => m.expansion_audio_chip(),
+        }
+    }
+
+    fn clock_expansion_audio(&mut self) -> () {
+        match self {
+            MapperImpl::DUMMY(m) => m.clock_expansion_audio(),
+            MapperImpl::NROM(m)   => m.clock_expansion_audio(),
+            MapperImpl::MMC1(m)   => m.clock_expansion_audio(),
+            MapperImpl::MMC2(m)   => m.clock_expansion_audio(),
+            MapperImpl::MMC3(m)   => m.clock_expansion_audio(),
+            MapperImpl::MMC5(m)   => m.clock_expansion_audio(),
+            MapperImpl::UxROM(m)  => m.clock_expansion_audio(),
+            MapperImpl::CNROM(m)  => m.clock_expansion_audio(),
+            MapperImpl::DiscreteLatch(m) => m.clock_expansion_audio(),
+            MapperImpl::AxROM(m)  => m.clock_expansion_audio(),
+            MapperImpl::BNROM(m)  => m.clock_expansion_audio(),
+        }
+    }
+
+    fn expansion_audio_output(&self) -> f32 {
+        match self {
+            MapperImpl::DUMMY(m) => m.expansion_audio_output(),
+            MapperImpl::NROM(m)   => m.expansion_audio_output(),
+            MapperImpl::MMC1(m)   => m.expansion_audio_output(),
+            MapperImpl::MMC2(m)   => m.expansion_audio_output(),
+            MapperImpl::MMC3(m)   => m.expansion_audio_output(),
+            MapperImpl::MMC5(m)   => m.expansion_audio_output(),
+            MapperImpl::UxROM(m)  => m.expansion_audio_output(),
+            MapperImpl::CNROM(m)  => m.expansion_audio_output(),
+            MapperImpl::DiscreteLatch(m) => m.expansion_audio_output(),
+            MapperImpl::AxROM(m)  => m.expansion_audio_output(),
+            MapperImpl::BNROM(m)  => m.expansion_audio_output(),
+        }
+    }
+}
+
+impl Snapshot for MapperImpl {
+    fn save_state(&self, w: &mut StateWriter) {
+        match self {
+            MapperImpl::DUMMY(m) => m.save_state(w),
+            MapperImpl::NROM(m)   => m.save_state(w),
+            MapperImpl::MMC1(m)   => m.save_state(w),
+            MapperImpl::MMC2(m)   => m.save_state(w),
+            MapperImpl::MMC3(m)   => m.save_state(w),
+            MapperImpl::MMC5(m)   => m.save_state(w),
+            MapperImpl::UxROM(m)  => m.save_state(w),
+            MapperImpl::CNROM(m)  => m.save_state(w),
+            MapperImpl::DiscreteLatch(m) => m.save_state(w),
+            MapperImpl::AxROM(m)  => m.save_state(w),
+            MapperImpl::BNROM(m)  => m.save_state(w),
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        match self {
+            MapperImpl::DUMMY(m) => m.load_state(r),
+            MapperImpl::NROM(m)   => m.load_state(r),
+            MapperImpl::MMC1(m)   => m.load_state(r),
+            MapperImpl::MMC2(m)   => m.load_state(r),
+            MapperImpl::MMC3(m)   => m.load_state(r),
+            MapperImpl::MMC5(m)   => m.load_state(r),
+            MapperImpl::UxROM(m)  => m.load_state(r),
+            MapperImpl::CNROM(m)  => m.load_state(r),
+            MapperImpl::DiscreteLatch(m) => m.load_state(r),
+            MapperImpl::AxROM(m)  => m.load_state(r),
+            MapperImpl::BNROM(m)  => m.load_state(r),
         }
     }
 }
@@ -138,7 +372,14 @@ impl Memory for MapperImpl {
             MapperImpl::DUMMY(m) => m.read(addr),
             MapperImpl::NROM(m)   => m.read(addr),
             MapperImpl::MMC1(m)   => m.read(addr),
+            MapperImpl::MMC2(m)   => m.read(addr),
             MapperImpl::MMC3(m)   => m.read(addr),
+            MapperImpl::MMC5(m)   => m.read(addr),
+            MapperImpl::UxROM(m)  => m.read(addr),
+            MapperImpl::CNROM(m)  => m.read(addr),
+            MapperImpl::DiscreteLatch(m) => m.read(addr),
+            MapperImpl::AxROM(m)  => m.read(addr),
+            MapperImpl::BNROM(m)  => m.read(addr),
         }
     }
 
@@ -147,7 +388,14 @@ impl Memory for MapperImpl {
             MapperImpl::DUMMY(m) => m.write(addr, val),
             MapperImpl::NROM(m)   => m.write(addr, val),
             MapperImpl::MMC1(m)   => m.write(addr, val),
+            MapperImpl::MMC2(m)   => m.write(addr, val),
             MapperImpl::MMC3(m)   => m.write(addr, val),
+            MapperImpl::MMC5(m)   => m.write(addr, val),
+            MapperImpl::UxROM(m)  => m.write(addr, val),
+            MapperImpl::CNROM(m)  => m.write(addr, val),
+            MapperImpl::DiscreteLatch(m) => m.write(addr, val),
+            MapperImpl::AxROM(m)  => m.write(addr, val),
+            MapperImpl::BNROM(m)  => m.write(addr, val),
         }
     }
 }
@@ -181,6 +429,20 @@ impl Memory for MemMap {
     }
 }
 
+impl Snapshot for MemMap {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.ram);
+        self.input.save_state(w);
+        self.mapper.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.ram = r.read()?;
+        self.input.load_state(r)?;
+        self.mapper.load_state(r)
+    }
+}
+
 macro_rules! create_mapper {
     ($mapper_type:ident, $mapper_type_mapper:ident, $nesfile:expr) => {{
         let mapper = Box::new(MapperImpl::$mapper_type($mapper_type_mapper::from_nesfile($nesfile)));
@@ -224,15 +486,26 @@ impl MemMap {
         match nesfile.mapper_type() {
             mapper::MapperType::NROM => create_mapper!(NROM, NROMMapper, nesfile),
             mapper::MapperType::MMC1 => create_mapper!(MMC1, MMC1Mapper, nesfile),
-            mapper::MapperType::MMC2 => unsupported_mapper!("MMC2"),
+            mapper::MapperType::UxROM => create_mapper!(UxROM, UxROMMapper, nesfile),
+            mapper::MapperType::CNROM => create_mapper!(CNROM, CNROMMapper, nesfile),
+            mapper::MapperType::GxROM => create_mapper!(DiscreteLatch, DiscreteLatchMapper, nesfile),
+            mapper::MapperType::ColorDreams => create_mapper!(DiscreteLatch, DiscreteLatchMapper, nesfile),
+            mapper::MapperType::AxROM => create_mapper!(AxROM, AxROMMapper, nesfile),
+            mapper::MapperType::BNROM => create_mapper!(BNROM, BNROMMapper, nesfile),
+            mapper::MapperType::MMC2 => create_mapper!(MMC2, MMC2Mapper, nesfile),
             mapper::MapperType::MMC3 => create_mapper!(MMC3, MMC3Mapper, nesfile),
-            mapper::MapperType::MMC4 => unsupported_mapper!("MMC4"),
-            mapper::MapperType::MMC5 => unsupported_mapper!("MMC5"),
+            mapper::MapperType::MMC4 => create_mapper!(MMC2, MMC2Mapper, nesfile),
+            mapper::MapperType::MMC5 => create_mapper!(MMC5, MMC5Mapper, nesfile),
             mapper::MapperType::MMC6 => unsupported_mapper!("MMC6"),
             mapper::MapperType::UNKNOWN(i) => unsupported_mapper!(format!("{i:03}")),
         }
     }
 
+    /// The internal 2KB of CPU RAM.
+    pub(crate) fn ram(&self) -> &[u8; 0x800] {
+        &self.ram
+    }
+
     pub(super) fn print_state(&self) -> () {
         match self.mapper.as_ref() {
             MapperImpl::MMC3(m) => m.print_state(),
@@ -260,7 +533,7 @@ impl MemMap {
         }
     }
 
-    pub(crate) fn irq_triggered(&mut self) -> bool {
+    pub(crate) fn irq_triggered(&mut self, apu: &APU) -> bool {
         // Sources of IRQ:
         // - APU DMC finish
         // - APU frame counter
@@ -269,15 +542,13 @@ impl MemMap {
         // - FDS
         // - (other mappers)
 
-        // TODO
-        let apu_dmc = false;
+        let apu_dmc = apu.dmc_irq_triggered();
 
-        // TODO
-        let apu_frame_counter = false;
+        let apu_frame_counter = apu.frame_irq_triggered();
 
         let mapper = match self.mapper.as_ref() {
             MapperImpl::MMC3(m) => m.irq_triggered(),
-            // MBC5
+            MapperImpl::MMC5(m) => m.irq_triggered(),
             // MBC6
             // FDS
             _ => false,
@@ -289,7 +560,7 @@ impl MemMap {
     pub(crate) fn irq_un_trigger(&mut self) -> () {
         match self.mapper.as_mut() {
             MapperImpl::MMC3(m) => m.irq_un_trigger(),
-            // MBC5
+            // MMC5 (acknowledged by reading $5204 or $5010)
             // MBC6
             // FDS
             _ => {}
@@ -304,8 +575,9 @@ impl MemMap {
 
             match self.mapper.as_mut() {
                 MapperImpl::MMC1(m) => m.replace_sram(buf)?,
+                MapperImpl::MMC2(m) => m.replace_sram(buf)?,
                 MapperImpl::MMC3(m) => m.replace_sram(buf)?,
-                // MBC5
+                MapperImpl::MMC5(m) => m.replace_sram(buf)?,
                 // MBC6
                 // FDS
                 _ => unreachable!(),
@@ -318,8 +590,9 @@ impl MemMap {
     pub(crate) fn write_sram_to_file(&self, save_path: &std::path::Path) -> Result<(), std::io::Error>{
         let sram = match self.mapper.as_ref() {
             MapperImpl::MMC1(m) => Some(m.sram()),
+            MapperImpl::MMC2(m) => Some(m.sram()),
             MapperImpl::MMC3(m) => Some(m.sram()),
-            // MBC5
+            MapperImpl::MMC5(m) => Some(m.sram()),
             // MBC6
             // FDS
             _ => None,
@@ -336,7 +609,9 @@ impl MemMap {
     fn mapper_can_save(&self) -> bool {
         match self.mapper.as_ref() {
             MapperImpl::MMC1(m) => m.has_battery(),
+            MapperImpl::MMC2(m) => m.has_battery(),
             MapperImpl::MMC3(m) => m.has_battery(),
+            MapperImpl::MMC5(m) => m.has_battery(),
             _ => false,
         }
     }
@@ -346,7 +621,14 @@ impl MemMap {
         match self.mapper.as_mut() {
             MapperImpl::NROM(m) => m.open_bus = val,
             MapperImpl::MMC1(m) => m.open_bus = val,
+            MapperImpl::MMC2(m) => m.open_bus = val,
             MapperImpl::MMC3(m) => m.open_bus = val,
+            MapperImpl::MMC5(m) => m.open_bus = val,
+            MapperImpl::UxROM(m) => m.open_bus = val,
+            MapperImpl::CNROM(m) => m.open_bus = val,
+            MapperImpl::DiscreteLatch(m) => m.open_bus = val,
+            MapperImpl::AxROM(m) => m.open_bus = val,
+            MapperImpl::BNROM(m) => m.open_bus = val,
             _ => (),
         }
     }
@@ -5,7 +5,7 @@ use std::{
     fs::File, io::{self, Error, Read}, path::Path
 };
 
-use crate::{bits::Bitwise, fc::mem::mapper::MapperType};
+use crate::{bits::Bitwise, fc::{mem::mapper::MapperType, state::fnv1a_hash}};
 
 const NES_FILE_IDENTIFIER: [u8; 4] = [b'N', b'E', b'S', 0x1a];
 
@@ -48,6 +48,8 @@ impl NESFileHeader {
 pub struct NESFile {
     header: NESFileHeader,
     pub data: Vec<u8>,
+    /// See [NESFile::hash]. Computed once, as it's needed every time a state is saved or loaded.
+    hash: u64,
 }
 
 impl NESFile {
@@ -66,6 +68,7 @@ impl NESFile {
 
             Ok(NESFile {
                 header: header,
+                hash: fnv1a_hash(&data),
                 data: data,
             })
         } else {
@@ -73,10 +76,21 @@ impl NESFile {
         }
     }
 
+    /// A hash of the ROM contents (excluding the header, so fixing a bad header doesn't change it.)
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
     pub fn mapper_type(&self) -> MapperType {
         match self.mapper_number() {
             0 => MapperType::NROM,
             1 | 105 | 155 => MapperType::MMC1,
+            2 | 30 => MapperType::UxROM,
+            3 => MapperType::CNROM,
+            7 => MapperType::AxROM,
+            11 => MapperType::ColorDreams,
+            34 => MapperType::BNROM,
+            66 => MapperType::GxROM,
             9 => MapperType::MMC2,
             4 => {
                 match self.submapper_number() {
@@ -142,10 +156,10 @@ impl NESFile {
         }
     }
 
-    /// Get the submapper number stored in byte 7 of the header (NES2.0 only)
+    /// Get the submapper number stored in byte 8 of the header (NES2.0 only)
     pub fn submapper_number(&self) -> u8 {
         if self.is_nes20_format() {
-            (self.header.flags7 & 0xf0) >> 4
+            (self.header.flags8 & 0xf0) >> 4
         } else {
             0
         }
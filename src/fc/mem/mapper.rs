@@ -1,8 +1,17 @@
 pub mod nrom;
 pub mod mmc1;
+pub mod mmc2;
 pub mod mmc3;
+pub mod mmc5;
+pub mod uxrom;
+pub mod cnrom;
+pub mod gxrom;
+pub mod color_dreams;
+pub mod discrete_latch;
+pub mod axrom;
+pub mod bnrom;
 
-use crate::fc::{mem::cart::NESFile, ppu};
+use crate::fc::{apu::ExpansionChip, mem::cart::NESFile, ppu, state::Snapshot};
 
 use super::Memory;
 
@@ -10,6 +19,13 @@ use super::Memory;
 pub enum MapperType {
     NROM,
     MMC1,
+    UxROM,
+    CNROM,
+    GxROM,
+    ColorDreams,
+    AxROM,
+    /// Mapper 34, which is either BNROM or NINA-001.
+    BNROM,
     MMC2,
     MMC3,
     MMC4,
@@ -18,15 +34,68 @@ pub enum MapperType {
     UNKNOWN(u16),
 }
 
-pub trait Mapper : Memory {
+/// A cartridge mapper. Its state (bank registers, RAM, ...) has to be saved in save states, hence `Snapshot`.
+pub trait Mapper : Memory + Snapshot {
     fn read_chr(&self, addr: u16) -> u8;
     fn write_chr(&mut self, addr: u16, val: u8) -> ();
     fn nametable_read(&self, addr: u16, vram: [u8; ppu::VRAM_SIZE]) -> u8;
     fn nametable_write(&mut self, addr: u16, val: u8, vram: &mut [u8; ppu::VRAM_SIZE]) -> ();
     fn read_no_sideeffect(&self, addr: u16) -> u8;
+
+    /// Called with the address of every pattern table fetch made by the PPU while rendering (background and sprite
+    /// tiles), for mappers that switch banks based on the fetched tiles.
+    fn pattern_fetched(&mut self, _addr: u16) -> () {}
+
+    /// Called before every PPU memory read with side effects (rendering fetches and PPUDATA reads), for mappers that
+    /// watch the PPU address bus.
+    fn ppu_read(&mut self, _addr: u16) -> () {}
+
+    /// Called on CPU writes to the PPU registers ($2000-$2007), for mappers that snoop on them.
+    fn ppu_register_written(&mut self, _addr: u16, _val: u8) -> () {}
+
+    /// Called when the PPU enters vblank, for mappers that keep track of the frame.
+    fn vblank_started(&mut self) -> () {}
+
+    /// The expansion audio chip on the cartridge, if any.
+    fn expansion_audio_chip(&self) -> Option<ExpansionChip> {
+        None
+    }
+
+    /// Clock the expansion audio. Called once every CPU cycle (only if `expansion_audio_chip` is not `None`.)
+    fn clock_expansion_audio(&mut self) -> () {}
+
+    /// The current expansion audio output, from 0.0 (silent) to 1.0 (the chip's full-scale output.)
+    fn expansion_audio_output(&self) -> f32 {
+        0.0
+    }
 }
 
 // We can only create a mapper from a nes file if the mapper is actually "real".
 pub trait RealMapper : Mapper {
     fn from_nesfile(nesfile: &NESFile) -> Self;
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// An NES 2.0 file with the given number of 16KiB PRG-ROM and 8KiB CHR-ROM banks, each filled with its bank number.
+    pub(crate) fn test_nesfile(mapper: u16, submapper: u8, prg_banks: u8, chr_banks: u8) -> NESFile {
+        let mut bytes = vec![
+            b'N', b'E', b'S', 0x1a,
+            prg_banks,
+            chr_banks,
+            (mapper as u8 & 0x0f) << 4,
+            (mapper as u8 & 0xf0) | 0x08,
+            (submapper << 4) | (mapper >> 8) as u8,
+        ];
+        bytes.resize(16, 0);
+        for bank in 0..prg_banks {
+            bytes.extend([bank; 0x4000]);
+        }
+        for bank in 0..chr_banks {
+            bytes.extend([bank; 0x2000]);
+        }
+        NESFile::from_vec(bytes).unwrap()
+    }
+}
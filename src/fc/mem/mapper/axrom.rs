@@ -0,0 +1,175 @@
+// AxROM (mapper 7), see:
+// https://www.nesdev.org/wiki/AxROM
+
+use std::io::Error;
+
+use log::{debug, info};
+
+use crate::fc::{
+    mem::{
+        Memory,
+        NametableArrangement::{self, SingleScreenA, SingleScreenB},
+        cart::NESFile,
+        mapper::{Mapper, MapperType, RealMapper},
+    },
+    ppu,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const DEFAULT_CHR_RAM_SIZE: usize = 0x2000;
+
+pub struct AxROMMapper {
+    prg_rom: Vec<u8>,
+    chr_rxm: Vec<u8>,
+    chr_writable: bool,
+    /// Whether a written value is ANDed with the ROM byte at the written address, as both drive the data bus.
+    bus_conflicts: bool,
+    nametable_arrange: NametableArrangement,
+    prg_bank: usize,
+    pub(crate) open_bus: u8,
+}
+
+impl RealMapper for AxROMMapper {
+    fn from_nesfile(nesfile: &NESFile) -> AxROMMapper {
+        assert!(nesfile.mapper_type() == MapperType::AxROM);
+        let prg_rom_size = nesfile.prg_rom_size();
+        let chr_rom_size = nesfile.chr_rom_size();
+        let chr_ram_size = match nesfile.chr_ram_size() {
+            0 => DEFAULT_CHR_RAM_SIZE,
+            size => size,
+        };
+
+        // Submapper 0 is unspecified, which most games work fine with as they avoid conflicts anyway (AMROM and
+        // AOROM have them, ANROM doesn't)
+        let bus_conflicts = nesfile.submapper_number() == 2;
+
+        info!("AxROM with:");
+        info!("  PRG-ROM SIZE: {} (0x{:x}); {} banks", prg_rom_size, prg_rom_size, prg_rom_size / PRG_BANK_SIZE);
+        if chr_rom_size != 0 {
+            info!("  CHR-ROM SIZE: {} (0x{:x})", chr_rom_size, chr_rom_size);
+        } else {
+            info!("  CHR-RAM SIZE: {} (0x{:x})", chr_ram_size, chr_ram_size);
+        }
+        info!("  Bus conflicts: {}", bus_conflicts);
+
+        let prg_rom = nesfile.data[0..prg_rom_size].to_vec();
+        let (chr_rxm, chr_writable) = if chr_rom_size != 0 {
+            (nesfile.data[prg_rom_size..(prg_rom_size + chr_rom_size)].to_vec(), false)
+        } else {
+            (vec![0; chr_ram_size], true)
+        };
+
+        AxROMMapper {
+            prg_rom,
+            chr_rxm,
+            chr_writable,
+            bus_conflicts,
+            nametable_arrange: SingleScreenA,
+            prg_bank: 0,
+            open_bus: 0x00,
+        }
+    }
+}
+
+impl Memory for AxROMMapper {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_no_sideeffect(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> () {
+        if addr < 0x8000 {
+            return;
+        }
+
+        let val = if self.bus_conflicts {
+            val & self.read_no_sideeffect(addr)
+        } else {
+            val
+        };
+        self.prg_bank = val as usize & 0b1111;
+        // "Select 1 KB VRAM page for all 4 nametables"
+        self.nametable_arrange = if val & 0x10 == 0 { SingleScreenA } else { SingleScreenB };
+
+        debug!("Set PRG bank to {}, nametable arrangement to {:?}", self.prg_bank, self.nametable_arrange);
+    }
+}
+
+impl Mapper for AxROMMapper {
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr_rxm[addr as usize % self.chr_rxm.len()]
+    }
+
+    fn write_chr(&mut self, addr: u16, val: u8) -> () {
+        if self.chr_writable {
+            let len = self.chr_rxm.len();
+            self.chr_rxm[addr as usize % len] = val;
+        }
+    }
+
+    fn nametable_read(&self, addr: u16, vram: [u8; ppu::VRAM_SIZE]) -> u8 {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize]
+    }
+
+    fn nametable_write(&mut self, addr: u16, val: u8, vram: &mut [u8; ppu::VRAM_SIZE]) -> () {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize] = val
+    }
+
+    fn read_no_sideeffect(&self, addr: u16) -> u8 {
+        match addr {
+            0x4020..=0x7fff => {
+                info!("Open bus read at ${addr:04x}");
+                self.open_bus
+            }
+            0x8000..=0xffff => {
+                // "32 KB switchable PRG ROM bank"
+                let bank = self.prg_bank % (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+                self.prg_rom[(bank * PRG_BANK_SIZE + (addr - 0x8000) as usize) % self.prg_rom.len()]
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Snapshot for AxROMMapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        if self.chr_writable {
+            w.write_vec(&self.chr_rxm);
+        }
+        w.write(&self.nametable_arrange);
+        w.write(&self.prg_bank);
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        if self.chr_writable {
+            r.read_vec_into(&mut self.chr_rxm)?;
+        }
+        self.nametable_arrange = r.read()?;
+        self.prg_bank = r.read()?;
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fc::mem::mapper::test::test_nesfile;
+
+    #[test]
+    fn bank_and_nametable_switching_test() {
+        let mut m = AxROMMapper::from_nesfile(&test_nesfile(7, 1, 8, 0));
+        let mut vram = [0; ppu::VRAM_SIZE];
+
+        m.write(0x8000, 0x12);
+        assert_eq!((m.read(0x8000), m.read(0xc000)), (4, 5));
+        // All four nametables are the upper page
+        m.nametable_write(0x2000, 0x42, &mut vram);
+        assert_eq!(vram[0x400], 0x42);
+        assert_eq!(m.nametable_read(0x2c00, vram), 0x42);
+
+        m.write(0x8000, 0x00);
+        assert_eq!(m.nametable_read(0x2000, vram), 0x00);
+    }
+}
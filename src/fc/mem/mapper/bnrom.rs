@@ -0,0 +1,241 @@
+// Mapper 34: BNROM and NINA-001, see:
+// https://www.nesdev.org/wiki/BNROM
+// https://www.nesdev.org/wiki/NINA-001
+
+use std::io::Error;
+
+use log::{debug, info};
+
+use crate::fc::{
+    mem::{
+        Memory,
+        NametableArrangement,
+        cart::NESFile,
+        mapper::{Mapper, MapperType, RealMapper},
+    },
+    ppu,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const NINA001_CHR_BANK_SIZE: usize = 0x1000;
+const NINA001_PRG_RAM_SIZE: usize = 0x2000;
+const DEFAULT_CHR_RAM_SIZE: usize = 0x2000;
+
+/// The two (unrelated) boards using mapper 34.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Board {
+    /// 32KiB PRG bank register at $8000-$ffff (with bus conflicts), and CHR-RAM.
+    BNROM,
+    /// 32KiB PRG and two 4KiB CHR bank registers at $7ffd-$7fff, and PRG-RAM.
+    NINA001,
+}
+
+pub struct BNROMMapper {
+    board: Board,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_rxm: Vec<u8>,
+    chr_writable: bool,
+    nametable_arrange: NametableArrangement,
+    prg_bank: usize,
+    chr_bank0: usize,
+    chr_bank1: usize,
+    pub(crate) open_bus: u8,
+}
+
+impl RealMapper for BNROMMapper {
+    fn from_nesfile(nesfile: &NESFile) -> BNROMMapper {
+        assert!(nesfile.mapper_type() == MapperType::BNROM);
+        let prg_rom_size = nesfile.prg_rom_size();
+        let chr_rom_size = nesfile.chr_rom_size();
+        let chr_ram_size = match nesfile.chr_ram_size() {
+            0 => DEFAULT_CHR_RAM_SIZE,
+            size => size,
+        };
+
+        let board = match nesfile.submapper_number() {
+            1 => Board::NINA001,
+            2 => Board::BNROM,
+            // Unspecified, but only NINA-001 has more than 8KiB of CHR-ROM
+            _ if chr_rom_size > 0x2000 => Board::NINA001,
+            _ => Board::BNROM,
+        };
+
+        let prg_ram_size = match board {
+            Board::BNROM => 0,
+            Board::NINA001 => NINA001_PRG_RAM_SIZE,
+        };
+        let nametable_arrange = NametableArrangement::from_header(nesfile);
+
+        info!("{:?} with:", board);
+        info!("  PRG-ROM SIZE: {} (0x{:x}); {} banks", prg_rom_size, prg_rom_size, prg_rom_size / PRG_BANK_SIZE);
+        info!("  PRG-RAM SIZE: {} (0x{:x})", prg_ram_size, prg_ram_size);
+        if chr_rom_size != 0 {
+            info!("  CHR-ROM SIZE: {} (0x{:x})", chr_rom_size, chr_rom_size);
+        } else {
+            info!("  CHR-RAM SIZE: {} (0x{:x})", chr_ram_size, chr_ram_size);
+        }
+        info!("  Nametable mirroring: {:?}", nametable_arrange);
+
+        let prg_rom = nesfile.data[0..prg_rom_size].to_vec();
+        let prg_ram = vec![0; prg_ram_size];
+        let (chr_rxm, chr_writable) = if chr_rom_size != 0 {
+            (nesfile.data[prg_rom_size..(prg_rom_size + chr_rom_size)].to_vec(), false)
+        } else {
+            (vec![0; chr_ram_size], true)
+        };
+
+        BNROMMapper {
+            board,
+            prg_rom,
+            prg_ram,
+            chr_rxm,
+            chr_writable,
+            nametable_arrange,
+            prg_bank: 0,
+            chr_bank0: 0,
+            chr_bank1: 1,
+            open_bus: 0x00,
+        }
+    }
+}
+
+impl BNROMMapper {
+    fn chr_addr(&self, addr: u16) -> usize {
+        let addr = match self.board {
+            Board::BNROM => addr as usize,
+            Board::NINA001 => {
+                let bank = if addr < 0x1000 { self.chr_bank0 } else { self.chr_bank1 };
+                bank * NINA001_CHR_BANK_SIZE + (addr as usize & 0xfff)
+            }
+        };
+        addr % self.chr_rxm.len()
+    }
+}
+
+impl Memory for BNROMMapper {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_no_sideeffect(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> () {
+        match (self.board, addr) {
+            (Board::NINA001, 0x6000..=0x7fff) => {
+                // The registers are written to the RAM as well
+                self.prg_ram[(addr - 0x6000) as usize] = val;
+                match addr {
+                    0x7ffd => self.prg_bank = val as usize & 0b1,
+                    0x7ffe => self.chr_bank0 = val as usize & 0b1111,
+                    0x7fff => self.chr_bank1 = val as usize & 0b1111,
+                    _ => return,
+                }
+                debug!("Set PRG bank to {}, CHR banks to {}, {}", self.prg_bank, self.chr_bank0, self.chr_bank1);
+            }
+            (Board::BNROM, 0x8000..=0xffff) => {
+                // The board always has bus conflicts
+                self.prg_bank = (val & self.read_no_sideeffect(addr)) as usize;
+                debug!("Set PRG bank to {}", self.prg_bank);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for BNROMMapper {
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr_rxm[self.chr_addr(addr)]
+    }
+
+    fn write_chr(&mut self, addr: u16, val: u8) -> () {
+        if self.chr_writable {
+            let addr = self.chr_addr(addr);
+            self.chr_rxm[addr] = val;
+        }
+    }
+
+    fn nametable_read(&self, addr: u16, vram: [u8; ppu::VRAM_SIZE]) -> u8 {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize]
+    }
+
+    fn nametable_write(&mut self, addr: u16, val: u8, vram: &mut [u8; ppu::VRAM_SIZE]) -> () {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize] = val
+    }
+
+    fn read_no_sideeffect(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => self.prg_ram[(addr - 0x6000) as usize],
+            0x4020..=0x7fff => {
+                info!("Open bus read at ${addr:04x}");
+                self.open_bus
+            }
+            0x8000..=0xffff => {
+                // "32 KB switchable PRG ROM bank"
+                let bank = self.prg_bank % (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+                self.prg_rom[(bank * PRG_BANK_SIZE + (addr - 0x8000) as usize) % self.prg_rom.len()]
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Snapshot for BNROMMapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.prg_ram);
+        if self.chr_writable {
+            w.write_vec(&self.chr_rxm);
+        }
+        w.write(&self.prg_bank);
+        w.write(&self.chr_bank0);
+        w.write(&self.chr_bank1);
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        r.read_vec_into(&mut self.prg_ram)?;
+        if self.chr_writable {
+            r.read_vec_into(&mut self.chr_rxm)?;
+        }
+        self.prg_bank = r.read()?;
+        self.chr_bank0 = r.read()?;
+        self.chr_bank1 = r.read()?;
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fc::mem::mapper::test::test_nesfile;
+
+    #[test]
+    fn board_detection_test() {
+        assert_eq!(BNROMMapper::from_nesfile(&test_nesfile(34, 0, 4, 0)).board, Board::BNROM);
+        assert_eq!(BNROMMapper::from_nesfile(&test_nesfile(34, 0, 4, 2)).board, Board::NINA001);
+        assert_eq!(BNROMMapper::from_nesfile(&test_nesfile(34, 1, 4, 1)).board, Board::NINA001);
+        assert_eq!(BNROMMapper::from_nesfile(&test_nesfile(34, 2, 4, 2)).board, Board::BNROM);
+    }
+
+    #[test]
+    fn nina001_test() {
+        let mut m = BNROMMapper::from_nesfile(&test_nesfile(34, 1, 4, 2));
+        m.write(0x7ffd, 1);
+        m.write(0x7ffe, 3);
+        m.write(0x7fff, 0);
+        assert_eq!((m.read(0x8000), m.read(0x7ffe)), (2, 3));
+        // The 4KiB CHR banks 3 and 0 are the second half of 8KiB bank 1, and the first half of bank 0
+        assert_eq!((m.read_chr(0x0000), m.read_chr(0x1000)), (1, 0));
+    }
+
+    #[test]
+    fn bnrom_test() {
+        let mut m = BNROMMapper::from_nesfile(&test_nesfile(34, 2, 4, 0));
+        // The bus conflict with the byte at $c000 (1) leaves bank 1
+        m.write(0xc000, 0xff);
+        assert_eq!((m.read(0x8000), m.read(0xc000)), (2, 3));
+
+        m.write_chr(0x1234, 0x42);
+        assert_eq!(m.read_chr(0x1234), 0x42);
+    }
+}
@@ -0,0 +1,149 @@
+// CNROM (mapper 3), see:
+// https://www.nesdev.org/wiki/CNROM
+
+use std::io::Error;
+
+use log::{debug, info};
+
+use crate::fc::{
+    mem::{
+        Memory,
+        NametableArrangement,
+        cart::NESFile,
+        mapper::{Mapper, MapperType, RealMapper},
+    },
+    ppu,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+pub struct CNROMMapper {
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    /// Whether a written value is ANDed with the ROM byte at the written address, as both drive the data bus.
+    bus_conflicts: bool,
+    nametable_arrange: NametableArrangement,
+    chr_bank: usize,
+    pub(crate) open_bus: u8,
+}
+
+impl RealMapper for CNROMMapper {
+    fn from_nesfile(nesfile: &NESFile) -> CNROMMapper {
+        assert!(nesfile.mapper_type() == MapperType::CNROM);
+        let prg_rom_size = nesfile.prg_rom_size();
+        let chr_rom_size = nesfile.chr_rom_size();
+
+        // Submapper 0 is unspecified, which most games work fine with as they avoid conflicts anyway
+        let bus_conflicts = nesfile.submapper_number() == 2;
+        let nametable_arrange = NametableArrangement::from_header(nesfile);
+
+        info!("CNROM with:");
+        info!("  PRG-ROM SIZE: {} (0x{:x})", prg_rom_size, prg_rom_size);
+        info!("  CHR-ROM SIZE: {} (0x{:x}); {} banks", chr_rom_size, chr_rom_size, chr_rom_size / CHR_BANK_SIZE);
+        info!("  Bus conflicts: {}", bus_conflicts);
+        info!("  Nametable mirroring: {:?}", nametable_arrange);
+
+        let prg_rom = nesfile.data[0..prg_rom_size].to_vec();
+        let chr_rom = nesfile.data[prg_rom_size..(prg_rom_size + chr_rom_size)].to_vec();
+
+        CNROMMapper {
+            prg_rom,
+            chr_rom,
+            bus_conflicts,
+            nametable_arrange,
+            chr_bank: 0,
+            open_bus: 0x00,
+        }
+    }
+}
+
+impl Memory for CNROMMapper {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_no_sideeffect(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> () {
+        if addr < 0x8000 {
+            return;
+        }
+
+        let val = if self.bus_conflicts {
+            val & self.read_no_sideeffect(addr)
+        } else {
+            val
+        };
+        // Some boards have more than the original 2 bank bits, so use all of them
+        self.chr_bank = val as usize;
+
+        debug!("Set CHR bank to {0} (0x{0:02x})", self.chr_bank);
+    }
+}
+
+impl Mapper for CNROMMapper {
+    fn read_chr(&self, addr: u16) -> u8 {
+        if self.chr_rom.is_empty() {
+            return 0xff;
+        }
+
+        let bank = self.chr_bank % (self.chr_rom.len() / CHR_BANK_SIZE).max(1);
+        self.chr_rom[(bank * CHR_BANK_SIZE + addr as usize) % self.chr_rom.len()]
+    }
+
+    fn write_chr(&mut self, _addr: u16, _val: u8) -> () {}
+
+    fn nametable_read(&self, addr: u16, vram: [u8; ppu::VRAM_SIZE]) -> u8 {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize]
+    }
+
+    fn nametable_write(&mut self, addr: u16, val: u8, vram: &mut [u8; ppu::VRAM_SIZE]) -> () {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize] = val
+    }
+
+    fn read_no_sideeffect(&self, addr: u16) -> u8 {
+        match addr {
+            0x4020..=0x7fff => {
+                info!("Open bus read at ${addr:04x}");
+                self.open_bus
+            }
+            // "16 KiB or 32 KiB PRG ROM (mirrored if 16 KiB)"
+            0x8000..=0xffff => self.prg_rom[(addr - 0x8000) as usize % self.prg_rom.len().min(2 * PRG_BANK_SIZE)],
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Snapshot for CNROMMapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.chr_bank);
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.chr_bank = r.read()?;
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fc::mem::mapper::test::test_nesfile;
+
+    #[test]
+    fn chr_bank_switching_test() {
+        let mut m = CNROMMapper::from_nesfile(&test_nesfile(3, 2, 1, 4));
+        // 16KiB PRG-ROM is mirrored
+        assert_eq!(m.read(0x8000), m.read(0xc000));
+
+        // The PRG-ROM byte at the written address is 0, so the bus conflict clears the bank
+        m.write(0x8000, 2);
+        assert_eq!(m.read_chr(0x0000), 0);
+
+        let mut m = CNROMMapper::from_nesfile(&test_nesfile(3, 1, 1, 4));
+        m.write(0x8000, 2);
+        assert_eq!(m.read_chr(0x1fff), 2);
+    }
+}
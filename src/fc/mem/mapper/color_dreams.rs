@@ -0,0 +1,33 @@
+// Color Dreams (mapper 11), see:
+// https://www.nesdev.org/wiki/Color_Dreams
+//
+// The mapper itself is a `DiscreteLatchMapper`.
+
+/// "7654 3210
+///  CCCC LLPP" (the lockout defeat bits aren't emulated.)
+pub(super) fn decode_banks(val: u8) -> (usize, usize) {
+    (val as usize & 0b11, (val as usize >> 4) & 0b1111)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fc::mem::{
+        Memory,
+        mapper::{Mapper, RealMapper, discrete_latch::DiscreteLatchMapper, test::test_nesfile},
+    };
+
+    #[test]
+    fn bank_switching_test() {
+        // The PRG-ROM bytes are the number of their 16KiB bank, so the bus conflict clears the CHR bank bits
+        let mut m = DiscreteLatchMapper::from_nesfile(&test_nesfile(11, 0, 4, 4));
+        m.write(0xc000, 0x21);
+        assert_eq!((m.read(0x8000), m.read(0xc000), m.read_chr(0)), (2, 3, 0));
+
+        // With the upper bits set in the PRG-ROM as well
+        let mut nesfile = test_nesfile(11, 0, 4, 4);
+        nesfile.data[..0x10000].iter_mut().for_each(|b| *b |= 0xf0);
+        let mut m = DiscreteLatchMapper::from_nesfile(&nesfile);
+        m.write(0x8000, 0x30);
+        assert_eq!((m.read(0x8000), m.read_chr(0)), (0xf0, 3));
+    }
+}
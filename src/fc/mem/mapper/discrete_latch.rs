@@ -0,0 +1,131 @@
+// Boards with a single latch at $8000-$ffff selecting a 32KiB PRG-ROM bank and an 8KiB CHR-ROM bank, which only differ
+// in the bits used for each bank (see the `gxrom` and `color_dreams` modules.)
+
+use std::io::Error;
+
+use log::{debug, info};
+
+use crate::fc::{
+    mem::{
+        Memory,
+        NametableArrangement,
+        cart::NESFile,
+        mapper::{Mapper, MapperType, RealMapper, color_dreams, gxrom},
+    },
+    ppu,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+pub struct DiscreteLatchMapper {
+    /// Decodes a value written to the latch into the PRG and CHR bank.
+    decode_banks: fn(u8) -> (usize, usize),
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    nametable_arrange: NametableArrangement,
+    prg_bank: usize,
+    chr_bank: usize,
+    pub(crate) open_bus: u8,
+}
+
+impl RealMapper for DiscreteLatchMapper {
+    fn from_nesfile(nesfile: &NESFile) -> DiscreteLatchMapper {
+        let decode_banks = match nesfile.mapper_type() {
+            MapperType::GxROM => gxrom::decode_banks,
+            MapperType::ColorDreams => color_dreams::decode_banks,
+            t => panic!("not a discrete latch mapper: {t:?}"),
+        };
+        let prg_rom_size = nesfile.prg_rom_size();
+        let chr_rom_size = nesfile.chr_rom_size();
+        let nametable_arrange = NametableArrangement::from_header(nesfile);
+
+        info!("{:?} with:", nesfile.mapper_type());
+        info!("  PRG-ROM SIZE: {} (0x{:x}); {} banks", prg_rom_size, prg_rom_size, prg_rom_size / PRG_BANK_SIZE);
+        info!("  CHR-ROM SIZE: {} (0x{:x}); {} banks", chr_rom_size, chr_rom_size, chr_rom_size / CHR_BANK_SIZE);
+        info!("  Nametable mirroring: {:?}", nametable_arrange);
+
+        let prg_rom = nesfile.data[0..prg_rom_size].to_vec();
+        let chr_rom = nesfile.data[prg_rom_size..(prg_rom_size + chr_rom_size)].to_vec();
+
+        DiscreteLatchMapper {
+            decode_banks,
+            prg_rom,
+            chr_rom,
+            nametable_arrange,
+            prg_bank: 0,
+            chr_bank: 0,
+            open_bus: 0x00,
+        }
+    }
+}
+
+impl Memory for DiscreteLatchMapper {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_no_sideeffect(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> () {
+        if addr < 0x8000 {
+            return;
+        }
+
+        // The boards always have bus conflicts
+        let val = val & self.read_no_sideeffect(addr);
+        (self.prg_bank, self.chr_bank) = (self.decode_banks)(val);
+
+        debug!("Set PRG bank to {}, CHR bank to {}", self.prg_bank, self.chr_bank);
+    }
+}
+
+impl Mapper for DiscreteLatchMapper {
+    fn read_chr(&self, addr: u16) -> u8 {
+        if self.chr_rom.is_empty() {
+            return 0xff;
+        }
+
+        let bank = self.chr_bank % (self.chr_rom.len() / CHR_BANK_SIZE).max(1);
+        self.chr_rom[(bank * CHR_BANK_SIZE + addr as usize) % self.chr_rom.len()]
+    }
+
+    fn write_chr(&mut self, _addr: u16, _val: u8) -> () {}
+
+    fn nametable_read(&self, addr: u16, vram: [u8; ppu::VRAM_SIZE]) -> u8 {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize]
+    }
+
+    fn nametable_write(&mut self, addr: u16, val: u8, vram: &mut [u8; ppu::VRAM_SIZE]) -> () {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize] = val
+    }
+
+    fn read_no_sideeffect(&self, addr: u16) -> u8 {
+        match addr {
+            0x4020..=0x7fff => {
+                info!("Open bus read at ${addr:04x}");
+                self.open_bus
+            }
+            0x8000..=0xffff => {
+                // "32 KB switchable PRG ROM bank"
+                let bank = self.prg_bank % (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+                self.prg_rom[(bank * PRG_BANK_SIZE + (addr - 0x8000) as usize) % self.prg_rom.len()]
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Snapshot for DiscreteLatchMapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write(&self.prg_bank);
+        w.write(&self.chr_bank);
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.prg_bank = r.read()?;
+        self.chr_bank = r.read()?;
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+// GxROM (mapper 66), see:
+// https://www.nesdev.org/wiki/GxROM
+//
+// The mapper itself is a `DiscreteLatchMapper`.
+
+/// "7654 3210
+///  --PP --CC"
+pub(super) fn decode_banks(val: u8) -> (usize, usize) {
+    ((val as usize >> 4) & 0b11, val as usize & 0b11)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fc::mem::{
+        Memory,
+        mapper::{Mapper, RealMapper, discrete_latch::DiscreteLatchMapper, test::test_nesfile},
+    };
+
+    #[test]
+    fn bank_switching_test() {
+        // The PRG-ROM bytes are the number of their 16KiB bank, and only the bits set in them survive the bus conflict
+        let mut m = DiscreteLatchMapper::from_nesfile(&test_nesfile(66, 0, 4, 4));
+        assert_eq!((m.read(0x8000), m.read(0xc000)), (0, 1));
+        m.write(0xc000, 0x13);
+        assert_eq!((m.read(0x8000), m.read(0xc000), m.read_chr(0)), (0, 1, 1));
+
+        m.write(0x8000, 0x13);
+        assert_eq!((m.read(0x8000), m.read_chr(0)), (0, 0));
+    }
+}
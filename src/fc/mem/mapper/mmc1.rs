@@ -1,18 +1,21 @@
+use std::io::{Error, ErrorKind};
+
 use log::{debug, info};
 
 use crate::fc::mem::{self, Memory, NametableArrangement, cart::NESFile, mapper::{Mapper, MapperType::{self}, RealMapper, mmc1::CHRBankMode::Switch8K}};
+use crate::fc::state::{Snapshot, StateReader, StateWriter};
 
 const PRG_BANK_SIZE: usize = 0x4000;
 const CHR_BANK_SIZE: usize = 0x1000;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum PRGBankMode {
     FixAll,
     FixFirst,
     FixLast,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum CHRBankMode {
     Switch8K,
     Switch2x4K,
@@ -370,3 +373,49 @@ impl Mapper for MMC1Mapper {
         }
     }
 }
+
+impl Snapshot for MMC1Mapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.prg_ram);
+        if self.chr_writable {
+            w.write_vec(&self.chr_rxm);
+        }
+        w.write(&(self.prg_bank_mode as u8));
+        w.write(&(self.chr_bank_mode as u8));
+        w.write(&self.nametable_arrange);
+        w.write(&self.reg.shift);
+        w.write(&self.reg.control);
+        w.write(&self.reg.prg_bank0);
+        w.write(&self.reg.prg_bank1);
+        w.write(&self.reg.chr_bank0);
+        w.write(&self.reg.chr_bank1);
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        r.read_vec_into(&mut self.prg_ram)?;
+        if self.chr_writable {
+            r.read_vec_into(&mut self.chr_rxm)?;
+        }
+        self.prg_bank_mode = match r.read::<u8>()? {
+            0 => PRGBankMode::FixAll,
+            1 => PRGBankMode::FixFirst,
+            2 => PRGBankMode::FixLast,
+            n => return Err(Error::new(ErrorKind::InvalidData, format!("invalid MMC1 PRG bank mode {n}"))),
+        };
+        self.chr_bank_mode = match r.read::<u8>()? {
+            0 => CHRBankMode::Switch8K,
+            1 => CHRBankMode::Switch2x4K,
+            n => return Err(Error::new(ErrorKind::InvalidData, format!("invalid MMC1 CHR bank mode {n}"))),
+        };
+        self.nametable_arrange = r.read()?;
+        self.reg.shift = r.read()?;
+        self.reg.control = r.read()?;
+        self.reg.prg_bank0 = r.read()?;
+        self.reg.prg_bank1 = r.read()?;
+        self.reg.chr_bank0 = r.read()?;
+        self.reg.chr_bank1 = r.read()?;
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
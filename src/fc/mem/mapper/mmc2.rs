@@ -0,0 +1,329 @@
+// MMC2 (mapper 9) and MMC4 (mapper 10), see:
+// https://www.nesdev.org/wiki/MMC2
+// https://www.nesdev.org/wiki/MMC4
+
+use std::io::Error;
+
+use log::{debug, info};
+
+use crate::fc::{
+    mem::{
+        Memory,
+        NametableArrangement::{self, HorizontalMirroring, VerticalMirroring},
+        cart::NESFile,
+        mapper::{Mapper, MapperType, RealMapper},
+    },
+    ppu,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const CHR_BANK_SIZE: usize = 0x1000;
+/// Only MMC4 boards have PRG-RAM.
+const MMC4_DEFAULT_PRG_RAM_SIZE: usize = 0x2000;
+
+/// The two chips only differ in their PRG banking, and in the addresses that trigger the left latch.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Chip {
+    /// One switchable 8KiB PRG bank at $8000, the last three banks fixed at $a000-$ffff.
+    MMC2,
+    /// One switchable 16KiB PRG bank at $8000, the last bank fixed at $c000.
+    MMC4,
+}
+
+impl Chip {
+    fn prg_bank_size(self) -> usize {
+        match self {
+            Chip::MMC2 => 0x2000,
+            Chip::MMC4 => 0x4000,
+        }
+    }
+}
+
+/// The state of a CHR latch, which selects between the two CHR bank registers of a pattern table.
+///
+/// The latches are set by the PPU fetching tile $fd or $fe, so games can switch banks mid-frame by placing these tiles
+/// on screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Latch {
+    FD,
+    FE,
+}
+
+pub struct MMC2Mapper {
+    chip: Chip,
+    battery: bool,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_rom: Vec<u8>,
+    nametable_arrange: NametableArrangement,
+    prg_bank: usize,
+    /// The CHR banks of the left ($0000) pattern table, for latch $fd and $fe.
+    chr_banks0: [usize; 2],
+    /// The CHR banks of the right ($1000) pattern table, for latch $fd and $fe.
+    chr_banks1: [usize; 2],
+    latch0: Latch,
+    latch1: Latch,
+    pub(crate) open_bus: u8,
+}
+
+impl RealMapper for MMC2Mapper {
+    fn from_nesfile(nesfile: &NESFile) -> MMC2Mapper {
+        let chip = match nesfile.mapper_type() {
+            MapperType::MMC2 => Chip::MMC2,
+            MapperType::MMC4 => Chip::MMC4,
+            t => panic!("not an MMC2 or MMC4 cartridge: {t:?}"),
+        };
+        let prg_rom_size = nesfile.prg_rom_size();
+        let chr_rom_size = nesfile.chr_rom_size();
+        let battery = nesfile.battery();
+
+        let prg_ram_size = if nesfile.is_nes20_format() {
+            if battery {
+                nesfile.prg_nvram_eeprom_size()
+            } else {
+                nesfile.prg_ram_size()
+            }
+        } else {
+            match chip {
+                Chip::MMC2 => 0,
+                Chip::MMC4 => MMC4_DEFAULT_PRG_RAM_SIZE,
+            }
+        };
+        let nametable_arrange = NametableArrangement::from_header(nesfile);
+
+        info!("{:?} with:", chip);
+        info!(
+            "  PRG-ROM SIZE: {} (0x{:x}); {} banks",
+            prg_rom_size,
+            prg_rom_size,
+            prg_rom_size / chip.prg_bank_size()
+        );
+        info!("  PRG-RAM SIZE: {} (0x{:x})", prg_ram_size, prg_ram_size);
+        info!("  CHR-ROM SIZE: {} (0x{:x}); {} 4KiB banks", chr_rom_size, chr_rom_size, chr_rom_size / CHR_BANK_SIZE);
+        info!("  BATTERY: {}", battery);
+
+        let prg_rom = nesfile.data[0..prg_rom_size].to_vec();
+        let prg_ram = vec![0; prg_ram_size];
+        let chr_rom = nesfile.data[prg_rom_size..(prg_rom_size + chr_rom_size)].to_vec();
+
+        MMC2Mapper {
+            chip,
+            battery,
+            prg_rom,
+            prg_ram,
+            chr_rom,
+            nametable_arrange,
+            prg_bank: 0,
+            chr_banks0: [0; 2],
+            chr_banks1: [0; 2],
+            latch0: Latch::FE,
+            latch1: Latch::FE,
+            open_bus: 0x00,
+        }
+    }
+}
+
+impl MMC2Mapper {
+    fn chr_addr(&self, addr: u16) -> usize {
+        let (banks, latch) = if addr < 0x1000 {
+            (self.chr_banks0, self.latch0)
+        } else {
+            (self.chr_banks1, self.latch1)
+        };
+        let bank = match latch {
+            Latch::FD => banks[0],
+            Latch::FE => banks[1],
+        };
+        (bank * CHR_BANK_SIZE + (addr as usize & 0xfff)) % self.chr_rom.len()
+    }
+
+    pub(crate) fn sram(&self) -> &Vec<u8> {
+        &self.prg_ram
+    }
+
+    pub(crate) fn replace_sram(&mut self, sram: Vec<u8>) -> Result<(), std::io::Error> {
+        if self.prg_ram.len() != sram.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!(
+                    "Size of save RAM is incorrect, expected {} got {}",
+                    self.prg_ram.len(),
+                    sram.len()
+                )
+            ));
+        }
+
+        self.prg_ram = sram;
+        Ok(())
+    }
+
+    pub(crate) fn has_battery(&self) -> bool {
+        self.battery
+    }
+}
+
+impl Memory for MMC2Mapper {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_no_sideeffect(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> () {
+        match addr {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                let len = self.prg_ram.len();
+                self.prg_ram[(addr - 0x6000) as usize % len] = val;
+            }
+            0xa000..=0xafff => {
+                self.prg_bank = val as usize & 0x0f;
+                debug!("Set PRG bank to {}", self.prg_bank);
+            }
+            0xb000..=0xbfff => self.chr_banks0[0] = val as usize & 0x1f,
+            0xc000..=0xcfff => self.chr_banks0[1] = val as usize & 0x1f,
+            0xd000..=0xdfff => self.chr_banks1[0] = val as usize & 0x1f,
+            0xe000..=0xefff => self.chr_banks1[1] = val as usize & 0x1f,
+            0xf000..=0xffff => {
+                self.nametable_arrange = if val & 1 == 0 { VerticalMirroring } else { HorizontalMirroring };
+                debug!("Set nametable arrangement to {:?}", self.nametable_arrange);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for MMC2Mapper {
+    fn read_chr(&self, addr: u16) -> u8 {
+        if self.chr_rom.is_empty() {
+            return 0xff;
+        }
+
+        self.chr_rom[self.chr_addr(addr)]
+    }
+
+    fn write_chr(&mut self, _addr: u16, _val: u8) -> () {}
+
+    fn nametable_read(&self, addr: u16, vram: [u8; ppu::VRAM_SIZE]) -> u8 {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize]
+    }
+
+    fn nametable_write(&mut self, addr: u16, val: u8, vram: &mut [u8; ppu::VRAM_SIZE]) -> () {
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize] = val
+    }
+
+    fn read_no_sideeffect(&self, addr: u16) -> u8 {
+        let bank_size = self.chip.prg_bank_size();
+        let banks = (self.prg_rom.len() / bank_size).max(1);
+
+        match addr {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => self.prg_ram[(addr - 0x6000) as usize % self.prg_ram.len()],
+            0x4020..=0x7fff => {
+                info!("Open bus read at ${addr:04x}");
+                self.open_bus
+            }
+            0x8000..=0xffff => {
+                let offset = (addr - 0x8000) as usize;
+                let slot = offset / bank_size;
+                let bank = if slot == 0 {
+                    self.prg_bank
+                } else {
+                    // The other slots are fixed to the last banks
+                    let slots = 0x8000 / bank_size;
+                    banks * slots - (slots - slot)
+                };
+                self.prg_rom[((bank % banks) * bank_size + offset % bank_size) % self.prg_rom.len()]
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn pattern_fetched(&mut self, addr: u16) -> () {
+        // The latches switch after the fetch, so the tile that triggered it is still drawn with the previous bank.
+        // MMC2 only checks a single address for the left pattern table, MMC4 checks the whole second plane like the
+        // right one.
+        let latch = match (addr, self.chip) {
+            (0x0fd8, _) | (0x0fd9..=0x0fdf, Chip::MMC4) => &mut self.latch0,
+            (0x0fe8, _) | (0x0fe9..=0x0fef, Chip::MMC4) => &mut self.latch0,
+            (0x1fd8..=0x1fdf, _) | (0x1fe8..=0x1fef, _) => &mut self.latch1,
+            _ => return,
+        };
+        *latch = if addr & 0xff0 == 0xfd0 { Latch::FD } else { Latch::FE };
+    }
+}
+
+impl Snapshot for MMC2Mapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.prg_ram);
+        w.write(&self.nametable_arrange);
+        w.write(&self.prg_bank);
+        w.write(&self.chr_banks0[0]);
+        w.write(&self.chr_banks0[1]);
+        w.write(&self.chr_banks1[0]);
+        w.write(&self.chr_banks1[1]);
+        w.write(&(self.latch0 == Latch::FD));
+        w.write(&(self.latch1 == Latch::FD));
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        let latch = |fd: bool| if fd { Latch::FD } else { Latch::FE };
+
+        r.read_vec_into(&mut self.prg_ram)?;
+        self.nametable_arrange = r.read()?;
+        self.prg_bank = r.read()?;
+        self.chr_banks0[0] = r.read()?;
+        self.chr_banks0[1] = r.read()?;
+        self.chr_banks1[0] = r.read()?;
+        self.chr_banks1[1] = r.read()?;
+        self.latch0 = latch(r.read()?);
+        self.latch1 = latch(r.read()?);
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fc::mem::mapper::test::test_nesfile;
+
+    #[test]
+    fn prg_banking_test() {
+        // 8 16KiB banks, which are 16 8KiB banks for MMC2
+        let mut m = MMC2Mapper::from_nesfile(&test_nesfile(9, 0, 8, 2));
+        m.write(0xa000, 3);
+        assert_eq!([0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.read(a)), [1, 6, 7, 7]);
+
+        let mut m = MMC2Mapper::from_nesfile(&test_nesfile(10, 0, 8, 2));
+        m.write(0xa000, 3);
+        assert_eq!([0x8000, 0xbfff, 0xc000].map(|a| m.read(a)), [3, 3, 7]);
+    }
+
+    #[test]
+    fn small_prg_rom_test() {
+        // A single 16KiB bank is smaller than MMC2's fixed 24KiB, so it's mirrored
+        let mut m = MMC2Mapper::from_nesfile(&test_nesfile(9, 0, 1, 2));
+        m.write(0xa000, 5);
+        assert_eq!([0x8000, 0xa000, 0xe000].map(|a| m.read(a)), [0, 0, 0]);
+    }
+
+    #[test]
+    fn chr_latch_test() {
+        // 4 8KiB CHR banks, so 4KiB banks 2 and 3 contain 1, and 6 and 7 contain 3
+        let mut m = MMC2Mapper::from_nesfile(&test_nesfile(9, 0, 8, 4));
+        m.write(0xb000, 2); // $0000, $fd
+        m.write(0xc000, 6); // $0000, $fe
+        m.write(0xd000, 3); // $1000, $fd
+        m.write(0xe000, 7); // $1000, $fe
+        assert_eq!((m.read_chr(0x0000), m.read_chr(0x1000)), (3, 3));
+
+        m.pattern_fetched(0x0fd8);
+        assert_eq!((m.read_chr(0x0000), m.read_chr(0x1000)), (1, 3));
+        m.pattern_fetched(0x1fdf);
+        assert_eq!((m.read_chr(0x0000), m.read_chr(0x1000)), (1, 1));
+
+        // Only $0fe8 triggers the left latch on MMC2
+        m.pattern_fetched(0x0fe9);
+        assert_eq!(m.read_chr(0x0000), 1);
+        m.pattern_fetched(0x0fe8);
+        assert_eq!(m.read_chr(0x0000), 3);
+    }
+}
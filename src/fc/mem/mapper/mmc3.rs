@@ -1,3 +1,5 @@
+use std::io::{Error, ErrorKind};
+
 use log::{debug, info};
 
 use crate::fc::{
@@ -14,18 +16,19 @@ use crate::fc::{
         },
     },
     ppu,
+    state::{Snapshot, StateReader, StateWriter},
 };
 
 const PRG_BANK_SIZE: usize = 0x2000;
 const CHR_BANK_SIZE: usize = 0x400;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum PRGBankMode {
     Swap8000,
     SwapC000,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum CHRBankMode {
     Swap2KiBAt0000,
     Swap2KiBAt1000,
@@ -475,3 +478,60 @@ impl Mapper for MMC3Mapper {
         }
     }
 }
+
+impl Snapshot for MMC3Mapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.prg_ram);
+        w.write(&self.nametable_arrange);
+        w.write(&self.irq_enabled);
+        w.write(&(self.prg_bank_mode as u8));
+        w.write(&(self.chr_bank_mode as u8));
+        w.write(&self.reg.bank_select);
+        for bank in [
+            self.reg.chr_bank0, self.reg.chr_bank1, self.reg.chr_bank2,
+            self.reg.chr_bank3, self.reg.chr_bank4, self.reg.chr_bank5,
+            self.reg.prg_bank0, self.reg.prg_bank1,
+        ] {
+            w.write(&bank);
+        }
+        w.write(&self.reg.prg_ram_protected);
+        w.write(&self.reg.prg_ram_enabled);
+        w.write(&self.reg.irq_latch_val);
+        w.write(&self.reg.irq_counter);
+        w.write(&self.reg.irq_reload);
+        w.write(&self.irq_triggered);
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        r.read_vec_into(&mut self.prg_ram)?;
+        self.nametable_arrange = r.read()?;
+        self.irq_enabled = r.read()?;
+        self.prg_bank_mode = match r.read::<u8>()? {
+            0 => Swap8000,
+            1 => SwapC000,
+            n => return Err(Error::new(ErrorKind::InvalidData, format!("invalid MMC3 PRG bank mode {n}"))),
+        };
+        self.chr_bank_mode = match r.read::<u8>()? {
+            0 => Swap2KiBAt0000,
+            1 => Swap2KiBAt1000,
+            n => return Err(Error::new(ErrorKind::InvalidData, format!("invalid MMC3 CHR bank mode {n}"))),
+        };
+        self.reg.bank_select = r.read()?;
+        for bank in [
+            &mut self.reg.chr_bank0, &mut self.reg.chr_bank1, &mut self.reg.chr_bank2,
+            &mut self.reg.chr_bank3, &mut self.reg.chr_bank4, &mut self.reg.chr_bank5,
+            &mut self.reg.prg_bank0, &mut self.reg.prg_bank1,
+        ] {
+            *bank = r.read()?;
+        }
+        self.reg.prg_ram_protected = r.read()?;
+        self.reg.prg_ram_enabled = r.read()?;
+        self.reg.irq_latch_val = r.read()?;
+        self.reg.irq_counter = r.read()?;
+        self.reg.irq_reload = r.read()?;
+        self.irq_triggered = r.read()?;
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
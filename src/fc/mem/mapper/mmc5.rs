@@ -0,0 +1,865 @@
+// MMC5 (ExROM, mapper 5), see:
+// https://www.nesdev.org/wiki/MMC5
+// https://www.nesdev.org/wiki/MMC5_audio
+
+use std::io::{Error, ErrorKind};
+
+use log::{debug, info};
+
+use crate::fc::{
+    apu::{ExpansionChip, PulseChannel},
+    mem::{
+        Memory,
+        cart::NESFile,
+        mapper::{Mapper, MapperType, RealMapper},
+    },
+    ppu,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const SPLIT_CHR_BANK_SIZE: usize = 0x1000;
+const EXRAM_SIZE: usize = 0x400;
+const DEFAULT_PRG_RAM_SIZE: usize = 0x10000;
+const DEFAULT_CHR_RAM_SIZE: usize = 0x2000;
+/// The audio frame counter clocks the envelopes and length counters at a fixed ~240Hz, in CPU cycles.
+const AUDIO_FRAME_PERIOD: u64 = 7457;
+
+/// What the PPU is currently fetching, as far as the MMC5 can tell by counting the fetches since the start of the
+/// scanline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fetch {
+    /// Not rendering (or the unused fetches at the end of a scanline.)
+    Other,
+    Background,
+    Sprite,
+}
+
+pub struct MMC5Mapper {
+    battery: bool,
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr_rxm: Vec<u8>,
+    chr_writable: bool,
+    exram: [u8; EXRAM_SIZE],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect1: u8,
+    prg_ram_protect2: u8,
+    exram_mode: u8,
+    /// The source of each of the four nametables, two bits each: CIRAM page 0 or 1, ExRAM, or the fill mode tile.
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attr: u8,
+    prg_ram_bank: u8,
+    /// $5114-$5117, bit 7 selects ROM instead of RAM ($5117 is always ROM.)
+    prg_banks: [u8; 4],
+    /// $5120-$5127, used for sprites with 8x16 sprites.
+    chr_banks_a: [u16; 8],
+    /// $5128-$512b, used for the background with 8x16 sprites.
+    chr_banks_b: [u16; 4],
+    /// Whether the B set was written last, which is the one used for everything with 8x8 sprites.
+    chr_last_b: bool,
+    chr_upper: u8,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    irq_scanline: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+
+    /// Snooped from PPUCTRL.
+    sprites_large: bool,
+    in_frame: bool,
+    scanline: u8,
+    last_ppu_addr: u16,
+    /// The number of consecutive reads of the same nametable address. The third one is the start of a scanline.
+    nametable_reads: u8,
+    /// The number of PPU reads since the start of the scanline.
+    fetch_count: u8,
+    fetch: Fetch,
+    /// The ExRAM byte of the background tile being fetched (in extended attribute mode.)
+    ext_attr: u8,
+    in_split: bool,
+    split_tile: usize,
+    split_fine_y: usize,
+    split_palette: u8,
+
+    pulse1: PulseChannel,
+    pulse2: PulseChannel,
+    /// $5015, bits 0 and 1 enable the pulse channels.
+    pulse_enabled: u8,
+    /// Whether the PCM channel takes its samples from CPU reads of $8000-$bfff instead of writes to $5011.
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    /// Set when a zero is read in read mode.
+    pcm_irq_pending: bool,
+    pcm_output: u8,
+    audio_cycles: u64,
+
+    pub(crate) open_bus: u8,
+}
+
+impl RealMapper for MMC5Mapper {
+    fn from_nesfile(nesfile: &NESFile) -> MMC5Mapper {
+        assert!(nesfile.mapper_type() == MapperType::MMC5);
+        let prg_rom_size = nesfile.prg_rom_size();
+        let chr_rom_size = nesfile.chr_rom_size();
+        let battery = nesfile.battery();
+
+        let prg_ram_size = if nesfile.is_nes20_format() {
+            if battery {
+                nesfile.prg_nvram_eeprom_size()
+            } else {
+                nesfile.prg_ram_size()
+            }
+        } else {
+            // The largest size used by any board, so any game works
+            DEFAULT_PRG_RAM_SIZE
+        };
+        let chr_ram_size = match nesfile.chr_ram_size() {
+            0 => DEFAULT_CHR_RAM_SIZE,
+            size => size,
+        };
+
+        info!("MMC5 with:");
+        info!(
+            "  PRG-ROM SIZE: {} (0x{:x}); {} 8KiB banks",
+            prg_rom_size,
+            prg_rom_size,
+            prg_rom_size / PRG_BANK_SIZE
+        );
+        info!("  PRG-RAM SIZE: {} (0x{:x})", prg_ram_size, prg_ram_size);
+        if chr_rom_size != 0 {
+            info!("  CHR-ROM SIZE: {} (0x{:x})", chr_rom_size, chr_rom_size);
+        } else {
+            info!("  CHR-RAM SIZE: {} (0x{:x})", chr_ram_size, chr_ram_size);
+        }
+        info!("  BATTERY: {}", battery);
+
+        let prg_rom = nesfile.data[0..prg_rom_size].to_vec();
+        let prg_ram = vec![0; prg_ram_size];
+        let (chr_rxm, chr_writable) = if chr_rom_size != 0 {
+            (nesfile.data[prg_rom_size..(prg_rom_size + chr_rom_size)].to_vec(), false)
+        } else {
+            (vec![0; chr_ram_size], true)
+        };
+
+        MMC5Mapper {
+            battery,
+            prg_rom,
+            prg_ram,
+            chr_rxm,
+            chr_writable,
+            exram: [0; EXRAM_SIZE],
+            // "$5117 is reliably $FF on power-up", and games rely on the last bank being mapped to $e000
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect1: 0,
+            prg_ram_protect2: 0,
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attr: 0,
+            prg_ram_bank: 0,
+            prg_banks: [0xff; 4],
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_last_b: false,
+            chr_upper: 0,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            sprites_large: false,
+            in_frame: false,
+            scanline: 0,
+            last_ppu_addr: 0,
+            nametable_reads: 0,
+            fetch_count: 0,
+            fetch: Fetch::Other,
+            ext_attr: 0,
+            in_split: false,
+            split_tile: 0,
+            split_fine_y: 0,
+            split_palette: 0,
+            pulse1: PulseChannel::without_sweep(1),
+            pulse2: PulseChannel::without_sweep(2),
+            pulse_enabled: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq_pending: false,
+            pcm_output: 0,
+            audio_cycles: 0,
+            open_bus: 0x00,
+        }
+    }
+}
+
+impl MMC5Mapper {
+    /// Where the given address in $8000-$ffff is mapped: whether it's ROM, and the 8KiB bank.
+    fn prg_bank(&self, addr: u16) -> (bool, usize) {
+        let slot = (addr as usize - 0x8000) / PRG_BANK_SIZE;
+        // The register used, the 8KiB bank within the (larger) bank it selects, and the bits of the register used
+        let (reg, offset, mask) = match (self.prg_mode, slot) {
+            (0, _) => (3, slot, 0x7c),
+            (1 | 2, 0..=1) => (1, slot, 0x7e),
+            (1, _) => (3, slot - 2, 0x7e),
+            (_, _) => (slot, 0, 0x7f),
+        };
+        let val = self.prg_banks[reg];
+        (reg == 3 || val & 0x80 != 0, (val & mask) as usize + offset)
+    }
+
+    fn prg_ram_addr(&self, bank: usize, addr: u16) -> Option<usize> {
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(((bank & 0b111) * PRG_BANK_SIZE + (addr as usize & 0x1fff)) % self.prg_ram.len())
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect1 == 0b10 && self.prg_ram_protect2 == 0b01
+    }
+
+    /// The CHR address with the A (sprite) or B (background) set of bank registers.
+    fn chr_addr(&self, addr: u16, background: bool) -> usize {
+        let size = 0x2000 >> self.chr_mode;
+        let slot = addr as usize / size;
+        // Larger banks use the last register of the ones they span, and the B set is used for both pattern tables
+        let reg = (slot + 1) * (8 >> self.chr_mode) - 1;
+        let bank = if background { self.chr_banks_b[reg & 0b11] } else { self.chr_banks_a[reg] };
+        (bank as usize * size + addr as usize % size) % self.chr_rxm.len()
+    }
+
+    /// The CHR address of a background fetch, which can be replaced by the split or the extended attributes.
+    fn background_chr_addr(&self, addr: u16) -> usize {
+        let addr = if self.in_split {
+            self.split_bank as usize * SPLIT_CHR_BANK_SIZE + (addr as usize & 0xff8) + self.split_fine_y
+        } else if self.exram_mode == 1 {
+            let bank = (self.ext_attr as usize & 0x3f) | (self.chr_upper as usize) << 6;
+            bank * SPLIT_CHR_BANK_SIZE + (addr as usize & 0xfff)
+        } else {
+            return self.chr_addr(addr, self.sprites_large || self.chr_last_b);
+        };
+        addr % self.chr_rxm.len()
+    }
+
+    fn split_contains(&self, tile: usize) -> bool {
+        let threshold = (self.split_control & 0x1f) as usize;
+        self.split_control & 0x80 != 0
+            && self.exram_mode <= 1
+            && if self.split_control & 0x40 == 0 { tile < threshold } else { tile >= threshold }
+    }
+
+    fn scanline_started(&mut self) {
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_scanline {
+                self.irq_pending = true;
+                debug!("IRQ trigger at scanline {}", self.scanline);
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+        self.fetch_count = 0;
+    }
+
+    fn frame_ended(&mut self) {
+        self.in_frame = false;
+        self.fetch = Fetch::Other;
+        self.in_split = false;
+        self.last_ppu_addr = 0;
+        self.nametable_reads = 0;
+    }
+
+    /// Keep track of what the PPU is fetching. Each scanline starts with the background tiles 2 to 33 (4 fetches
+    /// each), then the sprites (4 each), then tiles 0 and 1 of the next scanline, and two unused nametable fetches.
+    fn update_fetch(&mut self, addr: u16) {
+        let n = self.fetch_count as usize;
+        self.fetch = match n {
+            _ if !self.in_frame => Fetch::Other,
+            0..=127 | 160..=167 => Fetch::Background,
+            128..=159 => Fetch::Sprite,
+            _ => Fetch::Other,
+        };
+        if self.fetch != Fetch::Background || !n.is_multiple_of(4) {
+            return;
+        }
+
+        // The nametable fetch of a new tile
+        let (tile, line) = if n < 128 {
+            (n / 4 + 2, self.scanline as usize)
+        } else {
+            ((n - 160) / 4, self.scanline as usize + 1)
+        };
+        self.in_split = self.split_contains(tile);
+        if self.in_split {
+            let y = (self.split_scroll as usize + line) % 240;
+            let x = tile & 0x1f;
+            self.split_tile = (y / 8) * 32 + x;
+            self.split_fine_y = y % 8;
+            let attr = self.exram[0x3c0 + (y / 32) * 8 + x / 4];
+            self.split_palette = (attr >> ((((y / 16) & 1) << 2) | ((x / 2) & 1) << 1)) & 0b11;
+        } else if self.exram_mode == 1 {
+            self.ext_attr = self.exram[addr as usize & 0x3ff];
+        }
+    }
+
+    fn write_audio_status(&mut self, val: u8) {
+        self.pulse_enabled = val & 0b11;
+        if val & 0b01 == 0 {
+            self.pulse1.disable();
+        }
+        if val & 0b10 == 0 {
+            self.pulse2.disable();
+        }
+    }
+
+    /// A PCM sample, either written to $5011 or read by the CPU in read mode. Zero is never output.
+    fn pcm_sample(&mut self, val: u8) {
+        if val != 0 {
+            self.pcm_output = val;
+        } else if self.pcm_read_mode {
+            self.pcm_irq_pending = true;
+        }
+    }
+
+    pub(crate) fn irq_triggered(&self) -> bool {
+        (self.irq_pending && self.irq_enabled) || (self.pcm_irq_pending && self.pcm_irq_enabled)
+    }
+
+    pub(crate) fn sram(&self) -> &Vec<u8> {
+        &self.prg_ram
+    }
+
+    pub(crate) fn replace_sram(&mut self, sram: Vec<u8>) -> Result<(), std::io::Error> {
+        if self.prg_ram.len() != sram.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!(
+                    "Size of save RAM is incorrect, expected {} got {}",
+                    self.prg_ram.len(),
+                    sram.len()
+                )
+            ));
+        }
+
+        self.prg_ram = sram;
+        Ok(())
+    }
+
+    pub(crate) fn has_battery(&self) -> bool {
+        self.battery
+    }
+}
+
+/// An attribute byte with the same palette for all four quadrants.
+fn attribute_byte(palette: u8) -> u8 {
+    (palette & 0b11) * 0b0101_0101
+}
+
+impl Memory for MMC5Mapper {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.read_no_sideeffect(addr);
+        match addr {
+            0x5010 => self.pcm_irq_pending = false,
+            0x5204 => self.irq_pending = false,
+            0x8000..=0xbfff if self.pcm_read_mode => self.pcm_sample(val),
+            _ => {}
+        }
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> () {
+        match addr {
+            0x5000 => self.pulse1.write_0(val),
+            0x5002 => self.pulse1.write_2(val),
+            0x5003 => self.pulse1.write_3(val, self.pulse_enabled & 0b01 != 0),
+            0x5004 => self.pulse2.write_0(val),
+            0x5006 => self.pulse2.write_2(val),
+            0x5007 => self.pulse2.write_3(val, self.pulse_enabled & 0b10 != 0),
+            0x5010 => {
+                self.pcm_read_mode = val & 0x01 != 0;
+                self.pcm_irq_enabled = val & 0x80 != 0;
+            }
+            0x5011 if !self.pcm_read_mode => self.pcm_sample(val),
+            0x5015 => self.write_audio_status(val),
+            0x5100 => self.prg_mode = val & 0b11,
+            0x5101 => self.chr_mode = val & 0b11,
+            0x5102 => self.prg_ram_protect1 = val & 0b11,
+            0x5103 => self.prg_ram_protect2 = val & 0b11,
+            0x5104 => self.exram_mode = val & 0b11,
+            0x5105 => self.nametable_mapping = val,
+            0x5106 => self.fill_tile = val,
+            0x5107 => self.fill_attr = val & 0b11,
+            0x5113 => self.prg_ram_bank = val & 0b111,
+            0x5114..=0x5117 => self.prg_banks[(addr - 0x5114) as usize] = val,
+            0x5120..=0x5127 => {
+                self.chr_banks_a[(addr - 0x5120) as usize] = val as u16 | (self.chr_upper as u16) << 8;
+                self.chr_last_b = false;
+            }
+            0x5128..=0x512b => {
+                self.chr_banks_b[(addr - 0x5128) as usize] = val as u16 | (self.chr_upper as u16) << 8;
+                self.chr_last_b = true;
+            }
+            0x5130 => self.chr_upper = val & 0b11,
+            0x5200 => self.split_control = val,
+            0x5201 => self.split_scroll = val,
+            0x5202 => self.split_bank = val,
+            0x5203 => self.irq_scanline = val,
+            0x5204 => self.irq_enabled = val & 0x80 != 0,
+            0x5205 => self.multiplicand = val,
+            0x5206 => self.multiplier = val,
+            0x5c00..=0x5fff => {
+                let i = (addr - 0x5c00) as usize;
+                match self.exram_mode {
+                    // Only writable while rendering, otherwise zero is written
+                    0 | 1 => self.exram[i] = if self.in_frame { val } else { 0 },
+                    2 => self.exram[i] = val,
+                    _ => {}
+                }
+            }
+            0x6000..=0x7fff => {
+                if self.prg_ram_writable() && let Some(i) = self.prg_ram_addr(self.prg_ram_bank as usize, addr) {
+                    self.prg_ram[i] = val;
+                }
+            }
+            0x8000..=0xdfff => {
+                let (rom, bank) = self.prg_bank(addr);
+                if !rom && self.prg_ram_writable() && let Some(i) = self.prg_ram_addr(bank, addr) {
+                    self.prg_ram[i] = val;
+                }
+            }
+            _ => {}
+        }
+
+        if (0x5100..=0x5130).contains(&addr) {
+            debug!("Wrote ${val:02x} to ${addr:04x}");
+        }
+    }
+}
+
+impl Mapper for MMC5Mapper {
+    fn read_chr(&self, addr: u16) -> u8 {
+        let addr = match self.fetch {
+            Fetch::Background => self.background_chr_addr(addr),
+            Fetch::Sprite if self.sprites_large => self.chr_addr(addr, false),
+            _ => self.chr_addr(addr, self.chr_last_b),
+        };
+        self.chr_rxm[addr]
+    }
+
+    fn write_chr(&mut self, addr: u16, val: u8) -> () {
+        if self.chr_writable {
+            let addr = self.chr_addr(addr, self.chr_last_b);
+            self.chr_rxm[addr] = val;
+        }
+    }
+
+    fn nametable_read(&self, addr: u16, vram: [u8; ppu::VRAM_SIZE]) -> u8 {
+        let offset = addr as usize & 0x3ff;
+        let attribute = offset >= 0x3c0;
+
+        if self.fetch == Fetch::Background {
+            if self.in_split {
+                return if attribute { attribute_byte(self.split_palette) } else { self.exram[self.split_tile] };
+            } else if attribute && self.exram_mode == 1 {
+                return attribute_byte(self.ext_attr >> 6);
+            }
+        }
+
+        match (self.nametable_mapping >> (((addr >> 10) & 0b11) * 2)) & 0b11 {
+            0 => vram[offset],
+            1 => vram[0x400 + offset],
+            2 if self.exram_mode <= 1 => self.exram[offset],
+            2 => 0,
+            _ if attribute => attribute_byte(self.fill_attr),
+            _ => self.fill_tile,
+        }
+    }
+
+    fn nametable_write(&mut self, addr: u16, val: u8, vram: &mut [u8; ppu::VRAM_SIZE]) -> () {
+        let offset = addr as usize & 0x3ff;
+        match (self.nametable_mapping >> (((addr >> 10) & 0b11) * 2)) & 0b11 {
+            0 => vram[offset] = val,
+            1 => vram[0x400 + offset] = val,
+            2 if self.exram_mode <= 1 => self.exram[offset] = val,
+            _ => {}
+        }
+    }
+
+    fn read_no_sideeffect(&self, addr: u16) -> u8 {
+        match addr {
+            0x5010 => (self.pcm_irq_pending as u8) << 7 | self.pcm_read_mode as u8,
+            0x5015 => self.pulse1.length_counter_active() as u8 | (self.pulse2.length_counter_active() as u8) << 1,
+            0x5204 => (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6,
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5c00..=0x5fff if self.exram_mode >= 2 => self.exram[(addr - 0x5c00) as usize],
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => {
+                self.prg_ram[self.prg_ram_addr(self.prg_ram_bank as usize, addr).unwrap()]
+            }
+            0x4020..=0x7fff => {
+                info!("Open bus read at ${addr:04x}");
+                self.open_bus
+            }
+            0x8000..=0xffff => {
+                let (rom, bank) = self.prg_bank(addr);
+                if rom {
+                    self.prg_rom[(bank * PRG_BANK_SIZE + (addr as usize & 0x1fff)) % self.prg_rom.len()]
+                } else if let Some(i) = self.prg_ram_addr(bank, addr) {
+                    self.prg_ram[i]
+                } else {
+                    self.open_bus
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> () {
+        if (0x2000..=0x2fff).contains(&addr) && addr == self.last_ppu_addr {
+            self.nametable_reads += 1;
+        } else {
+            self.nametable_reads = 0;
+        }
+        self.last_ppu_addr = addr;
+
+        if self.nametable_reads == 2 {
+            self.scanline_started();
+        } else {
+            self.fetch_count = self.fetch_count.saturating_add(1);
+        }
+        self.update_fetch(addr);
+    }
+
+    fn ppu_register_written(&mut self, addr: u16, val: u8) -> () {
+        match addr {
+            0x2000 => self.sprites_large = val & 0x20 != 0,
+            0x2001 if val & 0x18 == 0 => self.frame_ended(),
+            _ => {}
+        }
+    }
+
+    fn vblank_started(&mut self) -> () {
+        self.frame_ended();
+        self.irq_pending = false;
+    }
+
+    fn expansion_audio_chip(&self) -> Option<ExpansionChip> {
+        Some(ExpansionChip::MMC5)
+    }
+
+    fn clock_expansion_audio(&mut self) -> () {
+        self.audio_cycles += 1;
+        if self.audio_cycles.is_multiple_of(2) {
+            self.pulse1.tick();
+            self.pulse2.tick();
+        }
+        if self.audio_cycles.is_multiple_of(AUDIO_FRAME_PERIOD) {
+            // Unlike the APU's, quarter and half frames happen at the same rate
+            for pulse in [&mut self.pulse1, &mut self.pulse2] {
+                pulse.clock_envelope();
+                pulse.clock_length_counter();
+            }
+        }
+    }
+
+    fn expansion_audio_output(&self) -> f32 {
+        // Each pulse channel and the PCM channel count as one APU pulse channel at full volume
+        let pulses = (self.pulse1.output() + self.pulse2.output()) as f32 / 15.0;
+        let pcm = self.pcm_output as f32 / 255.0;
+        (pulses + pcm) / ExpansionChip::MMC5.relative_level()
+    }
+}
+
+impl Snapshot for MMC5Mapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.prg_ram);
+        if self.chr_writable {
+            w.write_vec(&self.chr_rxm);
+        }
+        w.write(&self.exram);
+
+        w.write(&self.prg_mode);
+        w.write(&self.chr_mode);
+        w.write(&self.prg_ram_protect1);
+        w.write(&self.prg_ram_protect2);
+        w.write(&self.exram_mode);
+        w.write(&self.nametable_mapping);
+        w.write(&self.fill_tile);
+        w.write(&self.fill_attr);
+        w.write(&self.prg_ram_bank);
+        w.write(&self.prg_banks);
+        for bank in self.chr_banks_a.iter().chain(&self.chr_banks_b) {
+            w.write(bank);
+        }
+        w.write(&self.chr_last_b);
+        w.write(&self.chr_upper);
+
+        w.write(&self.split_control);
+        w.write(&self.split_scroll);
+        w.write(&self.split_bank);
+
+        w.write(&self.irq_scanline);
+        w.write(&self.irq_enabled);
+        w.write(&self.irq_pending);
+        w.write(&self.multiplicand);
+        w.write(&self.multiplier);
+
+        w.write(&self.sprites_large);
+        w.write(&self.in_frame);
+        w.write(&self.scanline);
+        w.write(&self.last_ppu_addr);
+        w.write(&self.nametable_reads);
+        w.write(&self.fetch_count);
+        w.write(&(self.fetch as u8));
+        w.write(&self.ext_attr);
+        w.write(&self.in_split);
+        w.write(&self.split_tile);
+        w.write(&self.split_fine_y);
+        w.write(&self.split_palette);
+
+        self.pulse1.save_state(w);
+        self.pulse2.save_state(w);
+        w.write(&self.pulse_enabled);
+        w.write(&self.pcm_read_mode);
+        w.write(&self.pcm_irq_enabled);
+        w.write(&self.pcm_irq_pending);
+        w.write(&self.pcm_output);
+        w.write(&self.audio_cycles);
+
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        r.read_vec_into(&mut self.prg_ram)?;
+        if self.chr_writable {
+            r.read_vec_into(&mut self.chr_rxm)?;
+        }
+        self.exram = r.read()?;
+
+        self.prg_mode = r.read()?;
+        self.chr_mode = r.read()?;
+        self.prg_ram_protect1 = r.read()?;
+        self.prg_ram_protect2 = r.read()?;
+        self.exram_mode = r.read()?;
+        self.nametable_mapping = r.read()?;
+        self.fill_tile = r.read()?;
+        self.fill_attr = r.read()?;
+        self.prg_ram_bank = r.read()?;
+        self.prg_banks = r.read()?;
+        for bank in self.chr_banks_a.iter_mut().chain(&mut self.chr_banks_b) {
+            *bank = r.read()?;
+        }
+        self.chr_last_b = r.read()?;
+        self.chr_upper = r.read()?;
+
+        self.split_control = r.read()?;
+        self.split_scroll = r.read()?;
+        self.split_bank = r.read()?;
+
+        self.irq_scanline = r.read()?;
+        self.irq_enabled = r.read()?;
+        self.irq_pending = r.read()?;
+        self.multiplicand = r.read()?;
+        self.multiplier = r.read()?;
+
+        self.sprites_large = r.read()?;
+        self.in_frame = r.read()?;
+        self.scanline = r.read()?;
+        self.last_ppu_addr = r.read()?;
+        self.nametable_reads = r.read()?;
+        self.fetch_count = r.read()?;
+        self.fetch = match r.read::<u8>()? {
+            0 => Fetch::Other,
+            1 => Fetch::Background,
+            2 => Fetch::Sprite,
+            v => return Err(Error::new(ErrorKind::InvalidData, format!("Invalid MMC5 fetch state: {v}"))),
+        };
+        self.ext_attr = r.read()?;
+        self.in_split = r.read()?;
+        self.split_tile = r.read()?;
+        self.split_fine_y = r.read()?;
+        self.split_palette = r.read()?;
+
+        self.pulse1.load_state(r)?;
+        self.pulse2.load_state(r)?;
+        self.pulse_enabled = r.read()?;
+        self.pcm_read_mode = r.read()?;
+        self.pcm_irq_enabled = r.read()?;
+        self.pcm_irq_pending = r.read()?;
+        self.pcm_output = r.read()?;
+        self.audio_cycles = r.read()?;
+
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fc::{bus::Bus, clock::MasterClock, cpu::CPU, mem::{MapperImpl, MemMap}, region::Region};
+    use crate::fc::mem::mapper::test::test_nesfile;
+
+    /// Sets up an IRQ at scanline 3 and enables rendering, with IRQs left disabled in the CPU.
+    #[rustfmt::skip]
+    const IRQ_PROGRAM: [u8; 18] = [
+        0xa9, 0x03, 0x8d, 0x03, 0x52, // lda #$03; sta $5203  (IRQ scanline)
+        0xa9, 0x80, 0x8d, 0x04, 0x52, // lda #$80; sta $5204  (enable IRQ)
+        0xa9, 0x18, 0x8d, 0x01, 0x20, // lda #$18; sta $2001  (enable rendering)
+        0x4c, 0x0f, 0xe0,             // loop: jmp loop
+    ];
+
+    /// A bus with an MMC5 cartridge running `program` from $e000 (the last bank, which is mapped there on power-up.)
+    fn test_bus(program: &[u8]) -> Bus {
+        let mut data = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0x50, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xea; 0x4000];
+        prg[0x2000..0x2000 + program.len()].copy_from_slice(program);
+        prg[0x3ffa..0x4000].copy_from_slice(&[0x00, 0xe0, 0x00, 0xe0, 0x00, 0xe0]);
+        data.extend(prg);
+        data.extend([0; 0x2000]);
+
+        let mem = MemMap::from_nesfile(&NESFile::from_vec(data).unwrap()).unwrap();
+        Bus::new(mem, MasterClock::new(Region::NTSC, 0))
+    }
+
+    fn mapper(bus: &mut Bus) -> &mut MMC5Mapper {
+        match bus.mem.mapper.as_mut() {
+            MapperImpl::MMC5(m) => m,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn prg_banking_test() {
+        // 16 8KiB banks
+        let mut m = MMC5Mapper::from_nesfile(&test_nesfile(5, 0, 8, 1));
+        assert_eq!(m.read(0xe000), 7);
+
+        m.write(0x5100, 1);
+        m.write(0x5115, 0x85);
+        m.write(0x5117, 0x0b);
+        assert_eq!([0x8000, 0xa000, 0xc000, 0xe000].map(|a| m.read(a)), [2, 2, 5, 5]);
+
+        m.write(0x5100, 2);
+        m.write(0x5116, 0x87);
+        assert_eq!([0x8000, 0xc000, 0xe000].map(|a| m.read(a)), [2, 3, 5]);
+
+        // RAM at $c000, only writable once unlocked
+        m.prg_ram = vec![0; 0x10000];
+        m.write(0x5116, 0x01);
+        m.write(0xc000, 0x42);
+        assert_eq!(m.read(0xc000), 0);
+        m.write(0x5102, 0b10);
+        m.write(0x5103, 0b01);
+        m.write(0xc000, 0x42);
+        m.write(0x5113, 1);
+        assert_eq!((m.read(0xc000), m.read(0x6000)), (0x42, 0x42));
+    }
+
+    #[test]
+    fn scanline_irq_test() {
+        let mut bus = test_bus(&IRQ_PROGRAM);
+        let mut cpu = CPU::new();
+        bus.ppu.init();
+        cpu.init(&mut bus);
+
+        // Rendering is only enabled during the first frame, so start counting from the second one
+        while !bus.ppu.is_vblank() {
+            cpu.fetch_and_run(&mut bus);
+        }
+        assert_eq!(bus.read_no_sideeffect(0x5204), 0x00);
+
+        // The counter restarts every frame, so the IRQ is at the same scanline in all of them
+        for _ in 0..2 {
+            while bus.ppu.is_vblank() || !mapper(&mut bus).irq_triggered() {
+                cpu.fetch_and_run(&mut bus);
+            }
+            assert_eq!(bus.ppu.scanlines(), 3);
+            assert_eq!(bus.read(0x5204), 0xc0);
+            assert!(!mapper(&mut bus).irq_triggered());
+
+            while !bus.ppu.is_vblank() {
+                cpu.fetch_and_run(&mut bus);
+            }
+            assert_eq!(bus.read_no_sideeffect(0x5204), 0x00);
+        }
+    }
+
+    #[test]
+    fn nametable_and_fill_mode_test() {
+        let mut m = MMC5Mapper::from_nesfile(&test_nesfile(5, 0, 2, 1));
+        let mut vram = [0; ppu::VRAM_SIZE];
+        // CIRAM page 1, page 0, ExRAM, fill mode
+        m.write(0x5105, 0b11_10_00_01);
+        m.write(0x5106, 0x24);
+        m.write(0x5107, 0x02);
+
+        m.nametable_write(0x2005, 0x11, &mut vram);
+        m.nametable_write(0x2805, 0x22, &mut vram);
+        assert_eq!((vram[0x405], m.exram[5]), (0x11, 0x22));
+        assert_eq!((m.nametable_read(0x2c05, vram), m.nametable_read(0x2fc0, vram)), (0x24, 0xaa));
+    }
+
+    #[test]
+    fn audio_test() {
+        let mut m = MMC5Mapper::from_nesfile(&test_nesfile(5, 0, 2, 1));
+        assert_eq!(m.expansion_audio_chip(), Some(ExpansionChip::MMC5));
+
+        // Pulse 1 at constant volume 15 with a 50% duty cycle, and a period below the APU's sweep muting limit
+        m.write(0x5015, 0x01);
+        m.write(0x5000, 0xbf);
+        m.write(0x5002, 0x04);
+        m.write(0x5003, 0x08);
+        assert_eq!(m.read(0x5015), 0x01);
+        let max = (0..100)
+            .map(|_| {
+                m.clock_expansion_audio();
+                m.expansion_audio_output()
+            })
+            .fold(0.0, f32::max);
+        assert_eq!(max, 1.0 / 3.0);
+
+        // Disabling the channel silences it
+        m.write(0x5015, 0x00);
+        assert_eq!((m.read(0x5015), m.expansion_audio_output()), (0x00, 0.0));
+
+        // PCM write mode, where zero is ignored
+        m.write(0x5011, 0xff);
+        m.write(0x5011, 0x00);
+        assert_eq!(m.expansion_audio_output(), 1.0 / 3.0);
+
+        // Read mode, with an IRQ when a zero is read
+        m.write(0x5114, 0x80);
+        m.write(0x5010, 0x81);
+        assert_eq!(m.read(0x8000), 0);
+        assert!(m.irq_triggered());
+        assert_eq!(m.read(0x5010), 0x81);
+        assert!(!m.irq_triggered());
+        m.write(0x5114, 0x82);
+        assert_eq!(m.read(0x8000), 1);
+        assert_eq!(m.pcm_output, 1);
+    }
+
+    #[test]
+    fn multiplier_test() {
+        let mut m = MMC5Mapper::from_nesfile(&test_nesfile(5, 0, 2, 1));
+        m.write(0x5205, 200);
+        m.write(0x5206, 100);
+        assert_eq!((m.read(0x5205), m.read(0x5206)), (0x20, 0x4e));
+    }
+}
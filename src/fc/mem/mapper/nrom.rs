@@ -1,3 +1,5 @@
+use std::io::Error;
+
 use log::info;
 
 use crate::fc::{
@@ -7,6 +9,7 @@ use crate::fc::{
         mapper::{Mapper, MapperType, RealMapper},
     },
     ppu,
+    state::{Snapshot, StateReader, StateWriter},
 };
 
 const NROM256_PRG_ROM_SIZE: usize = 32_768;
@@ -140,3 +143,16 @@ impl Mapper for NROMMapper {
         }
     }
 }
+
+impl Snapshot for NROMMapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_vec(&self.prg_ram);
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        r.read_vec_into(&mut self.prg_ram)?;
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
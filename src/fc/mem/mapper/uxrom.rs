@@ -0,0 +1,303 @@
+// UxROM (mapper 2) and UNROM 512 (mapper 30), see:
+// https://www.nesdev.org/wiki/UxROM
+// https://www.nesdev.org/wiki/UNROM_512
+
+use std::io::Error;
+
+use log::{debug, info};
+
+use crate::fc::{
+    mem::{
+        Memory,
+        NametableArrangement::{self, SingleScreenA, SingleScreenB},
+        cart::NESFile,
+        mapper::{Mapper, MapperType, RealMapper},
+    },
+    ppu,
+    state::{Snapshot, StateReader, StateWriter},
+};
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+const DEFAULT_CHR_RAM_SIZE: usize = 0x2000;
+const UNROM512_DEFAULT_CHR_RAM_SIZE: usize = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Board {
+    /// UNROM/UOROM: the bank register selects the 16KiB PRG bank at $8000.
+    UxROM,
+    /// UNROM 512: the bank register also selects the 8KiB CHR-RAM bank, and the single screen nametable.
+    UNROM512,
+}
+
+/// How the nametables of UNROM 512 are arranged (selected with bits 0 and 3 of header byte 6.)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum UNROM512Nametables {
+    Fixed,
+    /// Selected by bit 7 of the bank register.
+    SingleScreen,
+    /// Stored in the last 8KiB of CHR-RAM.
+    FourScreen,
+}
+
+pub struct UxROMMapper {
+    board: Board,
+    prg_rom: Vec<u8>,
+    chr_rxm: Vec<u8>,
+    chr_writable: bool,
+    /// Whether a written value is ANDed with the ROM byte at the written address, as both drive the data bus.
+    bus_conflicts: bool,
+    /// Whether the PRG-ROM is a flash chip the game can write to (UNROM 512 only.)
+    flashable: bool,
+    nametables: UNROM512Nametables,
+    nametable_arrange: NametableArrangement,
+    bank: u8,
+    pub(crate) open_bus: u8,
+}
+
+impl RealMapper for UxROMMapper {
+    fn from_nesfile(nesfile: &NESFile) -> UxROMMapper {
+        assert!(nesfile.mapper_type() == MapperType::UxROM);
+        let prg_rom_size = nesfile.prg_rom_size();
+        let chr_rom_size = nesfile.chr_rom_size();
+
+        let board = if nesfile.mapper_number() == 30 { Board::UNROM512 } else { Board::UxROM };
+
+        let bus_conflicts = match (board, nesfile.submapper_number()) {
+            // Submapper 0 is unspecified, which most games work fine with as they avoid conflicts anyway
+            (Board::UxROM, 2) => true,
+            (Board::UxROM, _) => false,
+            // Flashable boards (marked by the battery bit) don't have bus conflicts, the others do
+            (Board::UNROM512, 0) => !nesfile.battery(),
+            (Board::UNROM512, 1) => true,
+            (Board::UNROM512, _) => false,
+        };
+
+        let flashable = board == Board::UNROM512 && nesfile.battery();
+
+        let nametables = match (board, nesfile.alt_nametable_layout(), nesfile.nametable_layout()) {
+            (Board::UNROM512, true, false) => UNROM512Nametables::SingleScreen,
+            (Board::UNROM512, true, true) => UNROM512Nametables::FourScreen,
+            _ => UNROM512Nametables::Fixed,
+        };
+        let nametable_arrange = match nametables {
+            UNROM512Nametables::SingleScreen => SingleScreenA,
+            _ => NametableArrangement::from_header(nesfile),
+        };
+
+        let chr_ram_size = match (nesfile.chr_ram_size(), board) {
+            (0, Board::UxROM) => DEFAULT_CHR_RAM_SIZE,
+            (0, Board::UNROM512) => UNROM512_DEFAULT_CHR_RAM_SIZE,
+            (size, _) => size,
+        };
+
+        info!("{:?} with:", board);
+        info!("  PRG-ROM SIZE: {} (0x{:x}); {} banks", prg_rom_size, prg_rom_size, prg_rom_size / PRG_BANK_SIZE);
+        if chr_rom_size != 0 {
+            info!("  CHR-ROM SIZE: {} (0x{:x})", chr_rom_size, chr_rom_size);
+        } else {
+            info!("  CHR-RAM SIZE: {} (0x{:x})", chr_ram_size, chr_ram_size);
+        }
+        info!("  Bus conflicts: {}", bus_conflicts);
+        info!("  Flashable: {}", flashable);
+        info!("  Nametable mirroring: {:?} ({:?})", nametable_arrange, nametables);
+
+        let prg_rom = nesfile.data[0..prg_rom_size].to_vec();
+        let (chr_rxm, chr_writable) = if chr_rom_size != 0 {
+            (nesfile.data[prg_rom_size..(prg_rom_size + chr_rom_size)].to_vec(), false)
+        } else {
+            (vec![0; chr_ram_size], true)
+        };
+
+        UxROMMapper {
+            board,
+            prg_rom,
+            chr_rxm,
+            chr_writable,
+            bus_conflicts,
+            flashable,
+            nametables,
+            nametable_arrange,
+            bank: 0,
+            open_bus: 0x00,
+        }
+    }
+}
+
+impl UxROMMapper {
+    fn prg_bank(&self) -> usize {
+        match self.board {
+            Board::UxROM => self.bank as usize,
+            Board::UNROM512 => self.bank as usize & 0x1f,
+        }
+    }
+
+    fn chr_addr(&self, addr: u16) -> usize {
+        let bank = match self.board {
+            Board::UxROM => 0,
+            Board::UNROM512 => (self.bank as usize >> 5) & 0b11,
+        };
+        (bank * CHR_BANK_SIZE + addr as usize) % self.chr_rxm.len()
+    }
+
+    /// The CHR-RAM address of a nametable address, when the nametables are stored in CHR-RAM (UNROM 512 four screen.)
+    fn four_screen_addr(&self, addr: u16) -> usize {
+        (0x6000 + (addr as usize & 0x1fff)) % self.chr_rxm.len()
+    }
+
+    fn write_bank(&mut self, val: u8) {
+        self.bank = val;
+
+        if self.nametables == UNROM512Nametables::SingleScreen {
+            self.nametable_arrange = if val & 0x80 == 0 { SingleScreenA } else { SingleScreenB };
+        }
+
+        debug!("Set bank register to 0x{0:02x} (0b{0:08b})", val);
+    }
+}
+
+impl Memory for UxROMMapper {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_no_sideeffect(addr)
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> () {
+        if addr < 0x8000 {
+            return;
+        }
+        // On flashable boards, only $c000-$ffff is the bank register
+        // TODO: the flash commands written to $8000-$bfff (used for saving)
+        if self.flashable && addr < 0xc000 {
+            return;
+        }
+
+        let val = if self.bus_conflicts {
+            val & self.read_no_sideeffect(addr)
+        } else {
+            val
+        };
+        self.write_bank(val);
+    }
+}
+
+impl Mapper for UxROMMapper {
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr_rxm[self.chr_addr(addr)]
+    }
+
+    fn write_chr(&mut self, addr: u16, val: u8) -> () {
+        if self.chr_writable {
+            let addr = self.chr_addr(addr);
+            self.chr_rxm[addr] = val;
+        }
+    }
+
+    fn nametable_read(&self, addr: u16, vram: [u8; ppu::VRAM_SIZE]) -> u8 {
+        if self.nametables == UNROM512Nametables::FourScreen {
+            return self.chr_rxm[self.four_screen_addr(addr)];
+        }
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize]
+    }
+
+    fn nametable_write(&mut self, addr: u16, val: u8, vram: &mut [u8; ppu::VRAM_SIZE]) -> () {
+        if self.nametables == UNROM512Nametables::FourScreen {
+            let addr = self.four_screen_addr(addr);
+            self.chr_rxm[addr] = val;
+            return;
+        }
+        vram[self.nametable_arrange.nametable_addr_fix(addr) as usize] = val
+    }
+
+    fn read_no_sideeffect(&self, addr: u16) -> u8 {
+        let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+
+        match addr {
+            0x4020..=0x7fff => {
+                info!("Open bus read at ${addr:04x}");
+                self.open_bus
+            }
+            0x8000..=0xbfff => {
+                // "16 KB switchable PRG ROM bank"
+                let bank = self.prg_bank() % banks;
+                self.prg_rom[bank * PRG_BANK_SIZE + (addr - 0x8000) as usize]
+            }
+            0xc000..=0xffff => {
+                // "16 KB PRG ROM bank, fixed to the last bank"
+                self.prg_rom[(banks - 1) * PRG_BANK_SIZE + (addr - 0xc000) as usize]
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Snapshot for UxROMMapper {
+    fn save_state(&self, w: &mut StateWriter) {
+        if self.chr_writable {
+            w.write_vec(&self.chr_rxm);
+        }
+        w.write(&self.nametable_arrange);
+        w.write(&self.bank);
+        w.write(&self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        if self.chr_writable {
+            r.read_vec_into(&mut self.chr_rxm)?;
+        }
+        self.nametable_arrange = r.read()?;
+        self.bank = r.read()?;
+        self.open_bus = r.read()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fc::mem::mapper::test::test_nesfile;
+
+    /// 8 PRG banks and CHR-RAM.
+    fn nesfile(mapper: u16, submapper: u8) -> NESFile {
+        test_nesfile(mapper, submapper, 8, 0)
+    }
+
+    #[test]
+    fn bank_switching_test() {
+        let mut m = UxROMMapper::from_nesfile(&nesfile(2, 1));
+        assert_eq!((m.read(0x8000), m.read(0xffff)), (0, 7));
+        m.write(0xc000, 3);
+        assert_eq!((m.read(0xbfff), m.read(0xc000)), (3, 7));
+
+        m.write_chr(0x1234, 0x42);
+        assert_eq!(m.read_chr(0x1234), 0x42);
+    }
+
+    #[test]
+    fn bus_conflict_test() {
+        // The written value is ANDed with the ROM byte (the bank number of the fixed bank, 7)
+        let mut m = UxROMMapper::from_nesfile(&nesfile(2, 2));
+        m.write(0xc000, 0x0d);
+        assert_eq!(m.read(0x8000), 5);
+
+        let mut m = UxROMMapper::from_nesfile(&nesfile(2, 1));
+        m.write(0xc000, 0x0d);
+        assert_eq!(m.read(0x8000), 0x0d % 8);
+    }
+
+    #[test]
+    fn unrom512_test() {
+        let mut m = UxROMMapper::from_nesfile(&nesfile(30, 3));
+        assert_eq!(m.chr_rxm.len(), UNROM512_DEFAULT_CHR_RAM_SIZE);
+
+        // Select PRG bank 2 and CHR-RAM bank 1
+        m.write(0x8000, 0x22);
+        assert_eq!(m.read(0x8000), 2);
+        m.write_chr(0x0010, 0x42);
+        assert_eq!(m.chr_rxm[CHR_BANK_SIZE + 0x10], 0x42);
+
+        // With bus conflicts, the CHR bank bits are cleared by the ROM byte
+        let mut m = UxROMMapper::from_nesfile(&nesfile(30, 1));
+        m.write(0xc000, 0x27);
+        assert_eq!((m.read(0x8000), m.chr_addr(0)), (7, 0));
+    }
+}
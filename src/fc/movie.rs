@@ -0,0 +1,236 @@
+//! Input movies: the controller input (and resets) of every frame, for reproducing a run exactly.
+//!
+//! A movie starts either on power-on or from an embedded save state. All values are stored little endian.
+//!
+//! | Offset | Size | Contents                                                  |
+//! |--------|------|-----------------------------------------------------------|
+//! | 0      | 8    | Magic (`RFCMOVIE`)                                        |
+//! | 8      | 4    | Format version ([MOVIE_VERSION])                          |
+//! | 12     | 8    | ROM hash (see [NESFile::hash])                            |
+//! | 20     | 1    | Region (0: NTSC, 1: PAL, 2: Dendy)                        |
+//! | 21     | 1    | Start (0: power-on, 1: save state)                        |
+//! | 22     | ..   | Save state (u32 length + data, only if starting from one) |
+//! | ..     | 4    | Number of frames                                          |
+//! | ..     | ..   | Frames (see [MovieFrame])                                 |
+//!
+//! FCEUX movies (`.fm2`) can be imported and exported as well, see [fm2].
+//!
+//! [NESFile::hash]: crate::fc::mem::cart::NESFile::hash
+
+use std::io::{Error, ErrorKind};
+
+use super::input::StandardControllerState;
+use super::region::Region;
+use super::state::{StateReader, StateValue, StateWriter};
+
+pub mod fm2;
+
+const MOVIE_MAGIC: [u8; 8] = *b"RFCMOVIE";
+
+/// The version of the movie format.
+pub const MOVIE_VERSION: u32 = 1;
+
+/// A reset, performed right before a frame is run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieReset {
+    Soft,
+    Hard,
+}
+
+/// The input of a single frame.
+///
+/// Stored as the buttons of both joypads (see [StandardControllerState::to_bits]), the reset (0: none, 1: soft,
+/// 2: hard) and the optional checksum.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MovieFrame {
+    pub joy1: StandardControllerState,
+    pub joy2: StandardControllerState,
+    pub reset: Option<MovieReset>,
+    /// Checksum of the machine after running the frame (see [FC::frame_checksum]), used to detect desyncs.
+    ///
+    /// [FC::frame_checksum]: crate::fc::FC::frame_checksum
+    pub checksum: Option<u64>,
+}
+
+impl StateValue for MovieFrame {
+    fn write_to(&self, w: &mut StateWriter) {
+        w.write(&self.joy1.to_bits());
+        w.write(&self.joy2.to_bits());
+        w.write(&match self.reset {
+            None => 0u8,
+            Some(MovieReset::Soft) => 1,
+            Some(MovieReset::Hard) => 2,
+        });
+        w.write(&self.checksum);
+    }
+
+    fn read_from(r: &mut StateReader) -> Result<Self, Error> {
+        let joy1 = StandardControllerState::from_bits(r.read()?);
+        let joy2 = StandardControllerState::from_bits(r.read()?);
+        let reset = match r.read::<u8>()? {
+            0 => None,
+            1 => Some(MovieReset::Soft),
+            2 => Some(MovieReset::Hard),
+            n => return Err(Error::new(ErrorKind::InvalidData, format!("invalid reset type {n} in movie"))),
+        };
+        let checksum = r.read()?;
+        Ok(MovieFrame { joy1, joy2, reset, checksum })
+    }
+}
+
+/// The state the emulator is in when a movie starts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MovieStart {
+    PowerOn,
+    /// A save state (see [FC::save_state](crate::fc::FC::save_state)).
+    SaveState(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub region: Region,
+    pub start: MovieStart,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(rom_hash: u64, region: Region, start: MovieStart) -> Movie {
+        Movie {
+            rom_hash,
+            region,
+            start,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.write_bytes(&MOVIE_MAGIC);
+        w.write(&MOVIE_VERSION);
+        w.write(&self.rom_hash);
+        w.write(&self.region);
+        match &self.start {
+            MovieStart::PowerOn => w.write(&0u8),
+            MovieStart::SaveState(state) => {
+                w.write(&1u8);
+                w.write_vec(state);
+            }
+        }
+        w.write(&(self.frames.len() as u32));
+        for frame in &self.frames {
+            w.write(frame);
+        }
+        w.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Movie, Error> {
+        let mut r = StateReader::new(data);
+
+        let mut magic = [0; 8];
+        r.read_bytes(&mut magic)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "not a movie"))?;
+        if magic != MOVIE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a movie"));
+        }
+
+        let version: u32 = r.read()?;
+        if version != MOVIE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported movie version {version} (expected {MOVIE_VERSION})"),
+            ));
+        }
+
+        let rom_hash = r.read()?;
+        let region = r.read()?;
+        let start = match r.read::<u8>()? {
+            0 => MovieStart::PowerOn,
+            1 => MovieStart::SaveState(r.read_vec()?),
+            n => return Err(Error::new(ErrorKind::InvalidData, format!("invalid movie start {n}"))),
+        };
+
+        let frame_count = r.read::<u32>()?;
+        let frames = (0..frame_count).map(|_| r.read()).collect::<Result<Vec<MovieFrame>, Error>>()?;
+        if !r.is_at_end() {
+            return Err(Error::new(ErrorKind::InvalidData, "unexpected data at the end of the movie"));
+        }
+
+        Ok(Movie { rom_hash, region, start, frames })
+    }
+}
+
+/// Whether a movie is being recorded or played back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+}
+
+/// The progress of the movie currently being recorded or played back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovieStatus {
+    pub mode: MovieMode,
+    /// The number of frames recorded or played back so far.
+    pub frame: usize,
+    pub length: usize,
+    /// The first frame whose checksum didn't match the one in the movie (when playing back.)
+    pub desync_frame: Option<usize>,
+}
+
+/// A movie being recorded or played back by the emulator.
+pub(crate) struct MovieSession {
+    pub movie: Movie,
+    pub mode: MovieMode,
+    pub frame: usize,
+    pub desync_frame: Option<usize>,
+    /// The input set by the frontend, which is recorded at the start of the next frame.
+    pub live_input: (StandardControllerState, StandardControllerState),
+    /// A reset performed since the last frame, which is recorded with the next one.
+    pub pending_reset: Option<MovieReset>,
+}
+
+impl MovieSession {
+    pub fn new(movie: Movie, mode: MovieMode) -> MovieSession {
+        MovieSession {
+            movie,
+            mode,
+            frame: 0,
+            desync_frame: None,
+            live_input: Default::default(),
+            pending_reset: None,
+        }
+    }
+
+    pub fn status(&self) -> MovieStatus {
+        MovieStatus {
+            mode: self.mode,
+            frame: self.frame,
+            length: self.movie.frames.len(),
+            desync_frame: self.desync_frame,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn movie_round_trip_test() {
+        let mut movie = Movie::new(0x1234, Region::PAL, MovieStart::SaveState(vec![1, 2, 3]));
+        movie.frames.push(MovieFrame::default());
+        movie.frames.push(MovieFrame {
+            joy1: StandardControllerState { a: true, right: true, ..Default::default() },
+            joy2: StandardControllerState { start: true, ..Default::default() },
+            reset: Some(MovieReset::Hard),
+            checksum: Some(0xdead_beef),
+        });
+
+        let bytes = movie.to_bytes();
+        assert_eq!(Movie::from_bytes(&bytes).unwrap(), movie);
+
+        assert!(Movie::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Movie::from_bytes(b"RFCSTATE").is_err());
+    }
+}
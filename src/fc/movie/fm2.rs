@@ -0,0 +1,338 @@
+//! Importing and exporting FCEUX movies (`.fm2`).
+//!
+//! An FM2 file is a text file, consisting of a header of `key value` lines followed by one line per frame:
+//!
+//! ```text
+//! |<commands>|<port 0>|<port 1>|<port 2>|
+//! ```
+//!
+//! Where the commands are a bitfield (1: soft reset, 2: hard reset, the rest are FDS/VS System specific), and each
+//! standard controller is written as `RLDUTSBA`, with `.` (or a space) for buttons that aren't held.
+//!
+//! Only movies using standard controllers and starting on power-on can be imported. Checksums aren't part of the
+//! format, so they are dropped on export.
+//!
+//! See: https://fceux.com/web/help/fm2.html
+
+use std::io::{Error, ErrorKind};
+
+use log::warn;
+
+use super::{Movie, MovieFrame, MovieReset, MovieStart};
+use crate::fc::input::StandardControllerState;
+use crate::fc::mem::cart::NESFile;
+use crate::fc::region::Region;
+use crate::fc::state::fnv1a_hash;
+
+const MOVIECMD_RESET: u32 = 1;
+const MOVIECMD_POWER: u32 = 2;
+
+/// `emuVersion` written to exported movies (FCEUX 2.6.0.)
+const EMU_VERSION: u32 = 20600;
+
+/// The buttons of a standard controller, in the order they are written.
+const BUTTONS: [char; 8] = ['R', 'L', 'D', 'U', 'T', 'S', 'B', 'A'];
+
+/// The checksum FCEUX identifies ROMs with: the MD5 of the PRG and CHR ROM (without the header and trainer.)
+pub fn rom_checksum(nesfile: &NESFile) -> [u8; 16] {
+    let start = if nesfile.trainer() { 512 } else { 0 };
+    let end = (start + nesfile.prg_rom_size() + nesfile.chr_rom_size()).min(nesfile.data.len());
+    md5(&nesfile.data[start.min(end)..end])
+}
+
+/// Import an FM2 movie, for the ROM with the given hash and checksum (see [rom_checksum].)
+///
+/// A movie recorded with a different ROM (according to its checksum) is still imported, but will most likely desync.
+pub fn import(text: &str, rom_hash: u64, rom_checksum: [u8; 16]) -> Result<Movie, Error> {
+    let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+
+    let mut movie = Movie::new(rom_hash, Region::NTSC, MovieStart::PowerOn);
+    let mut ports = [true, true];
+    let mut has_version = false;
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if line.starts_with('|') {
+            movie.frames.push(parse_frame(line, ports).map_err(|e| invalid(format!("line {}: {e}", i + 1)))?);
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match key {
+            "version" => {
+                if value != "3" {
+                    return Err(invalid(format!("unsupported FM2 version {value}")));
+                }
+                has_version = true;
+            }
+            "binary" if value == "1" => return Err(invalid("binary FM2 movies are not supported".to_owned())),
+            "palFlag" => movie.region = if value == "1" { Region::PAL } else { Region::NTSC },
+            "romChecksum" => {
+                let expected = format!("base64:{}", base64_encode(&rom_checksum));
+                if value != expected {
+                    warn!("The movie was recorded with a different ROM (checksum {value}, expected {expected})");
+                }
+            }
+            "savestate" if !value.is_empty() => {
+                return Err(invalid("FM2 movies starting from a save state are not supported".to_owned()));
+            }
+            "fourscore" if value == "1" => return Err(invalid("Four Score movies are not supported".to_owned())),
+            "port0" | "port1" => {
+                let port = (key == "port1") as usize;
+                ports[port] = match value {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(invalid(format!("unsupported input device {value} in {key}"))),
+                };
+            }
+            "FDS" if value == "1" => return Err(invalid("FDS movies are not supported".to_owned())),
+            // Everything else (comments, subtitles, emulator settings, ...) doesn't affect playback
+            _ => {}
+        }
+    }
+
+    if !has_version {
+        return Err(invalid("not an FM2 movie".to_owned()));
+    }
+    Ok(movie)
+}
+
+fn parse_frame(line: &str, ports: [bool; 2]) -> Result<MovieFrame, String> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 4 {
+        return Err(format!("invalid input record `{line}`"));
+    }
+
+    let commands: u32 = fields[1].parse().map_err(|_| format!("invalid commands `{}`", fields[1]))?;
+    let reset = if commands & MOVIECMD_POWER != 0 {
+        Some(MovieReset::Hard)
+    } else if commands & MOVIECMD_RESET != 0 {
+        Some(MovieReset::Soft)
+    } else {
+        None
+    };
+
+    let joy1 = if ports[0] { parse_controller(fields[2])? } else { Default::default() };
+    let joy2 = if ports[1] { parse_controller(fields[3])? } else { Default::default() };
+
+    Ok(MovieFrame { joy1, joy2, reset, checksum: None })
+}
+
+fn parse_controller(field: &str) -> Result<StandardControllerState, String> {
+    if field.chars().count() != BUTTONS.len() {
+        return Err(format!("invalid controller input `{field}`"));
+    }
+
+    // Any character other than `.` or a space means the button is held, in the order of [BUTTONS]
+    let held: Vec<bool> = field.chars().map(|c| c != '.' && c != ' ').collect();
+    Ok(StandardControllerState {
+        right: held[0],
+        left: held[1],
+        down: held[2],
+        up: held[3],
+        start: held[4],
+        select: held[5],
+        b: held[6],
+        a: held[7],
+    })
+}
+
+fn write_controller(out: &mut String, state: StandardControllerState) {
+    let held = [state.right, state.left, state.down, state.up, state.start, state.select, state.b, state.a];
+    for (button, held) in BUTTONS.iter().zip(held) {
+        out.push(if held { *button } else { '.' });
+    }
+}
+
+/// Export a movie as FM2. `rom_filename` is only informational.
+///
+/// Fails if the movie can't be represented as an FM2 movie (it starts from a save state, or is for Dendy.)
+pub fn export(movie: &Movie, rom_checksum: [u8; 16], rom_filename: &str) -> Result<String, Error> {
+    if movie.start != MovieStart::PowerOn {
+        return Err(Error::new(ErrorKind::Unsupported, "only movies starting on power-on can be exported to FM2"));
+    }
+    let pal_flag = match movie.region {
+        Region::NTSC => 0,
+        Region::PAL => 1,
+        Region::Dendy => return Err(Error::new(ErrorKind::Unsupported, "Dendy movies can't be exported to FM2")),
+    };
+
+    // FCEUX uses the GUID to match save states to movies, so it only has to be unique
+    let bytes = movie.to_bytes();
+    let hi = fnv1a_hash(&bytes);
+    let lo = fnv1a_hash(&hi.to_le_bytes());
+    let guid = format!(
+        "{:08X}-{:04X}-{:04X}-{:04X}-{:012X}",
+        hi >> 32, (hi >> 16) & 0xffff, hi & 0xffff, lo >> 48, lo & 0xffff_ffff_ffff
+    );
+
+    let mut out = String::new();
+    out.push_str("version 3\n");
+    out.push_str(&format!("emuVersion {EMU_VERSION}\n"));
+    out.push_str("rerecordCount 0\n");
+    out.push_str(&format!("palFlag {pal_flag}\n"));
+    out.push_str(&format!("romFilename {rom_filename}\n"));
+    out.push_str(&format!("romChecksum base64:{}\n", base64_encode(&rom_checksum)));
+    out.push_str(&format!("guid {guid}\n"));
+    out.push_str("fourscore 0\n");
+    out.push_str("microphone 0\n");
+    out.push_str("port0 1\n");
+    out.push_str("port1 1\n");
+    out.push_str("port2 0\n");
+    out.push_str("FDS 0\n");
+    out.push_str("NewPPU 0\n");
+
+    for frame in &movie.frames {
+        let commands = match frame.reset {
+            None => 0,
+            Some(MovieReset::Soft) => MOVIECMD_RESET,
+            Some(MovieReset::Hard) => MOVIECMD_POWER,
+        };
+        out.push_str(&format!("|{commands}|"));
+        write_controller(&mut out, frame.joy1);
+        out.push('|');
+        write_controller(&mut out, frame.joy2);
+        out.push_str("||\n");
+    }
+
+    Ok(out)
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// MD5, as used by FCEUX for ROM checksums.
+///
+/// See: https://www.rfc-editor.org/rfc/rfc1321
+fn md5(data: &[u8]) -> [u8; 16] {
+    #[rustfmt::skip]
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22,
+        5,  9, 14, 20, 5,  9, 14, 20, 5,  9, 14, 20, 5,  9, 14, 20,
+        4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23,
+        6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+    let constants: [u32; 64] = std::array::from_fn(|i| (((i + 1) as f64).sin().abs() * 4294967296.0) as u32);
+
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in msg.chunks(64) {
+        let words: [u32; 16] = std::array::from_fn(|i| u32::from_le_bytes(block[i * 4..i * 4 + 4].try_into().unwrap()));
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(constants[i]).wrapping_add(words[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i]));
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut digest = [0; 16];
+    for (i, word) in state.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn md5_test() {
+        assert_eq!(hex(&md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(&md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            hex(&md5(b"The quick brown fox jumps over the lazy dog")),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+        assert_eq!(hex(&md5(&[0x61; 1000])), "cabe45dcc9ae5b66ba86600cca6b8ba8");
+    }
+
+    #[test]
+    fn base64_test() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn fm2_import_export_test() {
+        let text = "version 3\n\
+                    emuVersion 22020\n\
+                    palFlag 0\n\
+                    romFilename test\n\
+                    port0 1\n\
+                    port1 0\n\
+                    port2 0\n\
+                    comment author someone\n\
+                    |2|........|||\n\
+                    |0|R..U...A|||\n\
+                    |1|.L..TSB |||\n";
+
+        let movie = import(text, 0x1234, [0; 16]).unwrap();
+        assert_eq!(movie.rom_hash, 0x1234);
+        assert_eq!(movie.region, Region::NTSC);
+        assert_eq!(movie.frames.len(), 3);
+        assert_eq!(movie.frames[0].reset, Some(MovieReset::Hard));
+        assert_eq!(
+            movie.frames[1].joy1,
+            StandardControllerState { right: true, up: true, a: true, ..Default::default() }
+        );
+        assert_eq!(movie.frames[2].reset, Some(MovieReset::Soft));
+        assert_eq!(
+            movie.frames[2].joy1,
+            StandardControllerState { left: true, start: true, select: true, b: true, ..Default::default() }
+        );
+
+        let exported = export(&movie, [0; 16], "test").unwrap();
+        assert!(exported.contains("|0|R..U...A|........||\n"));
+        assert_eq!(import(&exported, 0x1234, [0; 16]).unwrap(), movie);
+
+        assert!(import("|0|........|||\n", 0, [0; 16]).is_err());
+        assert!(import("version 3\nport0 2\n", 0, [0; 16]).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! Lockstep netplay between two emulators over TCP.
+//!
+//! The host (player 1) listens for the client (player 2), and sends it a save state so both start from the same
+//! state. From then on, every frame both sides send their controller input for the frame `input_delay` frames ahead,
+//! and only run a frame once they have the input of both players for it. Since the emulator is deterministic, both
+//! sides stay in sync as long as they run the same frames with the same input. To detect when they don't, both sides
+//! periodically send a hash of their state.
+//!
+//! Every message is sent as its length (u32), followed by the message type (u8) and its contents:
+//!
+//! | Type | Message  | Contents                                                    |
+//! |------|----------|-------------------------------------------------------------|
+//! | 0    | Sync     | Protocol version (u32), input delay (u32), save state (vec) |
+//! | 1    | Ready    |                                                             |
+//! | 2    | Reject   | Reason (UTF-8 vec)                                          |
+//! | 3    | Input    | Frame (u32), buttons (u8, see [StandardControllerState::to_bits]) |
+//! | 4    | Checksum | Frame (u32), state hash (u64)                               |
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use log::{info, warn};
+
+use super::FC;
+use super::input::StandardControllerState;
+use super::state::{StateReader, StateWriter, fnv1a_hash};
+
+/// The version of the netplay protocol, both sides have to use the same one.
+pub const NETPLAY_VERSION: u32 = 1;
+
+/// Default number of frames between pressing a button and it taking effect, to hide the network latency.
+pub const DEFAULT_INPUT_DELAY: u32 = 2;
+
+/// Number of frames between state hashes.
+const CHECKSUM_INTERVAL: u32 = 60;
+
+/// How long to wait for the other side before giving up.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest message accepted (the initial save state being the largest one.)
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+enum Message {
+    Sync { version: u32, input_delay: u32, state: Vec<u8> },
+    Ready,
+    Reject(String),
+    Input { frame: u32, buttons: u8 },
+    Checksum { frame: u32, hash: u64 },
+}
+
+impl Message {
+    fn write(&self, stream: &mut TcpStream) -> Result<(), Error> {
+        let mut w = StateWriter::new();
+        match self {
+            Message::Sync { version, input_delay, state } => {
+                w.write(&0u8);
+                w.write(version);
+                w.write(input_delay);
+                w.write_vec(state);
+            }
+            Message::Ready => w.write(&1u8),
+            Message::Reject(reason) => {
+                w.write(&2u8);
+                w.write_vec(reason.as_bytes());
+            }
+            Message::Input { frame, buttons } => {
+                w.write(&3u8);
+                w.write(frame);
+                w.write(buttons);
+            }
+            Message::Checksum { frame, hash } => {
+                w.write(&4u8);
+                w.write(frame);
+                w.write(hash);
+            }
+        }
+
+        let data = w.into_bytes();
+        stream.write_all(&(data.len() as u32).to_le_bytes())?;
+        stream.write_all(&data)
+    }
+
+    fn read(stream: &mut TcpStream) -> Result<Message, Error> {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, format!("netplay message too large ({len} bytes)")));
+        }
+        let mut data = vec![0; len];
+        stream.read_exact(&mut data)?;
+
+        let mut r = StateReader::new(&data);
+        let msg = match r.read::<u8>()? {
+            0 => Message::Sync {
+                version: r.read()?,
+                input_delay: r.read()?,
+                state: r.read_vec()?,
+            },
+            1 => Message::Ready,
+            2 => Message::Reject(String::from_utf8_lossy(&r.read_vec()?).into_owned()),
+            3 => Message::Input {
+                frame: r.read()?,
+                buttons: r.read()?,
+            },
+            4 => Message::Checksum {
+                frame: r.read()?,
+                hash: r.read()?,
+            },
+            n => return Err(Error::new(ErrorKind::InvalidData, format!("unknown netplay message type {n}"))),
+        };
+        Ok(msg)
+    }
+}
+
+/// A netplay session with another emulator, see the [module documentation](self).
+pub struct Netplay {
+    stream: TcpStream,
+    is_host: bool,
+    input_delay: u32,
+    /// The next frame to run.
+    frame: u32,
+    /// Input of the local player, for frames `frame..frame + input_delay`.
+    local_inputs: VecDeque<StandardControllerState>,
+    /// Input of the remote player, received ahead of time.
+    remote_inputs: HashMap<u32, StandardControllerState>,
+    local_checksums: HashMap<u32, u64>,
+    remote_checksums: HashMap<u32, u64>,
+    desync_frame: Option<u32>,
+}
+
+impl Netplay {
+    /// Wait for a client to connect to `listener`, and send it the current state of `fc`.
+    pub fn host(listener: &TcpListener, fc: &FC, input_delay: u32) -> Result<Netplay, Error> {
+        let (mut stream, addr) = listener.accept()?;
+        info!("Netplay: {addr} connected");
+        configure_stream(&stream)?;
+
+        let state = fc.save_state()?;
+        Message::Sync { version: NETPLAY_VERSION, input_delay, state }.write(&mut stream)?;
+        match Message::read(&mut stream)? {
+            Message::Ready => {}
+            Message::Reject(reason) => return Err(Error::other(format!("the client rejected the session: {reason}"))),
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unexpected netplay message during sync")),
+        }
+
+        info!("Netplay: hosting as player 1 (input delay: {input_delay} frames)");
+        Ok(Netplay::new(stream, true, input_delay))
+    }
+
+    /// Connect to a host, and load the state it sends into `fc`.
+    pub fn join<A: ToSocketAddrs>(addr: A, fc: &mut FC) -> Result<Netplay, Error> {
+        let mut stream = TcpStream::connect(addr)?;
+        configure_stream(&stream)?;
+
+        let Message::Sync { version, input_delay, state } = Message::read(&mut stream)? else {
+            return Err(Error::new(ErrorKind::InvalidData, "expected a netplay sync message"));
+        };
+        let result = if version != NETPLAY_VERSION {
+            Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported netplay version {version} (expected {NETPLAY_VERSION})"),
+            ))
+        } else {
+            fc.load_state(&state)
+        };
+        if let Err(e) = result {
+            // Let the host know why, rather than just disconnecting
+            let _ = Message::Reject(e.to_string()).write(&mut stream);
+            return Err(e);
+        }
+        Message::Ready.write(&mut stream)?;
+
+        info!("Netplay: joined as player 2 (input delay: {input_delay} frames)");
+        Ok(Netplay::new(stream, false, input_delay))
+    }
+
+    fn new(stream: TcpStream, is_host: bool, input_delay: u32) -> Netplay {
+        Netplay {
+            stream,
+            is_host,
+            input_delay,
+            frame: 0,
+            // The first `input_delay` frames are run without any input
+            local_inputs: VecDeque::from(vec![StandardControllerState::default(); input_delay as usize]),
+            remote_inputs: HashMap::new(),
+            local_checksums: HashMap::new(),
+            remote_checksums: HashMap::new(),
+            desync_frame: None,
+        }
+    }
+
+    /// The player controlled by this side (1 for the host, 2 for the client.)
+    pub fn player(&self) -> u8 {
+        if self.is_host { 1 } else { 2 }
+    }
+
+    pub fn input_delay(&self) -> u32 {
+        self.input_delay
+    }
+
+    /// The number of frames run since the session started.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// The first frame after which the states of both sides were found to differ (if any.)
+    pub fn desync_frame(&self) -> Option<u32> {
+        self.desync_frame
+    }
+
+    /// Run a frame, with `local` as the input of the local player (taking effect after the input delay.)
+    ///
+    /// Blocks until the input of the other player for this frame has been received.
+    pub fn run_frame(&mut self, fc: &mut FC, local: StandardControllerState) -> Result<(), Error> {
+        let delayed_frame = self.frame + self.input_delay;
+        Message::Input { frame: delayed_frame, buttons: local.to_bits() }.write(&mut self.stream)?;
+        self.local_inputs.push_back(local);
+
+        let local = self.local_inputs.pop_front().unwrap_or_default();
+        let remote = if self.frame < self.input_delay {
+            StandardControllerState::default()
+        } else {
+            self.receive_input(self.frame)?
+        };
+
+        if self.is_host {
+            fc.set_controller_values(local, remote);
+        } else {
+            fc.set_controller_values(remote, local);
+        }
+        fc.run_until_render_done();
+        self.frame += 1;
+
+        if self.frame.is_multiple_of(CHECKSUM_INTERVAL) {
+            let hash = fnv1a_hash(&fc.save_state()?);
+            Message::Checksum { frame: self.frame, hash }.write(&mut self.stream)?;
+            self.local_checksums.insert(self.frame, hash);
+            self.compare_checksums(self.frame);
+        }
+        Ok(())
+    }
+
+    /// Receive messages until the remote input for `frame` has arrived.
+    fn receive_input(&mut self, frame: u32) -> Result<StandardControllerState, Error> {
+        loop {
+            if let Some(input) = self.remote_inputs.remove(&frame) {
+                return Ok(input);
+            }
+
+            match Message::read(&mut self.stream)? {
+                Message::Input { frame, buttons } => {
+                    self.remote_inputs.insert(frame, StandardControllerState::from_bits(buttons));
+                }
+                Message::Checksum { frame, hash } => {
+                    self.remote_checksums.insert(frame, hash);
+                    self.compare_checksums(frame);
+                }
+                _ => return Err(Error::new(ErrorKind::InvalidData, "unexpected netplay message during play")),
+            }
+        }
+    }
+
+    /// Compare the hashes of both sides for `frame`, once both are known.
+    fn compare_checksums(&mut self, frame: u32) {
+        let (Some(&local), Some(&remote)) = (self.local_checksums.get(&frame), self.remote_checksums.get(&frame))
+        else {
+            return;
+        };
+        self.local_checksums.remove(&frame);
+        self.remote_checksums.remove(&frame);
+
+        if local != remote && self.desync_frame.is_none() {
+            warn!("Netplay: desynced at frame {frame}");
+            self.desync_frame = Some(frame);
+        }
+    }
+}
+
+fn configure_stream(stream: &TcpStream) -> Result<(), Error> {
+    // Every message is tiny and needed right away
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::thread;
+
+    use super::*;
+    use crate::fc::test::{INPUT_PROGRAM, INPUT_PROGRAM_NMI, test_rom};
+
+    fn input(frame: u32, player: u8) -> StandardControllerState {
+        StandardControllerState::from_bits((frame / 10) as u8 * player)
+    }
+
+    /// Run a host and a client over loopback for `frames` frames, with the client soft resetting at `reset_frame`.
+    /// Returns the final states and desync frames of both sides.
+    fn run_session(name: &str, frames: u32, reset_frame: Option<u32>) -> [(Vec<u8>, Option<u32>); 2] {
+        let path = test_rom(name, &INPUT_PROGRAM, INPUT_PROGRAM_NMI);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let host_path = path.clone();
+        let host = thread::spawn(move || {
+            let mut fc = FC::from_file(&host_path).unwrap();
+            fc.init();
+            // The client starts from whatever state the host is in
+            for _ in 0..5 {
+                fc.run_until_render_done();
+            }
+
+            let mut netplay = Netplay::host(&listener, &fc, 3).unwrap();
+            assert_eq!(netplay.player(), 1);
+            for frame in 0..frames {
+                netplay.run_frame(&mut fc, input(frame, 1)).unwrap();
+            }
+            (fc.save_state().unwrap(), netplay.desync_frame())
+        });
+
+        let mut fc = FC::from_file(&path).unwrap();
+        fc.init();
+        let mut netplay = Netplay::join(addr, &mut fc).unwrap();
+        assert_eq!((netplay.player(), netplay.input_delay()), (2, 3));
+        for frame in 0..frames {
+            if Some(frame) == reset_frame {
+                fc.reset();
+            }
+            netplay.run_frame(&mut fc, input(frame, 2)).unwrap();
+        }
+        let client = (fc.save_state().unwrap(), netplay.desync_frame());
+
+        let host = host.join().unwrap();
+        fs::remove_file(path).unwrap();
+        [host, client]
+    }
+
+    #[test]
+    fn netplay_loopback_test() {
+        let [host, client] = run_session("netplay", 125, None);
+        assert_eq!(host.0, client.0);
+        assert_eq!((host.1, client.1), (None, None));
+    }
+
+    #[test]
+    fn netplay_desync_test() {
+        let [host, client] = run_session("netplay_desync", 125, Some(30));
+        assert_ne!(host.0, client.0);
+        assert_eq!((host.1, client.1), (Some(60), Some(60)));
+    }
+}
@@ -1,6 +1,8 @@
 mod sprite;
 mod tile;
 
+use std::io::Error;
+
 use log::debug;
 use rgb::*;
 use sprite::Sprite;
@@ -8,7 +10,7 @@ use tile::Tile;
 
 use crate::{bits::Bitwise, fc::mem::mapper::Mapper};
 
-use super::{CPU_FREQ, mem::MemMap};
+use super::{mem::MemMap, region::Region, state::{Snapshot, StateReader, StateWriter}};
 
 pub const VRAM_SIZE: usize = NAMETABLE_SIZE * 2;
 
@@ -30,13 +32,8 @@ const SPRITE_HEIGHT_SMALL: u8 = 8;
 const SPRITE_HEIGHT_LARGE: u8 = 16;
 
 const RENDERING_LINES: u32 = 240;
-const PRE_RENDER_LINE: u32 = 261;
 
 pub const SCANLINE_DURATION: u32 = 341;
-pub const FRAME_SCANLINES: u32 = 262;
-pub const PPU_FREQ: f64 = CPU_FREQ * 3.0;
-
-pub const FRAMERATE: f64 = PPU_FREQ / (341.0 * 261.0 + 340.5);
 
 const ADDRESS_PPUCTRL:   u16 = 0x2000;
 const ADDRESS_PPUMASK:   u16 = 0x2001;
@@ -214,6 +211,8 @@ pub struct PPU {
     // For IRQ
     cpu_cycles: usize,
     cpu_cycles_prev: usize,
+    // Timing
+    region: Region,
 
     // ??vvv
     frame_buf: Vec<u8>,
@@ -222,6 +221,8 @@ pub struct PPU {
 
 struct OAMSystem {
     sprites: [Sprite; 8],
+    /// The pattern bytes (low, high) of the row of each sprite in `sprites` to be drawn, read when the sprite is fetched.
+    sprite_patterns: [[u8; 2]; 8],
     tmp_idxes: [u8; 8],
     oam_secondary: [u8; OAM_SIZE / 2],
     oam_tmp: u8,
@@ -246,6 +247,7 @@ impl OAMSystem {
                 attrs: 0,
                 x: 0,
             }; 8],
+            sprite_patterns: [[0; 2]; 8],
             tmp_idxes: [0xff; 8],
             oam_secondary: [0; OAM_SIZE / 2],
             oam_ptr: 0,
@@ -263,7 +265,7 @@ impl OAMSystem {
 }
 
 impl PPU {
-    pub fn new() -> PPU {
+    pub fn new(region: Region) -> PPU {
         PPU {
             reg: Registers::new(),
             // chr: Box::new([0; PATTERN_TABLE_SIZE * 2].to_vec()),
@@ -290,6 +292,8 @@ impl PPU {
 
             cpu_cycles: 0,
             cpu_cycles_prev: 0,
+
+            region,
         }
     }
 
@@ -363,31 +367,32 @@ impl PPU {
         self.reg.oam_dma
     }
 
+    /// Run a single PPU cycle (dot). How many of these happen per CPU cycle is decided by the [MasterClock].
+    ///
+    /// [MasterClock]: crate::fc::clock::MasterClock
     pub fn cycle(&mut self, mem: &mut MemMap, cpu_cycles: usize) -> () {
-        // TODO: should this call some other fn 3 times instead?
-        // TODO: also, should this really just be a for loop...?
         self.cpu_cycles = cpu_cycles;
 
-        for _ in 0..3 {
-            assert!(self.cycle < SCANLINE_DURATION);
-            assert!(self.scanline < FRAME_SCANLINES);
+        assert!(self.cycle < SCANLINE_DURATION);
+        assert!(self.scanline < self.region.frame_scanlines());
 
-            if self.scanline <= RENDERING_LINES || self.scanline == PRE_RENDER_LINE && self.rendering_enabled() {
-                self.sprite_eval();
-            }
-            self.render(mem);
-
-            self.cycle += 1;
-            if self.cycle >= SCANLINE_DURATION {
-                // TODO? do something more..?
-                self.cycle = 0;
-                self.scanline += 1;
-
-                if self.scanline >= FRAME_SCANLINES {
-                    // TODO? do something..?
-                    self.scanline = 0;
-                    self.frame += 1;
-                }
+        if self.scanline <= RENDERING_LINES
+            || self.scanline == self.region.pre_render_scanline() && self.rendering_enabled()
+        {
+            self.sprite_eval();
+        }
+        self.render(mem);
+
+        self.cycle += 1;
+        if self.cycle >= SCANLINE_DURATION {
+            // TODO? do something more..?
+            self.cycle = 0;
+            self.scanline += 1;
+
+            if self.scanline >= self.region.frame_scanlines() {
+                // TODO? do something..?
+                self.scanline = 0;
+                self.frame += 1;
             }
         }
     }
@@ -422,6 +427,7 @@ impl PPU {
 
     pub fn read_addr(&mut self, addr: u16, mem: &mut MemMap) -> u8 {
         self.update_addr_bus(addr, mem);
+        mem.mapper.ppu_read(addr);
 
         self.read_addr_no_sideeffect(addr, mem)
     }
@@ -773,18 +779,14 @@ impl PPU {
     #[inline]
     fn render(&mut self, mem: &mut MemMap) {
         // TODO
+        let vblank_line = self.region.vblank_scanline();
+        let pre_render_line = self.region.pre_render_scanline();
+
         match self.scanline {
             0..=239 => {    // rendering (visible scanlines)
                 self.render_dot(mem);
             },
-            240 => {},      // idle (post-render scanline)
-            241..=260 => {  // vblank
-                if self.cycle == 1 && self.scanline == 241 {
-                    self.reg.status.vblank = true;
-                    debug!("set PPUSTATUS (${ADDRESS_PPUSTATUS:04x}) vblank flag (bit 7)")
-                }
-            },
-            261 => {     // dummy scanline (pre-render scanline)
+            l if l == pre_render_line => {  // dummy scanline (pre-render scanline)
                 if self.cycle == 1 {
                     self.reg.status.vblank = false;
                     debug!("cleared PPUSTATUS (${ADDRESS_PPUSTATUS:04x}) vblank flag (bit 7)");
@@ -812,10 +814,19 @@ impl PPU {
                         if self.cycle == 328 || self.cycle == 336 {
                             self.shl_shift_registers(8);
                         }
+                    } else if self.cycle >= 337 {
+                        self.dummy_nametable_fetch(mem);
                     }
                 }
             }
-            _ => unreachable!(),
+            l if l >= vblank_line => {  // vblank
+                if self.cycle == 1 && self.scanline == vblank_line {
+                    self.reg.status.vblank = true;
+                    debug!("set PPUSTATUS (${ADDRESS_PPUSTATUS:04x}) vblank flag (bit 7)");
+                    mem.mapper.vblank_started();
+                }
+            },
+            _ => {},        // idle (post-render scanline(s))
         }
     }
 
@@ -831,7 +842,7 @@ impl PPU {
                 let y = self.scanline as usize;
                 let idx = y * PICTURE_WIDTH + x;
 
-                let px = self.get_next_pixel(mem);
+                let px = self.get_next_pixel();
 
                 self.shl_shift_registers(1);
 
@@ -854,16 +865,19 @@ impl PPU {
                             // "Garbage nametable byte"
                             self.read_addr(self.reg.control.nametable_addr, mem);
                         } // 259
-                        5 => {
-                            // "Pattern table tile low"
+                        5 | 7 => {
+                            // "Pattern table tile low" (5) and "Pattern table tile high" (7)
                             // TODO: this is basically just to get MMC3 IRQ working. Look into implementing it properly
                             self.read_addr(0x1000, mem);
-                        } // 261
-                        7 => {
-                            // "Pattern table tile high"
-                            // TODO: same again
-                            self.read_addr(0x1000, mem);
-                        } // 263
+
+                            // Read with the banks at the time of the fetch, which can be switched before the sprite
+                            // is drawn (e.g. by the MMC2's latches)
+                            let i = (self.cycle as usize - 257) / 8;
+                            let plane = if self.cycle & 0x7 == 5 { 0 } else { 1 };
+                            let addr = self.sprite_pattern_addr(i) + plane as u16 * 8;
+                            self.oam_sys.sprite_patterns[i][plane] = self.read_addr_no_sideeffect(addr, mem);
+                            mem.mapper.pattern_fetched(addr);
+                        } // 261, 263
                         _ => {} // other
                     }
 
@@ -885,9 +899,13 @@ impl PPU {
             }
             337..=340 => {
                 if self.rendering_enabled() {
-                    // TODO: two ppu fetches
+                    self.dummy_nametable_fetch(mem);
 
-                    if self.cycle == 339 && self.scanline == PRE_RENDER_LINE && self.frame % 2 == 1 {
+                    if self.cycle == 339
+                        && self.scanline == self.region.pre_render_scanline()
+                        && self.frame % 2 == 1
+                        && self.region.skips_odd_frame_dot()
+                    {
                         self.cycle = 340;
                     }
                 }
@@ -897,6 +915,31 @@ impl PPU {
         // }
     }
 
+    /// The two unused nametable fetches at the end of each scanline (ticks 337 and 339). Together with the first fetch
+    /// of the next scanline these are three reads of the same address, which the MMC5 uses to detect scanlines.
+    fn dummy_nametable_fetch(&mut self, mem: &mut MemMap) {
+        if self.cycle == 337 || self.cycle == 339 {
+            self.read_addr(0x2000 | (self.reg.v & 0x0fff), mem);
+        }
+    }
+
+    /// The address of the (low) pattern table byte of sprite `i` fetched for the next scanline.
+    fn sprite_pattern_addr(&self, i: usize) -> u16 {
+        let spr = self.oam_sys.sprites[i];
+        let height = self.reg.control.sprites_large as u16;
+        // Unused sprite slots (all $ff) fetch garbage, but still fetch tile $ff
+        let row = (self.scanline as u16).wrapping_sub(spr.y as u16) % height;
+        let row = if spr.flipped_vertical() { height - 1 - row } else { row };
+
+        if height == SPRITE_HEIGHT_LARGE as u16 {
+            let table = if spr.tile & 1 == 0 { 0x0000 } else { 0x1000 };
+            let tile = (spr.tile & 0xfe) as u16 + row / 8;
+            table + tile * TILE_SIZE + (row & 7)
+        } else {
+            self.reg.control.spr_pattern_addr + spr.tile as u16 * TILE_SIZE + row
+        }
+    }
+
     fn shl_shift_registers(&mut self, amount: u16) {
         // Update the shift register(s)
         self.shift_reg_lo <<= amount;
@@ -944,9 +987,11 @@ impl PPU {
                 let addr: u16 = tile_addr | y_scroll | bg_addr;
 
                 if c == 5 {
-                    self.curr_pattern_lo = self.read_addr(addr, mem)
+                    self.curr_pattern_lo = self.read_addr(addr, mem);
+                    mem.mapper.pattern_fetched(addr);
                 } else {
-                    self.curr_pattern_hi = self.read_addr(addr + 8, mem)
+                    self.curr_pattern_hi = self.read_addr(addr + 8, mem);
+                    mem.mapper.pattern_fetched(addr + 8);
                 }
             }
             0 => {
@@ -961,7 +1006,7 @@ impl PPU {
     }
 
     #[inline]
-    fn get_next_pixel(&mut self, mem: &mut MemMap) -> RGB<u8> {
+    fn get_next_pixel(&mut self) -> RGB<u8> {
         let scroll_x_fine = self.reg.scroll_x;
         let y = self.scanline;
         let x = self.cycle - 1;
@@ -993,44 +1038,8 @@ impl PPU {
                 && self.reg.mask.sprites_enable
             {
                 // Hit sprite
-                let rel_y = (y - 1 - spr.y as u32) as u16;
                 let rel_x = (x - spr.x as u32) as usize;
-
-                let large_sprites = spr_height > SPRITE_HEIGHT_SMALL as u32;
-                let is_second_sprite = y > spr.y as u32 + SPRITE_HEIGHT_SMALL as u32;
-
-                let pattern_table_start = if large_sprites {
-                    if spr.tile & 1 == 0 { 0x0000 } else { 0x1000 }
-                } else {
-                    self.reg.control.spr_pattern_addr
-                };
-
-                let tile_idx = if large_sprites {
-                    if !is_second_sprite {
-                        spr.tile & 0xfe
-                    } else {
-                        (spr.tile & 0xfe) | 1
-                    }
-                } else {
-                    spr.tile
-                };
-
-                let rel_y = if spr.flipped_vertical() {
-                    if large_sprites && !is_second_sprite {
-                        7 - rel_y + 16
-                    } else {
-                        7 - rel_y
-                    }
-                } else {
-                    rel_y
-                };
-
-                let second_sprite_delta = if is_second_sprite { 8 } else { 0 };
-
-                let addr_lo = pattern_table_start + ((tile_idx as u16 * TILE_SIZE) + rel_y - second_sprite_delta);
-                let addr_hi = pattern_table_start + ((tile_idx as u16 * TILE_SIZE) + rel_y - second_sprite_delta + 8);
-                let tile_line_lo = self.read_addr_no_sideeffect(addr_lo, mem);
-                let tile_line_hi = self.read_addr_no_sideeffect(addr_hi, mem);
+                let [tile_line_lo, tile_line_hi] = self.oam_sys.sprite_patterns[i];
 
                 let rel_x = if spr.flipped_horizontal() { rel_x } else { 7 - rel_x };
                 let b0 = tile_line_lo.test_bit(rel_x) as u8;
@@ -1253,3 +1262,177 @@ fn as_rgb(color: u8) -> RGB8 {
     let b = PALETTE_COLORS[3 * color as usize + 2];
     RGB8 { r, g, b }
 }
+
+impl Snapshot for OAMSystem {
+    fn save_state(&self, w: &mut StateWriter) {
+        for sprite in &self.sprites {
+            w.write(&[sprite.idx, sprite.y, sprite.tile, sprite.attrs, sprite.x]);
+        }
+        for pattern in &self.sprite_patterns {
+            w.write(pattern);
+        }
+        w.write(&self.tmp_idxes);
+        w.write(&self.oam_secondary);
+        w.write(&self.oam_tmp);
+        w.write(&self.n);
+        w.write(&self.m);
+        w.write(&self.oam_ptr);
+        w.write(&self.copy_state);
+        w.write(&self.is_full);
+        w.write(&self.curr_in_bounds);
+        w.write(&self.curr_sprite);
+        w.write(&self.sprite_0_hit_curr_scanline);
+        w.write(&self.sprite_0_hit_next_scanline);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        for sprite in &mut self.sprites {
+            let [idx, y, tile, attrs, x] = r.read()?;
+            *sprite = Sprite { idx, y, tile, attrs, x };
+        }
+        for pattern in &mut self.sprite_patterns {
+            *pattern = r.read()?;
+        }
+        self.tmp_idxes = r.read()?;
+        self.oam_secondary = r.read()?;
+        self.oam_tmp = r.read()?;
+        self.n = r.read()?;
+        self.m = r.read()?;
+        self.oam_ptr = r.read()?;
+        self.copy_state = r.read()?;
+        self.is_full = r.read()?;
+        self.curr_in_bounds = r.read()?;
+        self.curr_sprite = r.read()?;
+        self.sprite_0_hit_curr_scanline = r.read()?;
+        self.sprite_0_hit_next_scanline = r.read()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for PPU {
+    /// Note: the frame buffer is not saved, it is fully redrawn during the next frame.
+    fn save_state(&self, w: &mut StateWriter) {
+        let reg = &self.reg;
+        w.write(&Into::<u8>::into(reg.control));
+        w.write(&Into::<u8>::into(reg.mask));
+        w.write(&reg.status.sprite_overflow);
+        w.write(&reg.status.sprite_0_hit);
+        w.write(&reg.status.vblank);
+        w.write(&reg.oam_addr);
+        w.write(&reg.oam_data);
+        w.write(&reg.x_y_scroll);
+        w.write(&reg.oam_dma);
+        w.write(&reg.io_bus);
+        w.write(&reg.v);
+        w.write(&reg.t);
+        w.write(&reg.scroll_x);
+        w.write(&reg.write_toggle);
+        w.write(&reg.addr_bus);
+        w.write(&reg.read_buf);
+
+        w.write(&self.pal);
+        w.write(&self.oam);
+        w.write(&self.vram);
+        w.write(&self.cycle);
+        w.write(&self.scanline);
+        w.write(&self.frame);
+
+        w.write(&self.curr_tile_idx);
+        w.write(&self.prev_attribute_byte);
+        w.write(&self.curr_attribute_byte);
+        w.write(&self.temp_attribute_byte);
+        w.write(&self.curr_pattern_lo);
+        w.write(&self.curr_pattern_hi);
+        w.write(&self.shift_reg_lo);
+        w.write(&self.shift_reg_hi);
+
+        self.oam_sys.save_state(w);
+
+        w.write(&self.cpu_cycles);
+        w.write(&self.cpu_cycles_prev);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        let reg = &mut self.reg;
+        reg.control = r.read::<u8>()?.into();
+        reg.mask = r.read::<u8>()?.into();
+        reg.status.sprite_overflow = r.read()?;
+        reg.status.sprite_0_hit = r.read()?;
+        reg.status.vblank = r.read()?;
+        reg.oam_addr = r.read()?;
+        reg.oam_data = r.read()?;
+        reg.x_y_scroll = r.read()?;
+        reg.oam_dma = r.read()?;
+        reg.io_bus = r.read()?;
+        reg.v = r.read()?;
+        reg.t = r.read()?;
+        reg.scroll_x = r.read()?;
+        reg.write_toggle = r.read()?;
+        reg.addr_bus = r.read()?;
+        reg.read_buf = r.read()?;
+
+        self.pal = r.read()?;
+        self.oam = r.read()?;
+        self.vram = r.read()?;
+        self.cycle = r.read()?;
+        self.scanline = r.read()?;
+        self.frame = r.read()?;
+
+        self.curr_tile_idx = r.read()?;
+        self.prev_attribute_byte = r.read()?;
+        self.curr_attribute_byte = r.read()?;
+        self.temp_attribute_byte = r.read()?;
+        self.curr_pattern_lo = r.read()?;
+        self.curr_pattern_hi = r.read()?;
+        self.shift_reg_lo = r.read()?;
+        self.shift_reg_hi = r.read()?;
+
+        self.oam_sys.load_state(r)?;
+
+        self.cpu_cycles = r.read()?;
+        self.cpu_cycles_prev = r.read()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fc::mem::{Memory, mapper::test::test_nesfile};
+
+    #[test]
+    fn sprite_fetch_chr_latch_test() {
+        // MMC2 with 4 8KiB CHR banks filled with their number, so 4KiB bank 0 is transparent and 2 is color 3
+        let mut mem = MemMap::from_nesfile(&test_nesfile(9, 0, 8, 4)).unwrap();
+        mem.mapper.write(0xd000, 0); // $1000, latch $fd
+        mem.mapper.write(0xe000, 2); // $1000, latch $fe
+        let mut ppu = PPU::new(Region::NTSC);
+
+        // Backdrop and sprite color 3, then 8x8 sprites from $1000
+        for (addr, val) in [(0x3f00, 0x0f), (0x3f13, 0x16)] {
+            ppu.write_mmio(ADDRESS_PPUADDR, (addr >> 8) as u8, &mut mem);
+            ppu.write_mmio(ADDRESS_PPUADDR, addr as u8, &mut mem);
+            ppu.write_mmio(ADDRESS_PPUDATA, val, &mut mem);
+        }
+        ppu.write_mmio(ADDRESS_PPUADDR, 0x00, &mut mem);
+        ppu.write_mmio(ADDRESS_PPUADDR, 0x00, &mut mem);
+        ppu.write_mmio(ADDRESS_PPUCTRL, 0x08, &mut mem);
+        ppu.write_mmio(ADDRESS_PPUMASK, 0x1e, &mut mem);
+
+        // Fetching tile $fd switches to the $fd bank right after its own fetch, and tile $fe switches back, so on every
+        // line the first sprite is fetched from the $fe bank and the second from the $fd one
+        for dst in 0..=255 {
+            ppu.write_oam(dst, 0xff);
+        }
+        for (dst, val) in [9, 0xfd, 0, 16, 9, 0xfe, 0, 64].into_iter().enumerate() {
+            ppu.write_oam(dst as u8, val);
+        }
+
+        for dot in 0..2 * SCANLINE_DURATION as usize * ppu.region.frame_scanlines() as usize {
+            ppu.cycle(&mut mem, dot / 3);
+        }
+        let pixel = |x: usize, y: usize| ppu.get_frame_buf().as_rgb()[y * PICTURE_WIDTH + x];
+        assert_eq!(pixel(16 + 7, 12), as_rgb(0x16));
+        assert_eq!(pixel(64 + 7, 12), as_rgb(0x0f));
+    }
+}
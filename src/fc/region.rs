@@ -0,0 +1,122 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// The console region, which determines the timings of the CPU, PPU and APU.
+///
+/// See: https://www.nesdev.org/wiki/Cycle_reference_chart
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// RP2A03 & RP2C02 (North America, Japan, ...)
+    NTSC,
+    /// RP2A07 & RP2C07 (Europe, Australia, ...)
+    PAL,
+    /// UA6527P & UA6538 (Famiclones, mostly in Russia)
+    Dendy,
+}
+
+impl Region {
+    /// Get the region from the CPU/PPU timing field of a NES 2.0 header.
+    ///
+    /// Multi-region games are run as NTSC.
+    pub fn from_timing_mode(timing_mode: u8) -> Region {
+        match timing_mode {
+            1 => Region::PAL,
+            3 => Region::Dendy,
+            _ => Region::NTSC,
+        }
+    }
+
+    /// Master clock frequency, in Hz.
+    pub fn master_freq(self) -> f64 {
+        match self {
+            Region::NTSC => 21_477_272.727272727,
+            Region::PAL | Region::Dendy => 26_601_712.5,
+        }
+    }
+
+    /// Number of master clock cycles per CPU cycle.
+    pub fn cpu_divider(self) -> u32 {
+        match self {
+            Region::NTSC => 12,
+            Region::PAL => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    /// Number of master clock cycles per PPU cycle (dot).
+    pub fn ppu_divider(self) -> u32 {
+        match self {
+            Region::NTSC => 4,
+            Region::PAL | Region::Dendy => 5,
+        }
+    }
+
+    pub fn cpu_freq(self) -> f64 {
+        self.master_freq() / self.cpu_divider() as f64
+    }
+
+    pub fn ppu_freq(self) -> f64 {
+        self.master_freq() / self.ppu_divider() as f64
+    }
+
+    /// Number of scanlines per frame (including the pre-render scanline.)
+    pub fn frame_scanlines(self) -> u32 {
+        match self {
+            Region::NTSC => 262,
+            Region::PAL | Region::Dendy => 312,
+        }
+    }
+
+    /// The scanline where vblank starts (and the vblank flag is set.)
+    pub fn vblank_scanline(self) -> u32 {
+        match self {
+            Region::NTSC | Region::PAL => 241,
+            // "the Dendy's PPU [...] has 51 post-render lines instead of 1"
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn pre_render_scanline(self) -> u32 {
+        self.frame_scanlines() - 1
+    }
+
+    /// Whether the PPU skips a dot on the pre-render scanline of odd frames (when rendering is enabled.)
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::NTSC
+    }
+
+    /// Number of frames per second.
+    pub fn framerate(self) -> f64 {
+        let dots = (341 * self.frame_scanlines()) as f64;
+        if self.skips_odd_frame_dot() {
+            // Every other frame is one dot shorter
+            self.ppu_freq() / (dots - 0.5)
+        } else {
+            self.ppu_freq() / dots
+        }
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Region::NTSC => "NTSC",
+            Region::PAL => "PAL",
+            Region::Dendy => "Dendy",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ntsc" => Ok(Region::NTSC),
+            "pal" => Ok(Region::PAL),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region: `{s}` (expected ntsc, pal or dendy)")),
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Save states: snapshots of the complete machine state.
+//!
+//! A save state consists of a header followed by the state of every component, in the order they are saved by
+//! [FC::save_state](crate::fc::FC::save_state). All values are stored little endian.
+//!
+//! | Offset | Size | Contents                                       |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 8    | Magic (`RFCSTATE`)                             |
+//! | 8      | 4    | Format version ([STATE_VERSION])               |
+//! | 12     | 8    | ROM hash (see [NESFile::hash])                 |
+//! | 20     | 1    | Region (0: NTSC, 1: PAL, 2: Dendy)             |
+//! | 21     | ..   | Component states                               |
+//!
+//! Only the state of the emulated machine is saved. Output settings (e.g. the audio sample rate and mixing) and
+//! buffers derived from the machine state (e.g. the frame buffer) are not part of a save state.
+//!
+//! [NESFile::hash]: crate::fc::mem::cart::NESFile::hash
+
+use std::io::{Error, ErrorKind};
+
+use super::region::Region;
+
+const STATE_MAGIC: [u8; 8] = *b"RFCSTATE";
+
+/// The version of the save state format. Bump this whenever the layout of any component's state changes.
+pub const STATE_VERSION: u32 = 1;
+
+/// Size of the header, in bytes.
+pub const HEADER_SIZE: usize = 21;
+
+/// The header of a save state, identifying the ROM (and region) it was saved from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StateHeader {
+    pub version: u32,
+    pub rom_hash: u64,
+    pub region: Region,
+}
+
+impl StateHeader {
+    pub fn write(&self, w: &mut StateWriter) {
+        w.write_bytes(&STATE_MAGIC);
+        w.write(&self.version);
+        w.write(&self.rom_hash);
+        w.write(&self.region);
+    }
+
+    /// Read and validate a header. Fails if `data` is not a save state, or uses an unsupported version.
+    pub fn read(r: &mut StateReader) -> Result<StateHeader, Error> {
+        let mut magic = [0; 8];
+        r.read_bytes(&mut magic)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "not a save state"))?;
+        if magic != STATE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a save state"));
+        }
+
+        let version: u32 = r.read()?;
+        if version != STATE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unsupported save state version {version} (expected {STATE_VERSION})"),
+            ));
+        }
+
+        let rom_hash = r.read()?;
+        let region = r.read()?;
+
+        Ok(StateHeader { version, rom_hash, region })
+    }
+}
+
+/// A component whose state can be saved to, and restored from, a save state.
+pub trait Snapshot {
+    fn save_state(&self, w: &mut StateWriter);
+
+    /// Restore the state written by `save_state`.
+    ///
+    /// If this fails the component may be left partially restored.
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error>;
+}
+
+/// A value that can be stored in a save state.
+pub trait StateValue: Sized {
+    fn write_to(&self, w: &mut StateWriter);
+    fn read_from(r: &mut StateReader) -> Result<Self, Error>;
+}
+
+macro_rules! impl_state_value_int {
+    ($($t: ty),*) => {
+        $(
+            impl StateValue for $t {
+                fn write_to(&self, w: &mut StateWriter) {
+                    w.write_bytes(&self.to_le_bytes());
+                }
+
+                fn read_from(r: &mut StateReader) -> Result<Self, Error> {
+                    let mut bytes = [0; size_of::<$t>()];
+                    r.read_bytes(&mut bytes)?;
+                    Ok(<$t>::from_le_bytes(bytes))
+                }
+            }
+        )*
+    };
+}
+
+impl_state_value_int!(u8, u16, u32, u64, i8, i16, i32, i64, f32);
+
+impl StateValue for usize {
+    fn write_to(&self, w: &mut StateWriter) {
+        (*self as u64).write_to(w);
+    }
+
+    fn read_from(r: &mut StateReader) -> Result<Self, Error> {
+        Ok(u64::read_from(r)? as usize)
+    }
+}
+
+impl StateValue for bool {
+    fn write_to(&self, w: &mut StateWriter) {
+        (*self as u8).write_to(w);
+    }
+
+    fn read_from(r: &mut StateReader) -> Result<Self, Error> {
+        Ok(u8::read_from(r)? != 0)
+    }
+}
+
+impl StateValue for Region {
+    fn write_to(&self, w: &mut StateWriter) {
+        (*self as u8).write_to(w);
+    }
+
+    fn read_from(r: &mut StateReader) -> Result<Self, Error> {
+        match u8::read_from(r)? {
+            0 => Ok(Region::NTSC),
+            1 => Ok(Region::PAL),
+            2 => Ok(Region::Dendy),
+            n => Err(Error::new(ErrorKind::InvalidData, format!("invalid region {n}"))),
+        }
+    }
+}
+
+impl<T: StateValue> StateValue for Option<T> {
+    fn write_to(&self, w: &mut StateWriter) {
+        self.is_some().write_to(w);
+        if let Some(val) = self {
+            val.write_to(w);
+        }
+    }
+
+    fn read_from(r: &mut StateReader) -> Result<Self, Error> {
+        Ok(if bool::read_from(r)? { Some(T::read_from(r)?) } else { None })
+    }
+}
+
+impl<const N: usize> StateValue for [u8; N] {
+    fn write_to(&self, w: &mut StateWriter) {
+        w.write_bytes(self);
+    }
+
+    fn read_from(r: &mut StateReader) -> Result<Self, Error> {
+        let mut bytes = [0; N];
+        r.read_bytes(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+/// Writes the state of components to a byte buffer.
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { buf: Vec::new() }
+    }
+
+    /// Create a writer that writes to `buf` (after clearing it), to reuse its allocation.
+    pub fn with_buffer(mut buf: Vec<u8>) -> StateWriter {
+        buf.clear();
+        StateWriter { buf }
+    }
+
+    pub fn write<T: StateValue>(&mut self, val: &T) {
+        val.write_to(self);
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Write a variable length buffer (e.g. RAM, whose size depends on the ROM.)
+    pub fn write_vec(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u32));
+        self.write_bytes(bytes);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Reads the state of components from a byte buffer.
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, pos: 0 }
+    }
+
+    pub fn read<T: StateValue>(&mut self) -> Result<T, Error> {
+        T::read_from(self)
+    }
+
+    /// Fill `buf` with the next `buf.len()` bytes.
+    pub fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let end = self.pos + buf.len();
+        if end > self.data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "save state is truncated"));
+        }
+        buf.copy_from_slice(&self.data[self.pos..end]);
+        self.pos = end;
+        Ok(())
+    }
+
+    /// Read a buffer written by [StateWriter::write_vec] into `buf`, which has to be of the same length.
+    pub fn read_vec_into(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        let len = self.read::<u32>()? as usize;
+        if len != buf.len() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("save state buffer size mismatch ({len} bytes, expected {})", buf.len()),
+            ));
+        }
+        self.read_bytes(buf)
+    }
+
+    /// Read a variable length buffer written by [StateWriter::write_vec].
+    pub fn read_vec(&mut self) -> Result<Vec<u8>, Error> {
+        let len = self.read::<u32>()? as usize;
+        if len > self.data.len() - self.pos {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "save state is truncated"));
+        }
+        let mut buf = vec![0; len];
+        self.read_bytes(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Whether all data has been read.
+    pub fn is_at_end(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+/// Initial value of [fnv1a_update].
+pub const FNV1A_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// 64-bit FNV-1a hash, used for identifying ROMs (and anything else that has to be stable between runs.)
+pub fn fnv1a_hash(data: &[u8]) -> u64 {
+    fnv1a_update(FNV1A_OFFSET_BASIS, data)
+}
+
+/// Continue an FNV-1a hash with more data, for hashing data that doesn't arrive all at once.
+pub fn fnv1a_update(hash: u64, data: &[u8]) -> u64 {
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    data.iter().fold(hash, |hash, &b| (hash ^ b as u64).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn value_round_trip_test() {
+        let mut w = StateWriter::new();
+        w.write(&0x12u8);
+        w.write(&0x3456u16);
+        w.write(&true);
+        w.write(&Some(0x78u8));
+        w.write(&None::<u8>);
+        w.write(&-2i8);
+        w.write(&0.5f32);
+        w.write_vec(&[1, 2, 3]);
+        let bytes = w.into_bytes();
+
+        let mut r = StateReader::new(&bytes);
+        assert_eq!(r.read::<u8>().unwrap(), 0x12);
+        assert_eq!(r.read::<u16>().unwrap(), 0x3456);
+        assert_eq!(r.read::<bool>().unwrap(), true);
+        assert_eq!(r.read::<Option<u8>>().unwrap(), Some(0x78));
+        assert_eq!(r.read::<Option<u8>>().unwrap(), None);
+        assert_eq!(r.read::<i8>().unwrap(), -2);
+        assert_eq!(r.read::<f32>().unwrap(), 0.5);
+        let mut buf = [0; 3];
+        r.read_vec_into(&mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3]);
+        assert!(r.is_at_end());
+
+        // Reading past the end fails instead of panicking
+        assert!(r.read::<u8>().is_err());
+    }
+
+    #[test]
+    fn header_test() {
+        let header = StateHeader { version: STATE_VERSION, rom_hash: 0x0123_4567_89ab_cdef, region: Region::PAL };
+        let mut w = StateWriter::new();
+        header.write(&mut w);
+        let bytes = w.into_bytes();
+        assert_eq!(bytes.len(), HEADER_SIZE);
+
+        assert_eq!(StateHeader::read(&mut StateReader::new(&bytes)).unwrap(), header);
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[8] = 0xff;
+        assert!(StateHeader::read(&mut StateReader::new(&wrong_version)).is_err());
+        assert!(StateHeader::read(&mut StateReader::new(b"NES\x1a")).is_err());
+    }
+
+    #[test]
+    fn fnv1a_test() {
+        assert_eq!(fnv1a_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a_update(fnv1a_hash(b"ab"), b"cd"), fnv1a_hash(b"abcd"));
+    }
+}
@@ -0,0 +1,164 @@
+use std::fs::File;
+use std::io::{BufWriter, Error, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+
+const HEADER_SIZE: u32 = 44;
+const CHANNELS: u16 = 1;
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Convert a sample (in the range -1.0 to 1.0) to 16-bit PCM.
+pub(crate) fn quantize_sample(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+/// Writes (mono, 16-bit PCM) audio samples to a .wav file.
+pub struct WavRecorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+    sample_rate: u32,
+    samples_written: u32,
+    finished: bool,
+}
+
+impl WavRecorder {
+    /// Create the file at `path` and start recording.
+    pub fn create(path: &Path, sample_rate: u32) -> Result<WavRecorder, Error> {
+        let file = File::create(path)?;
+        let mut recorder = WavRecorder {
+            writer: BufWriter::new(file),
+            path: path.to_path_buf(),
+            sample_rate,
+            samples_written: 0,
+            finished: false,
+        };
+
+        recorder.write_header()?;
+        info!("Started recording audio to {}", path.display());
+
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Append samples (in the range -1.0 to 1.0) to the file.
+    pub fn write_samples(&mut self, samples: &[f32]) -> Result<(), Error> {
+        // The data chunk size is a u32, so the file can't grow past 4 GiB
+        let max_samples = (u32::MAX - HEADER_SIZE) / (BITS_PER_SAMPLE as u32 / 8);
+        let count = samples.len().min((max_samples - self.samples_written) as usize);
+
+        for &s in &samples[..count] {
+            let val = quantize_sample(s);
+            self.writer.write_all(&val.to_le_bytes())?;
+        }
+
+        // Keep the header up to date about once a second, so the file stays (mostly) intact if the emulator is
+        // killed before the recording is finished.
+        let prev_seconds = self.samples_written / self.sample_rate;
+        self.samples_written += count as u32;
+        if self.samples_written / self.sample_rate != prev_seconds {
+            self.update_header_sizes()?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop recording, writing the final sizes to the header.
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.finalize()
+    }
+
+    fn finalize(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        self.update_header_sizes()?;
+
+        info!(
+            "Finished recording audio to {} ({:.2}s)",
+            self.path.display(),
+            self.samples_written as f64 / self.sample_rate as f64
+        );
+        Ok(())
+    }
+
+    /// Write the current RIFF and data chunk sizes to the header.
+    fn update_header_sizes(&mut self) -> Result<(), Error> {
+        let data_size = self.samples_written * (CHANNELS * BITS_PER_SAMPLE / 8) as u32;
+
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()
+    }
+
+    fn write_header(&mut self) -> Result<(), Error> {
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+        let byte_rate = self.sample_rate * block_align as u32;
+        let data_size = self.samples_written * block_align as u32;
+
+        let w = &mut self.writer;
+        w.write_all(b"RIFF")?;
+        w.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        w.write_all(b"WAVE")?;
+
+        w.write_all(b"fmt ")?;
+        w.write_all(&16u32.to_le_bytes())?;
+        w.write_all(&1u16.to_le_bytes())?; // PCM
+        w.write_all(&CHANNELS.to_le_bytes())?;
+        w.write_all(&self.sample_rate.to_le_bytes())?;
+        w.write_all(&byte_rate.to_le_bytes())?;
+        w.write_all(&block_align.to_le_bytes())?;
+        w.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+        w.write_all(b"data")?;
+        w.write_all(&data_size.to_le_bytes())?;
+
+        Ok(())
+    }
+}
+
+impl Drop for WavRecorder {
+    fn drop(&mut self) {
+        // Make sure the file is still valid if the recording was never stopped explicitly
+        if let Err(e) = self.finalize() {
+            warn!("Failed to finish audio recording {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_test() {
+        let path = std::env::temp_dir().join(format!("rfce_wav_test_{}.wav", std::process::id()));
+
+        let mut recorder = WavRecorder::create(&path, 100).unwrap();
+        recorder.write_samples(&[0.0; 150]).unwrap();
+        recorder.write_samples(&[1.0, -1.0]).unwrap();
+        recorder.finish().unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(data.len(), 44 + 152 * 2);
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(data[4..8].try_into().unwrap()), 36 + 152 * 2);
+        assert_eq!(u32::from_le_bytes(data[24..28].try_into().unwrap()), 100);
+        assert_eq!(u32::from_le_bytes(data[40..44].try_into().unwrap()), 152 * 2);
+        assert_eq!(i16::from_le_bytes(data[44 + 300..44 + 302].try_into().unwrap()), i16::MAX);
+    }
+}
@@ -1,4 +1,13 @@
-use std::path::{Path, PathBuf};
+mod audio;
+pub mod rewind;
+mod slots;
+
+use std::{
+    io::Error,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
 use log::{debug, info, warn};
 use sdl3::{
@@ -11,27 +20,50 @@ use sdl3::{
     video::Window,
 };
 
-use crate::fc::{FC, input::StandardControllerState, ppu};
+use crate::fc::{FC, apu::Channel, input::StandardControllerState, movie::MovieMode, netplay::Netplay, ppu, region::Region};
+use audio::AudioOutput;
+use rewind::{DEFAULT_REWIND_INTERVAL, DEFAULT_REWIND_LENGTH, RewindBuffer};
+use slots::{SLOT_COUNT, StateSlots, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH};
+
+/// Maximum number of frames to run ahead when cycling through the run-ahead settings.
+const MAX_RUN_AHEAD: u32 = 3;
+
+/// How long the thumbnail of the selected save state slot is shown for.
+const SLOT_PREVIEW_DURATION: Duration = Duration::from_secs(2);
 
 pub struct GUI {
     canvas: Canvas<Window>,
     screen_texture: Texture,
+    slot_preview_texture: Texture,
     state: GUIState,
     fc: Option<Box<FC>>,
+    audio: Option<AudioOutput>,
+    rewind: RewindBuffer,
+    /// The state saved before running ahead, restored afterwards (kept around to reuse its allocation.)
+    run_ahead_state: Vec<u8>,
+    netplay: Option<Netplay>,
 }
 
 struct GUIState {
     continue_running: bool,
     emulator_paused: bool,
     emulator_60fps: bool,
-    emulator_fps_changed: bool,
     fast_forward: bool,
     frame_advancing: bool,
+    rewinding: bool,
+    /// Number of frames to run ahead (0 to disable run-ahead.)
+    run_ahead: u32,
+    audio_sync: bool,
+    region_override: Option<Region>,
     holding_ctrl_key: bool,
+    holding_shift_key: bool,
     curr_rom_path: PathBuf,
     curr_joypad_is_joy2: bool,
     joypad1: StandardControllerState,
     joypad2: StandardControllerState,
+    state_slot: usize,
+    /// Show the thumbnail of the selected slot until this time.
+    slot_preview_until: Option<Instant>,
     // TODO: actual debugging in the GUI
     // debugging_view: bool,
     // TODO? actually show errors in the UI?
@@ -66,29 +98,53 @@ impl GUI {
         // The rust sdl3 crate doesn't seem to expose SDL_SCALEMODE_PIXELART, so this is the current best.
         screen_texture.set_scale_mode(sdl3::render::ScaleMode::Nearest);
 
+        let mut slot_preview_texture = texture_cretor
+            .create_texture_streaming(PixelFormat::RGB24, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT)
+            .unwrap();
+        slot_preview_texture.set_scale_mode(sdl3::render::ScaleMode::Nearest);
+
         let state = GUIState {
             continue_running: true,
             emulator_paused: false,
             emulator_60fps: false,
-            emulator_fps_changed: false,
             fast_forward: false,
             frame_advancing: false,
+            rewinding: false,
+            run_ahead: 0,
+            audio_sync: true,
+            region_override: None,
             holding_ctrl_key: false,
+            holding_shift_key: false,
             curr_rom_path: PathBuf::new(),
             curr_joypad_is_joy2: false,
             joypad1: StandardControllerState::default(),
             joypad2: StandardControllerState::default(),
+            state_slot: 1,
+            slot_preview_until: None,
             // debugging_view: false,
             // ui_show_error: false,
             // ui_last_error: None,
             // ui_show_error_timer: std::time::Instant::now(),
         };
 
+        let audio = match AudioOutput::new(&sdl_context) {
+            Ok(a) => Some(a),
+            Err(e) => {
+                warn!("Failed to open audio device, continuing without audio: {e}");
+                None
+            }
+        };
+
         GUI {
             canvas,
             screen_texture,
+            slot_preview_texture,
             state,
             fc: None,
+            audio,
+            rewind: RewindBuffer::new(DEFAULT_REWIND_LENGTH, DEFAULT_REWIND_INTERVAL),
+            run_ahead_state: Vec::new(),
+            netplay: None,
         }
     }
 
@@ -100,12 +156,16 @@ impl GUI {
         gui
     }
 
+    /// Set the number of snapshots kept for rewinding, and the number of frames between them.
+    pub fn set_rewind_config(&mut self, length: usize, interval: u32) {
+        info!("Rewind: {length} snapshots, every {interval} frames");
+        self.rewind = RewindBuffer::new(length, interval);
+    }
+
     /// Run the GUI until it is manually stopped or an error occurs.
     pub fn run(&mut self, mut event_pump: EventPump) -> Result<(), sdl3::Error> {
         info!("Starting GUI run loop");
 
-        let mut frame_duration = (1_000_000_000.0 / ppu::FRAMERATE) as u32;
-
         loop {
             // Must be the very first thing to happen in the loop
             let frame_start = std::time::Instant::now();
@@ -124,19 +184,31 @@ impl GUI {
             }
 
             self.run_frame();
+            self.update_audio();
 
             if self.state.fast_forward {
                 continue;
             }
 
-            if self.state.emulator_fps_changed {
-                frame_duration = if self.state.emulator_60fps {
-                    (1_000_000_000.0 / 60.0) as u32
-                } else {
-                    (1_000_000_000.0 / ppu::FRAMERATE) as u32
-                };
+            if self.is_audio_synced() {
+                // Audio is the master clock: wait until the queued audio has been (mostly) played
+                if let Some(audio) = &self.audio {
+                    while audio.is_full() {
+                        unsafe {
+                            SDL_DelayPrecise(500_000);
+                        }
+                    }
+                }
+                continue;
             }
 
+            let framerate = if self.state.emulator_60fps {
+                60.0
+            } else {
+                self.fc.as_ref().map_or(Region::NTSC.framerate(), |f| f.region().framerate())
+            };
+            let frame_duration = (1_000_000_000.0 / framerate) as u32;
+
             let frame_time = std::time::Duration::new(0, frame_duration);
             let delta = frame_start.elapsed();
             if let Some(time) = frame_time.checked_sub(delta) {
@@ -178,7 +250,49 @@ impl GUI {
             if !self.state.emulator_paused {
                 let start = std::time::Instant::now();
 
-                fc.run_until_render_done();
+                if self.state.rewinding {
+                    // Restore the previous snapshot, and run a frame from it to have something to show
+                    if let Some(state) = self.rewind.step_back() {
+                        if let Err(e) = fc.load_state(&state) {
+                            warn!("Failed to rewind: {e}");
+                            self.rewind.clear();
+                        }
+                        fc.set_controller_values(self.state.joypad1, self.state.joypad2);
+                    }
+                } else if self.rewind.snapshot_due() {
+                    match fc.save_state() {
+                        Ok(state) => self.rewind.push(state),
+                        Err(e) => warn!("Failed to take rewind snapshot: {e}"),
+                    }
+                }
+
+                if let Some(netplay) = &mut self.netplay {
+                    let local = if self.state.curr_joypad_is_joy2 { self.state.joypad2 } else { self.state.joypad1 };
+                    if let Err(e) = netplay.run_frame(fc, local) {
+                        warn!("Netplay: connection lost, continuing offline: {e}");
+                        self.netplay = None;
+                    }
+                } else {
+                    fc.run_until_render_done();
+                }
+
+                // Show the frame from `run_ahead` frames in the future, then go back to the actual state. The audio
+                // is still from the actual state, as it can't be taken back once it's played.
+                if self.state.run_ahead > 0 && !self.state.rewinding {
+                    match fc.save_state_into(&mut self.run_ahead_state) {
+                        Ok(()) => {
+                            for _ in 0..self.state.run_ahead {
+                                fc.run_frame_ahead();
+                            }
+                            if let Err(e) = fc.restore_state(&self.run_ahead_state) {
+                                // Should never happen, as the state was just saved
+                                warn!("Failed to restore state after running ahead, disabling run-ahead: {e}");
+                                self.state.run_ahead = 0;
+                            }
+                        }
+                        Err(e) => warn!("Failed to save state for run-ahead: {e}"),
+                    }
+                }
 
                 let end = start.elapsed();
                 debug!("Time: {:.2?}", end);
@@ -197,9 +311,56 @@ impl GUI {
         let rect = FRect::new(0.0, 0.0, window_w as f32, window_h as f32);
 
         self.canvas.copy(&self.screen_texture, None, Some(rect)).unwrap();
+
+        if let Some(until) = self.state.slot_preview_until {
+            if Instant::now() < until {
+                // Top right corner, at a quarter of the window size
+                let (w, h) = (window_w as f32 / 4.0, window_h as f32 / 4.0);
+                let preview_rect = FRect::new(window_w as f32 - w - 8.0, 8.0, w, h);
+                self.canvas.copy(&self.slot_preview_texture, None, Some(preview_rect)).unwrap();
+            } else {
+                self.state.slot_preview_until = None;
+            }
+        }
+
         self.canvas.present();
     }
 
+    /// Send the audio generated by the last frame to the audio device.
+    fn update_audio(&mut self) {
+        let audio_synced = self.is_audio_synced();
+        let (Some(fc), Some(audio)) = (&mut self.fc, &mut self.audio) else {
+            return;
+        };
+
+        let samples = fc.take_audio_samples();
+
+        // Muted while fast-forwarding, rewinding or paused; clearing the queue makes sure nothing stale is played
+        // afterwards.
+        if self.state.fast_forward || self.state.rewinding || self.state.emulator_paused {
+            audio.clear();
+            return;
+        }
+
+        if audio_synced {
+            fc.set_audio_sample_rate(audio.sample_rate());
+        } else {
+            // Video is the master clock, so the audio has to be stretched to fit
+            fc.set_audio_sample_rate(audio.adjusted_sample_rate());
+        }
+
+        audio.queue(&samples);
+    }
+
+    /// Whether the emulator should be paced by the audio device, rather than by sleeping.
+    fn is_audio_synced(&self) -> bool {
+        self.state.audio_sync
+            && self.audio.is_some()
+            && self.fc.is_some()
+            && !self.state.emulator_paused
+            && !self.state.emulator_60fps
+    }
+
     /// Handle the given [Event]. Returns `true` if the event was [Event::Quit].
     #[rustfmt::skip]
     fn handle_event(&mut self, event: Event) {
@@ -207,6 +368,8 @@ impl GUI {
             Event::Quit { .. } => self.state.continue_running = false,
             Event::KeyDown { keycode: Some(Keycode::LCtrl), .. } => self.state.holding_ctrl_key = true,
             Event::KeyUp   { keycode: Some(Keycode::LCtrl), .. } => self.state.holding_ctrl_key = false,
+            Event::KeyDown { keycode: Some(Keycode::LShift), .. } => self.state.holding_shift_key = true,
+            Event::KeyUp   { keycode: Some(Keycode::LShift), .. } => self.state.holding_shift_key = false,
             // Joypad
             Event::KeyDown { keycode: Some(Keycode::C), .. } => self.state.curr_joypad_is_joy2 = !self.state.curr_joypad_is_joy2,
             // (press)
@@ -227,29 +390,49 @@ impl GUI {
             Event::KeyUp   { keycode: Some(Keycode::Down),      .. } => self.curr_controller().down   = false,
             Event::KeyUp   { keycode: Some(Keycode::Left),      .. } => self.curr_controller().left   = false,
             Event::KeyUp   { keycode: Some(Keycode::Right),     .. } => self.curr_controller().right  = false,
-            // Scale
-            Event::KeyDown { keycode: Some(Keycode::_1), .. } => if self.state.holding_ctrl_key { self.set_scale(1); },
-            Event::KeyDown { keycode: Some(Keycode::_2), .. } => if self.state.holding_ctrl_key { self.set_scale(2); },
-            Event::KeyDown { keycode: Some(Keycode::_3), .. } => if self.state.holding_ctrl_key { self.set_scale(3); },
-            Event::KeyDown { keycode: Some(Keycode::_4), .. } => if self.state.holding_ctrl_key { self.set_scale(4); },
-            Event::KeyDown { keycode: Some(Keycode::_5), .. } => if self.state.holding_ctrl_key { self.set_scale(5); },
+            // Scale (with ctrl) / channel mute & solo (without/with shift)
+            Event::KeyDown { keycode: Some(Keycode::_1), .. } => self.number_key_pressed(1),
+            Event::KeyDown { keycode: Some(Keycode::_2), .. } => self.number_key_pressed(2),
+            Event::KeyDown { keycode: Some(Keycode::_3), .. } => self.number_key_pressed(3),
+            Event::KeyDown { keycode: Some(Keycode::_4), .. } => self.number_key_pressed(4),
+            Event::KeyDown { keycode: Some(Keycode::_5), .. } => self.number_key_pressed(5),
+            Event::KeyDown { keycode: Some(Keycode::_6), .. } => self.number_key_pressed(6),
+            Event::KeyDown { keycode: Some(Keycode::_0), .. } => self.number_key_pressed(0),
             // Emulator state
             Event::KeyDown { keycode: Some(Keycode::Tab), .. } => self.enable_fast_forward(),
             Event::KeyUp   { keycode: Some(Keycode::Tab), .. } => self.disable_fast_forward(),
             Event::KeyDown { keycode: Some(Keycode::Escape | Keycode::P), .. } => self.pause_emulation(),
+            Event::KeyDown { keycode: Some(Keycode::A), .. } => if self.state.holding_ctrl_key { self.toggle_audio_sync(); },
+            Event::KeyDown { keycode: Some(Keycode::W), .. } => if self.state.holding_ctrl_key { self.toggle_wav_recording(); },
+            Event::KeyDown { keycode: Some(Keycode::G), .. } => if self.state.holding_ctrl_key { self.cycle_region_override(); },
+            Event::KeyDown { keycode: Some(Keycode::E), .. } => if self.state.holding_ctrl_key { self.cycle_run_ahead(); },
+            Event::KeyDown { keycode: Some(Keycode::M), .. } => if self.state.holding_ctrl_key { self.toggle_movie_recording(); },
+            Event::KeyDown { keycode: Some(Keycode::L), .. } => if self.state.holding_ctrl_key { self.toggle_movie_playback(); },
+            // Save states
+            Event::KeyDown { keycode: Some(Keycode::F5), .. } => self.save_state_slot(),
+            Event::KeyDown { keycode: Some(Keycode::F6), .. } => self.cycle_state_slot(),
+            Event::KeyDown { keycode: Some(Keycode::F7), .. } => self.load_state_slot(),
+            Event::KeyDown { keycode: Some(Keycode::LeftBracket), repeat: false, .. } => self.start_rewinding(),
+            Event::KeyUp   { keycode: Some(Keycode::LeftBracket), .. } => self.state.rewinding = false,
             Event::KeyDown { keycode: Some(Keycode::RightBracket), .. } => {
+                if self.netplay_blocks("advance frames") {
+                    return;
+                }
                 self.state.emulator_paused = false;
                 self.state.frame_advancing = true;
             }
             // Resets
             Event::KeyDown { keycode: Some(Keycode::R), .. } => {
+                if self.state.holding_ctrl_key && self.netplay_blocks("reset") {
+                    return;
+                }
                 if let Some(f) = &mut self.fc && self.state.holding_ctrl_key {
                     info!("Soft reset");
                     f.reset();
                 }
             }
             Event::KeyDown { keycode: Some(Keycode::H), .. } => {
-                if !self.state.holding_ctrl_key {
+                if !self.state.holding_ctrl_key || self.netplay_blocks("reset") {
                     return;
                 }
 
@@ -270,6 +453,9 @@ impl GUI {
             }
             // Load ROM
             Event::KeyDown { keycode: Some(Keycode::O), .. } => {
+                if self.netplay_blocks("load a ROM") {
+                    return;
+                }
                 if let Some(path) = rfd::FileDialog::new()
                     .add_filter(".NES ROM file", &["nes"])
                     .pick_file()
@@ -317,6 +503,9 @@ impl GUI {
 
     /// Pause emulator
     fn pause_emulation(&mut self) {
+        if self.netplay_blocks("pause") {
+            return;
+        }
         self.state.emulator_paused = !self.state.emulator_paused
     }
 
@@ -328,8 +517,18 @@ impl GUI {
                 warn!("Failed to load ROM: {e}");
                 None
             }
-            Ok(f) => {
+            Ok(mut f) => {
                 self.state.curr_rom_path = filename.to_path_buf();
+                self.rewind.clear();
+                if let Err(e) = f.set_region_override(self.state.region_override) {
+                    warn!("Failed to set region: {e}");
+                }
+                if let Some(old) = &self.fc {
+                    f.apu_mut().copy_output_settings(old.apu());
+                }
+                if let Some(audio) = &self.audio {
+                    f.set_audio_sample_rate(audio.sample_rate());
+                }
                 Some(f)
             }
         }
@@ -343,6 +542,300 @@ impl GUI {
         self.state.fast_forward = false;
     }
 
+    fn start_rewinding(&mut self) {
+        if self.netplay_blocks("rewind") {
+            return;
+        }
+        if self.fc.as_ref().is_some_and(|fc| fc.movie_status().is_some()) {
+            warn!("Can't rewind while a movie is being recorded or played back");
+            return;
+        }
+        debug!("Rewinding ({} snapshots, {} KiB)", self.rewind.len(), self.rewind.size() / 1024);
+        self.state.rewinding = true;
+    }
+
+    /// Handle the number keys: ctrl+1-5 sets the window scale, 1-6 toggles muting an audio channel, shift+1-6 solos
+    /// an audio channel, and 0 resets the channel mixing.
+    fn number_key_pressed(&mut self, num: usize) {
+        if self.state.holding_ctrl_key {
+            if (1..=5).contains(&num) {
+                self.set_scale(num as u32);
+            }
+            return;
+        }
+
+        let Some(fc) = &mut self.fc else {
+            return;
+        };
+        let apu = fc.apu_mut();
+
+        if num == 0 {
+            info!("Reset audio channel mixing");
+            apu.reset_channel_mixing();
+            return;
+        }
+
+        let channel = Channel::ALL[num - 1];
+        if self.state.holding_shift_key {
+            let solo = if apu.solo_channel() == Some(channel) { None } else { Some(channel) };
+            info!("Solo audio channel: {}", solo.map_or("off".to_owned(), |ch| ch.to_string()));
+            apu.set_solo_channel(solo);
+        } else {
+            apu.toggle_channel_muted(channel);
+            info!("Audio channel {channel} muted: {}", apu.is_channel_muted(channel));
+        }
+    }
+
+    /// Start or stop recording audio to `<romfile>.wav`.
+    fn toggle_wav_recording(&mut self) {
+        let Some(fc) = &mut self.fc else {
+            warn!("Failed to start audio recording: no rom loaded");
+            return;
+        };
+
+        if fc.wav_recording_path().is_some() {
+            if let Err(e) = fc.stop_wav_recording() {
+                warn!("Failed to finish audio recording: {e}");
+            }
+        } else {
+            // TODO: same problem as screenshots, this will overwrite any existing file...
+            let wav_path = self.state.curr_rom_path.with_extension("wav");
+            if let Err(e) = fc.start_wav_recording(&wav_path) {
+                warn!("Failed to start audio recording: {e}");
+            }
+        }
+    }
+
+    /// Start recording a movie (from power-on, or from the current state with shift held), or stop the current one.
+    ///
+    /// When stopping a recording, the movie is saved to a file picked by the user.
+    fn toggle_movie_recording(&mut self) {
+        let from_power_on = !self.state.holding_shift_key;
+        let Some(fc) = &mut self.fc else {
+            warn!("Failed to start recording movie: no rom loaded");
+            return;
+        };
+
+        let Some(status) = fc.movie_status() else {
+            if let Err(e) = fc.start_movie_recording(from_power_on) {
+                warn!("Failed to start recording movie: {e}");
+            }
+            return;
+        };
+
+        let Some(movie) = fc.stop_movie() else {
+            return;
+        };
+        if status.mode != MovieMode::Recording {
+            return;
+        }
+
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("rfce movie", &["rfm"])
+            .add_filter("FCEUX movie", &["fm2"])
+            .set_file_name(get_movie_path(&self.state.curr_rom_path).file_name().unwrap_or_default().to_string_lossy())
+            .save_file()
+            && let Err(e) = fc.save_movie(&movie, &path)
+        {
+            warn!("Failed to save movie: {e}");
+        }
+    }
+
+    /// Play back a movie picked by the user, or stop the movie that is currently playing.
+    fn toggle_movie_playback(&mut self) {
+        if self.netplay_blocks("play a movie") {
+            return;
+        }
+        let Some(fc) = &mut self.fc else {
+            warn!("Failed to play movie: no rom loaded");
+            return;
+        };
+
+        match fc.movie_status().map(|s| s.mode) {
+            Some(MovieMode::Playing) => {
+                fc.stop_movie();
+                return;
+            }
+            Some(MovieMode::Recording) => {
+                warn!("Stop recording the current movie first");
+                return;
+            }
+            None => {}
+        }
+
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("Movie", &["rfm", "fm2"])
+            .pick_file()
+        else {
+            return;
+        };
+
+        if let Err(e) = fc.load_movie(&path).and_then(|movie| fc.start_movie_playback(movie)) {
+            warn!("Failed to play movie: {e}");
+        }
+    }
+
+    /// Manually set the region of the emulator (or use the one from the ROM header if `None`.)
+    ///
+    /// Changing the region hard resets the emulator.
+    pub fn set_region_override(&mut self, region: Option<Region>) {
+        self.state.region_override = region;
+        info!("Region: {}", region.map_or("auto".to_owned(), |r| r.to_string()));
+
+        if let Some(fc) = &self.fc
+            && fc.region_override() != region
+        {
+            self.save_savefile();
+
+            if let Some(fc) = &mut self.fc
+                && let Err(e) = fc.set_region_override(region)
+            {
+                warn!("Failed to change region: {e}");
+            }
+
+            self.load_savefile();
+        }
+    }
+
+    /// Cycle the region override: auto -> NTSC -> PAL -> Dendy -> auto.
+    fn cycle_region_override(&mut self) {
+        if self.netplay_blocks("change the region") {
+            return;
+        }
+        let next = match self.state.region_override {
+            None => Some(Region::NTSC),
+            Some(Region::NTSC) => Some(Region::PAL),
+            Some(Region::PAL) => Some(Region::Dendy),
+            Some(Region::Dendy) => None,
+        };
+        self.set_region_override(next);
+    }
+
+    fn state_slots(&self) -> StateSlots {
+        StateSlots::new(get_state_dir(&self.state.curr_rom_path))
+    }
+
+    /// Save the emulator state to the selected slot.
+    fn save_state_slot(&mut self) {
+        let Some(fc) = &self.fc else {
+            warn!("Failed to save state: no rom loaded");
+            return;
+        };
+
+        let slot = self.state.state_slot;
+        match self.state_slots().save(slot, fc) {
+            Ok(()) => info!("Saved state to slot {slot}"),
+            Err(e) => warn!("Failed to save state to slot {slot}: {e}"),
+        }
+    }
+
+    /// Load the emulator state from the selected slot.
+    fn load_state_slot(&mut self) {
+        if self.netplay_blocks("load a state") {
+            return;
+        }
+        let slots = self.state_slots();
+        let slot = self.state.state_slot;
+        let Some(fc) = &mut self.fc else {
+            warn!("Failed to load state: no rom loaded");
+            return;
+        };
+        if fc.movie_status().is_some() {
+            warn!("Can't load a state while a movie is being recorded or played back");
+            return;
+        }
+
+        if let Err(e) = slots.load(slot, fc) {
+            warn!("Failed to load state from slot {slot}: {e}");
+            return;
+        }
+        info!("Loaded state from slot {slot}");
+
+        // The state contains the buttons that were held when it was saved. Replace them with the ones held right now,
+        // otherwise they would stay pressed until the game reads the controllers again.
+        fc.set_controller_values(self.state.joypad1, self.state.joypad2);
+    }
+
+    /// Select the next (or previous, with shift) save state slot, and show its thumbnail.
+    fn cycle_state_slot(&mut self) {
+        let slot = if self.state.holding_shift_key {
+            (self.state.state_slot + SLOT_COUNT - 1) % SLOT_COUNT
+        } else {
+            (self.state.state_slot + 1) % SLOT_COUNT
+        };
+        self.state.state_slot = slot;
+
+        match self.state_slots().info(slot) {
+            Some(slot_info) => {
+                info!("State slot {slot} (saved {})", slots::format_age(slot_info.timestamp));
+                if let Some(thumbnail) = slot_info.thumbnail {
+                    update_texture(&mut self.slot_preview_texture, thumbnail.as_raw());
+                    self.state.slot_preview_until = Some(Instant::now() + SLOT_PREVIEW_DURATION);
+                } else {
+                    self.state.slot_preview_until = None;
+                }
+            }
+            None => {
+                info!("State slot {slot} (empty)");
+                self.state.slot_preview_until = None;
+            }
+        }
+    }
+
+    /// Set the number of frames to run ahead, to hide the input latency of games (0 disables run-ahead.)
+    ///
+    /// Every frame run ahead is an extra frame emulated per displayed frame, so this should be kept as low as possible
+    /// (usually, the number of frames a game takes to react to input.)
+    pub fn set_run_ahead(&mut self, frames: u32) {
+        self.state.run_ahead = frames;
+        info!("Run-ahead: {}", if frames == 0 { "off".to_owned() } else { format!("{frames} frames") });
+    }
+
+    /// Cycle the number of frames to run ahead: off -> 1 -> 2 -> 3 -> off.
+    fn cycle_run_ahead(&mut self) {
+        self.set_run_ahead((self.state.run_ahead + 1) % (MAX_RUN_AHEAD + 1));
+    }
+
+    /// Host a netplay session as player 1, waiting for another emulator to connect to `addr`.
+    ///
+    /// The other emulator starts from the current state, and has to have the same ROM loaded.
+    pub fn host_netplay(&mut self, addr: &str, input_delay: u32) -> Result<(), Error> {
+        let Some(fc) = &self.fc else {
+            return Err(Error::other("no rom loaded"));
+        };
+
+        let listener = TcpListener::bind(addr)?;
+        info!("Netplay: waiting for a player to connect on {}", listener.local_addr()?);
+        self.netplay = Some(Netplay::host(&listener, fc, input_delay)?);
+        Ok(())
+    }
+
+    /// Join the netplay session hosted at `addr` as player 2, replacing the current state with the one of the host.
+    pub fn join_netplay(&mut self, addr: &str) -> Result<(), Error> {
+        let Some(fc) = &mut self.fc else {
+            return Err(Error::other("no rom loaded"));
+        };
+
+        info!("Netplay: connecting to {addr}");
+        self.netplay = Some(Netplay::join(addr, fc)?);
+        Ok(())
+    }
+
+    /// Whether a netplay session is active, warning that `action` can't be done during one if so.
+    ///
+    /// Anything that changes the state of only one of the emulators would make them desync.
+    fn netplay_blocks(&self, action: &str) -> bool {
+        if self.netplay.is_some() {
+            warn!("Can't {action} during netplay");
+        }
+        self.netplay.is_some()
+    }
+
+    fn toggle_audio_sync(&mut self) {
+        self.state.audio_sync = !self.state.audio_sync;
+        info!("Audio sync: {}", if self.state.audio_sync { "on" } else { "off" });
+    }
+
     fn set_scale(&mut self, scale: u32) {
         let window = self.canvas.window_mut();
         window
@@ -370,3 +863,12 @@ fn get_save_path(rom_path: &Path) -> PathBuf {
     save_path.set_extension("sav");
     save_path
 }
+
+fn get_movie_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("rfm")
+}
+
+/// The directory containing the save state slots of a ROM (`<romfile>.states`.)
+fn get_state_dir(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("states")
+}
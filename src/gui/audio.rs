@@ -0,0 +1,87 @@
+use log::{info, warn};
+use sdl3::{
+    Sdl,
+    audio::{AudioFormat, AudioSpec, AudioStreamOwner},
+};
+
+use crate::fc::apu::DEFAULT_SAMPLE_RATE;
+
+/// Amount of audio to keep queued, in seconds.
+const TARGET_LATENCY: f64 = 0.05;
+
+/// Maximum amount the sample rate may be adjusted by dynamic rate control (0.5%).
+///
+/// See: https://docs.libretro.com/development/cores/dynamic-rate-control/
+const MAX_RATE_DELTA: f64 = 0.005;
+
+/// Audio output, using an SDL audio stream.
+pub struct AudioOutput {
+    stream: AudioStreamOwner,
+    sample_rate: u32,
+}
+
+impl AudioOutput {
+    pub fn new(sdl_context: &Sdl) -> Result<AudioOutput, sdl3::Error> {
+        let audio_subsystem = sdl_context.audio()?;
+
+        let spec = AudioSpec::new(Some(DEFAULT_SAMPLE_RATE as i32), Some(1), Some(AudioFormat::f32_sys()));
+        let stream = audio_subsystem.default_playback_device().open_device_stream(Some(&spec))?;
+        stream.resume()?;
+
+        info!("Opened audio device: {}", stream.device_name().unwrap_or_default());
+
+        Ok(AudioOutput {
+            stream,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of samples that are queued, but not yet played.
+    pub fn queued_samples(&self) -> usize {
+        self.stream.queued_bytes().unwrap_or(0) as usize / size_of::<f32>()
+    }
+
+    fn target_samples(&self) -> usize {
+        (self.sample_rate as f64 * TARGET_LATENCY) as usize
+    }
+
+    /// Whether enough audio is queued that the emulator should wait before running the next frame.
+    pub fn is_full(&self) -> bool {
+        self.queued_samples() > self.target_samples()
+    }
+
+    /// The sample rate the emulator should generate samples at, to keep the queue from under- or over-running.
+    pub fn adjusted_sample_rate(&self) -> u32 {
+        let fill = self.queued_samples() as f64 / (self.target_samples() * 2) as f64;
+        let ratio = 1.0 + MAX_RATE_DELTA * (1.0 - 2.0 * fill.min(1.0));
+
+        (self.sample_rate as f64 * ratio).round() as u32
+    }
+
+    /// Queue the given samples for playback.
+    pub fn queue(&mut self, samples: &[f32]) {
+        if self.queued_samples() == 0 && !samples.is_empty() {
+            // Starting up (or recovering from an underrun): pad so the queue doesn't immediately run dry again.
+            // Repeating the first sample (instead of silence) avoids a pop.
+            let padding = vec![samples[0]; self.target_samples() / 2];
+            if let Err(e) = self.stream.put_data_f32(&padding) {
+                warn!("Failed to queue audio: {e}");
+            }
+        }
+
+        if let Err(e) = self.stream.put_data_f32(samples) {
+            warn!("Failed to queue audio: {e}");
+        }
+    }
+
+    /// Drop all queued audio.
+    pub fn clear(&mut self) {
+        if let Err(e) = self.stream.clear() {
+            warn!("Failed to clear audio: {e}");
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::VecDeque;
+
+/// Default number of snapshots kept for rewinding.
+pub const DEFAULT_REWIND_LENGTH: usize = 600;
+
+/// Default number of frames between snapshots (with the default length, ~20 seconds of rewind on NTSC.)
+pub const DEFAULT_REWIND_INTERVAL: u32 = 2;
+
+/// Equal runs shorter than this are stored as part of the surrounding literal, as they would take up more space as a
+/// separate run.
+const MIN_SKIP_RUN: usize = 4;
+
+/// A ring buffer of save states, taken every `interval` frames.
+///
+/// Only the newest snapshot is stored in full. Every older snapshot is stored as a delta against the snapshot after
+/// it, which is very small since most of the machine state doesn't change between frames. Stepping back reconstructs
+/// the previous snapshot from the newest one, so each snapshot is only decoded once.
+pub struct RewindBuffer {
+    /// Maximum number of snapshots (including the newest one.)
+    capacity: usize,
+    interval: u32,
+    frames_until_snapshot: u32,
+    newest: Option<Vec<u8>>,
+    /// Deltas reconstructing each snapshot from the one after it, the last one being the delta from `newest`.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize, interval: u32) -> RewindBuffer {
+        RewindBuffer {
+            capacity: capacity.max(1),
+            interval: interval.max(1),
+            frames_until_snapshot: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    /// Remove all snapshots (e.g. when a different ROM is loaded.)
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+        self.frames_until_snapshot = 0;
+    }
+
+    /// Number of snapshots in the buffer.
+    pub fn len(&self) -> usize {
+        self.newest.as_ref().map_or(0, |_| self.deltas.len() + 1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Size of the buffer in bytes.
+    pub fn size(&self) -> usize {
+        self.newest.as_ref().map_or(0, |n| n.len()) + self.deltas.iter().map(|d| d.len()).sum::<usize>()
+    }
+
+    /// Called once per emulated frame, returns whether a snapshot should be taken this frame.
+    pub fn snapshot_due(&mut self) -> bool {
+        if self.frames_until_snapshot == 0 {
+            self.frames_until_snapshot = self.interval - 1;
+            true
+        } else {
+            self.frames_until_snapshot -= 1;
+            false
+        }
+    }
+
+    /// Add a snapshot, dropping the oldest one if the buffer is full.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(newest) = self.newest.take() {
+            self.deltas.push_back(encode_delta(&state, &newest));
+        }
+        self.newest = Some(state);
+
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// Step back to the previous snapshot, returning the newest one.
+    ///
+    /// The oldest snapshot is never removed, so rewinding past the start of the buffer keeps returning it.
+    pub fn step_back(&mut self) -> Option<Vec<u8>> {
+        let newest = self.newest.take()?;
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                let mut prev = newest.clone();
+                apply_delta(&mut prev, &delta);
+                self.newest = Some(prev);
+            }
+            None => self.newest = Some(newest.clone()),
+        }
+        // Wait a full interval before the next snapshot, so it isn't a duplicate of the state that was just restored
+        self.frames_until_snapshot = self.interval - 1;
+        Some(newest)
+    }
+}
+
+/// Encode the changes needed to turn `from` into `to`.
+///
+/// The delta starts with the length of `to`, as the length of a save state can change (e.g. with optional values.) It
+/// is followed by a list of runs, each consisting of the number of equal bytes to skip, the number of changed bytes,
+/// and the changed bytes themselves (with all counts stored as LEB128.)
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let equal = |i: usize| from.get(i) == Some(&to[i]);
+
+    let mut delta = Vec::new();
+    write_leb128(&mut delta, to.len());
+    let mut i = 0;
+    while i < to.len() {
+        let skip_start = i;
+        while i < to.len() && equal(i) {
+            i += 1;
+        }
+        if i == to.len() {
+            break;
+        }
+
+        let literal_start = i;
+        let mut equal_run = 0;
+        while i < to.len() && equal_run < MIN_SKIP_RUN {
+            equal_run = if equal(i) { equal_run + 1 } else { 0 };
+            i += 1;
+        }
+        // Don't include the equal bytes that ended the literal
+        let literal_end = i - equal_run;
+        i = literal_end;
+
+        write_leb128(&mut delta, literal_start - skip_start);
+        write_leb128(&mut delta, literal_end - literal_start);
+        delta.extend_from_slice(&to[literal_start..literal_end]);
+    }
+    delta
+}
+
+/// Apply a delta created by [encode_delta] to `data`.
+fn apply_delta(data: &mut Vec<u8>, delta: &[u8]) {
+    let mut i = 0;
+    data.resize(read_leb128(delta, &mut i), 0);
+
+    let mut pos = 0;
+    while i < delta.len() {
+        pos += read_leb128(delta, &mut i);
+        let len = read_leb128(delta, &mut i);
+        data[pos..pos + len].copy_from_slice(&delta[i..i + len]);
+        pos += len;
+        i += len;
+    }
+}
+
+fn write_leb128(buf: &mut Vec<u8>, mut val: usize) {
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val >>= 7;
+        if val == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn read_leb128(buf: &[u8], i: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[*i];
+        *i += 1;
+        val |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delta_round_trip_test() {
+        let from: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut to = from.clone();
+        to[0] = 0xff;
+        to[10] = 0xff;
+        to[12] = 0xff;
+        to[500..700].fill(0x42);
+        to[999] = 0x00;
+
+        let delta = encode_delta(&from, &to);
+        assert!(delta.len() < 250);
+        let mut decoded = from.clone();
+        apply_delta(&mut decoded, &delta);
+        assert_eq!(decoded, to);
+
+        assert_eq!(encode_delta(&from, &from).len(), 2);
+    }
+
+    #[test]
+    fn length_change_test() {
+        // e.g. the DMC sample buffer, which is an `Option` and so takes up one or two bytes
+        let short: Vec<u8> = (0..100).collect();
+        let mut long = short.clone();
+        long.insert(50, 0xff);
+
+        for (from, to) in [(&short, &long), (&long, &short)] {
+            let mut decoded = from.clone();
+            apply_delta(&mut decoded, &encode_delta(from, to));
+            assert_eq!(&decoded, to);
+        }
+
+        // Neither loses the history
+        let mut buf = RewindBuffer::new(3, 1);
+        buf.push(short.clone());
+        buf.push(long.clone());
+        buf.push(short.clone());
+        assert_eq!(buf.len(), 3);
+        assert_eq!(buf.step_back(), Some(short.clone()));
+        assert_eq!(buf.step_back(), Some(long));
+        assert_eq!(buf.step_back(), Some(short));
+    }
+
+    #[test]
+    fn rewind_buffer_test() {
+        let mut buf = RewindBuffer::new(3, 2);
+        assert!(buf.snapshot_due());
+        assert!(!buf.snapshot_due());
+        assert!(buf.snapshot_due());
+
+        let state = |i: u8| {
+            let mut s = vec![0; 64];
+            s[10] = i;
+            s
+        };
+        for i in 0..5 {
+            buf.push(state(i));
+        }
+        // Only the last 3 snapshots are kept
+        assert_eq!(buf.len(), 3);
+        assert!(buf.size() < 64 * 3);
+
+        assert_eq!(buf.step_back(), Some(state(4)));
+        assert_eq!(buf.step_back(), Some(state(3)));
+        assert_eq!(buf.step_back(), Some(state(2)));
+        assert_eq!(buf.step_back(), Some(state(2)));
+        assert_eq!(buf.len(), 1);
+
+        // Resuming continues from the restored snapshot
+        buf.push(state(7));
+        assert_eq!(buf.step_back(), Some(state(7)));
+        assert_eq!(buf.step_back(), Some(state(2)));
+    }
+}
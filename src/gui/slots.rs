@@ -0,0 +1,102 @@
+use std::{
+    fs,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use image::RgbImage;
+
+use crate::fc::{FC, ppu};
+
+/// Number of save state slots per ROM.
+pub const SLOT_COUNT: usize = 10;
+
+pub const THUMBNAIL_WIDTH: u32 = ppu::PICTURE_WIDTH as u32 / 2;
+pub const THUMBNAIL_HEIGHT: u32 = ppu::PICTURE_HEIGHT as u32 / 2;
+
+/// The save state slots of a ROM, stored in their own directory.
+///
+/// Each slot consists of three files: `slot<n>.state` (the save state), `slot<n>.png` (a thumbnail of the screen when
+/// the state was saved) and `slot<n>.time` (the time the state was saved, in seconds since the unix epoch.)
+pub struct StateSlots {
+    dir: PathBuf,
+}
+
+/// Information about a saved slot.
+pub struct SlotInfo {
+    pub timestamp: SystemTime,
+    pub thumbnail: Option<RgbImage>,
+}
+
+impl StateSlots {
+    pub fn new(dir: PathBuf) -> StateSlots {
+        StateSlots { dir }
+    }
+
+    fn slot_path(&self, slot: usize, extension: &str) -> PathBuf {
+        self.dir.join(format!("slot{slot}.{extension}"))
+    }
+
+    pub fn state_path(&self, slot: usize) -> PathBuf {
+        self.slot_path(slot, "state")
+    }
+
+    /// Save the current state of `fc` to `slot`, along with a thumbnail of the current frame.
+    pub fn save(&self, slot: usize, fc: &FC) -> Result<(), Error> {
+        fs::create_dir_all(&self.dir)?;
+        fc.save_state_to_file(&self.state_path(slot))?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        fs::write(self.slot_path(slot, "time"), timestamp.as_secs().to_string())?;
+
+        let frame = RgbImage::from_raw(ppu::PICTURE_WIDTH as u32, ppu::PICTURE_HEIGHT as u32, fc.get_frame().to_vec())
+            .ok_or_else(|| Error::other("invalid frame buffer size"))?;
+        image::imageops::thumbnail(&frame, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT)
+            .save(self.slot_path(slot, "png"))
+            .map_err(Error::other)
+    }
+
+    /// Restore the state of `fc` from `slot`.
+    pub fn load(&self, slot: usize, fc: &mut FC) -> Result<(), Error> {
+        fc.load_state_from_file(&self.state_path(slot))
+    }
+
+    /// Get the timestamp and thumbnail of `slot`, or `None` if nothing has been saved to it.
+    pub fn info(&self, slot: usize) -> Option<SlotInfo> {
+        if !self.state_path(slot).is_file() {
+            return None;
+        }
+
+        // Fall back to the modification time of the state if the timestamp is missing
+        let timestamp = read_timestamp(&self.slot_path(slot, "time"))
+            .or_else(|_| fs::metadata(self.state_path(slot))?.modified())
+            .unwrap_or(UNIX_EPOCH);
+        let thumbnail = image::open(self.slot_path(slot, "png"))
+            .ok()
+            .map(|img| img.into_rgb8())
+            .filter(|img| img.dimensions() == (THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT));
+
+        Some(SlotInfo { timestamp, thumbnail })
+    }
+}
+
+fn read_timestamp(path: &Path) -> Result<SystemTime, Error> {
+    let secs = fs::read_to_string(path)?
+        .trim()
+        .parse::<u64>()
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    Ok(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Describe how long ago `timestamp` was, e.g. "5 minutes ago".
+pub fn format_age(timestamp: SystemTime) -> String {
+    let secs = SystemTime::now().duration_since(timestamp).unwrap_or_default().as_secs();
+    let (amount, unit) = match secs {
+        0..60 => return "just now".to_owned(),
+        60..3600 => (secs / 60, "minute"),
+        3600..86400 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+    format!("{amount} {unit}{} ago", if amount == 1 { "" } else { "s" })
+}
@@ -1,7 +1,12 @@
 use std::{env, path::Path};
 
+use fc::FC;
 use fc::dbg::Debugger;
+use fc::headless;
+use fc::netplay::DEFAULT_INPUT_DELAY;
+use fc::region::Region;
 use gui::GUI;
+use gui::rewind::{DEFAULT_REWIND_INTERVAL, DEFAULT_REWIND_LENGTH};
 use log::info;
 
 pub mod bits;
@@ -15,15 +20,25 @@ fn main() -> Result<(), String> {
 
     let headless = args.contains(&"--headless".to_owned());
     let last_arg_is_nes_file = args[args.len() - 1].ends_with(".nes");
+    let region = arg_value(&args, "--region").map(|r| r.parse::<Region>()).transpose()?;
 
     if headless {
-        if last_arg_is_nes_file {
+        if last_arg_is_nes_file && let Some(movie_path) = arg_value(&args, "--movie") {
+            let filename = &args[args.len() - 1];
+            run_headless_movie(&args, Path::new(filename), Path::new(movie_path), region)
+        } else if last_arg_is_nes_file {
             info!("Creating headless debugger");
 
             let filename = &args[args.len() - 1];
             let mut debugger = Debugger::new();
             match debugger.load_file(Path::new(&filename)) {
-                Ok(_) => Ok(debugger.run()),
+                Ok(_) => {
+                    debugger.set_region_override(region)?;
+                    if let Some(wav_path) = arg_value(&args, "--record-wav") {
+                        debugger.start_wav_recording(Path::new(wav_path))?;
+                    }
+                    Ok(debugger.run())
+                }
                 Err(e) => Err(format!(
                     "{} (file: '{}') Help: Did you specify a valid .nes file?",
                     e, filename
@@ -31,18 +46,114 @@ fn main() -> Result<(), String> {
             }
         } else {
             println!("No nes file provided.\n");
-            println!("Usage: rfce --headless <file>");
+            println!("Usage: rfce --headless [--region <ntsc|pal|dendy>] [--record-wav <file.wav>] <file>");
+            println!(
+                "       rfce --headless --movie <movie.rfm|movie.fm2> [--frames <n>] \
+                 [--hash-every <n> | --hash-frames <n,n,...>] [--hash-out <file>] [--golden <file>] <file>"
+            );
             Ok(())
         }
     } else {
         info!("Creating GUI");
 
         let filename = last_arg_is_nes_file.then_some(&args[args.len() - 1]);
-        run_gui(filename)
+        let rewind_length = arg_value(&args, "--rewind-length")
+            .map(|n| n.parse::<usize>().map_err(|e| format!("Invalid rewind length: {e}")))
+            .transpose()?
+            .unwrap_or(DEFAULT_REWIND_LENGTH);
+        let rewind_interval = arg_value(&args, "--rewind-interval")
+            .map(|n| n.parse::<u32>().map_err(|e| format!("Invalid rewind interval: {e}")))
+            .transpose()?
+            .unwrap_or(DEFAULT_REWIND_INTERVAL);
+        let run_ahead = arg_value(&args, "--run-ahead")
+            .map(|n| n.parse::<u32>().map_err(|e| format!("Invalid run-ahead: {e}")))
+            .transpose()?
+            .unwrap_or(0);
+        let input_delay = arg_value(&args, "--input-delay")
+            .map(|n| n.parse::<u32>().map_err(|e| format!("Invalid input delay: {e}")))
+            .transpose()?
+            .unwrap_or(DEFAULT_INPUT_DELAY);
+        let netplay = match (arg_value(&args, "--netplay-host"), arg_value(&args, "--netplay-join")) {
+            (Some(_), Some(_)) => return Err("Can't both host and join a netplay session".to_owned()),
+            (Some(addr), None) => Some(NetplayArgs::Host(addr, input_delay)),
+            (None, Some(addr)) => Some(NetplayArgs::Join(addr)),
+            (None, None) => None,
+        };
+        run_gui(filename, region, (rewind_length, rewind_interval), run_ahead, netplay)
     }
 }
 
-fn run_gui(filename: Option<&String>) -> Result<(), String> {
+/// How to start a netplay session (see [GUI::host_netplay] and [GUI::join_netplay].)
+enum NetplayArgs<'a> {
+    /// Listen on the address, with the given input delay.
+    Host(&'a str, u32),
+    /// Connect to the address.
+    Join(&'a str),
+}
+
+/// Get the value following the argument `name` (e.g. `--arg value`)
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a String> {
+    let i = args.iter().position(|a| a == name)?;
+    args.get(i + 1)
+}
+
+/// Play back a movie without the debugger, printing the frame hashes (or comparing them to a golden file.) A movie
+/// desync is an error.
+fn run_headless_movie(args: &[String], rom_path: &Path, movie_path: &Path, region: Option<Region>) -> Result<(), String> {
+    let parse_num = |name: &str, val: &str| val.parse::<usize>().map_err(|_| format!("Invalid value for {name}: `{val}`"));
+
+    let mut fc = FC::from_file(rom_path).map_err(|e| format!("{e} (file: '{}')", rom_path.display()))?;
+    fc.init();
+    fc.set_region_override(region).map_err(|e| format!("Failed to change region: {e}"))?;
+    if let Some(wav_path) = arg_value(args, "--record-wav") {
+        fc.start_wav_recording(Path::new(wav_path)).map_err(|e| format!("Failed to start recording: {e}"))?;
+    }
+
+    let movie = fc.load_movie(movie_path).map_err(|e| format!("Failed to load movie: {e}"))?;
+    let frames = match arg_value(args, "--frames") {
+        Some(n) => parse_num("--frames", n)?,
+        None => movie.frames.len(),
+    };
+    let hash_frames: Vec<usize> = if let Some(n) = arg_value(args, "--hash-every") {
+        let interval = parse_num("--hash-every", n)?.max(1);
+        (interval..=frames).step_by(interval).collect()
+    } else if let Some(list) = arg_value(args, "--hash-frames") {
+        list.split(',').map(|n| parse_num("--hash-frames", n.trim())).collect::<Result<_, _>>()?
+    } else {
+        vec![frames]
+    };
+
+    info!("Playing back {} for {frames} frames", movie_path.display());
+    let run = headless::run_movie(&mut fc, movie, frames, &hash_frames).map_err(|e| format!("Failed to play movie: {e}"))?;
+    fc.stop_wav_recording().map_err(|e| format!("Failed to finish the recording: {e}"))?;
+
+    let output = headless::format_hashes(&run.hashes);
+    match arg_value(args, "--hash-out") {
+        Some(path) => std::fs::write(path, &output).map_err(|e| format!("Failed to write hashes: {e}"))?,
+        None => print!("{output}"),
+    }
+    if let Some(frame) = run.desync_frame {
+        return Err(format!("Movie desynced at frame {frame}"));
+    }
+
+    if let Some(golden_path) = arg_value(args, "--golden") {
+        let golden = std::fs::read_to_string(golden_path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| headless::parse_hashes(&text).map_err(|e| e.to_string()))
+            .map_err(|e| format!("Failed to read golden file: {e}"))?;
+        headless::compare_hashes(&golden, &run.hashes).map_err(|e| format!("Output differs from {golden_path}:\n{e}"))?;
+        println!("Output matches {golden_path}");
+    }
+    Ok(())
+}
+
+fn run_gui(
+    filename: Option<&String>,
+    region: Option<Region>,
+    rewind: (usize, u32),
+    run_ahead: u32,
+    netplay: Option<NetplayArgs>,
+) -> Result<(), String> {
     let sdl_context = sdl3::init().map_err(|e| e.to_string())?;
     let event_pump = sdl_context.event_pump().map_err(|e| e.to_string())?;
 
@@ -54,6 +165,20 @@ fn run_gui(filename: Option<&String>) -> Result<(), String> {
         info!("Starting GUI without ROM");
         GUI::new(sdl_context)
     };
+    if region.is_some() {
+        gui.set_region_override(region);
+    }
+    gui.set_rewind_config(rewind.0, rewind.1);
+    if run_ahead > 0 {
+        gui.set_run_ahead(run_ahead);
+    }
+    match netplay {
+        Some(NetplayArgs::Host(addr, input_delay)) => {
+            gui.host_netplay(addr, input_delay).map_err(|e| format!("Failed to host netplay: {e}"))?
+        }
+        Some(NetplayArgs::Join(addr)) => gui.join_netplay(addr).map_err(|e| format!("Failed to join netplay: {e}"))?,
+        None => {}
+    }
 
     gui.run(event_pump).map_err(|e| e.to_string())
 }